@@ -0,0 +1,132 @@
+//! Provides calendar schedules for Allowances that repeat on calendar
+//! dates rather than after a fixed delay.
+//!
+//! All dates are in UTC, and we use the proleptic Gregorian calendar
+//! for them. The conversions between days and dates are the well
+//! known ones by Howard Hinnant, which work for any date we are ever
+//! likely to see.
+
+use scrypto::prelude::*;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Describes when a scheduled Allowance can be used. It can be used
+/// once in each period of the schedule, and each period starts at
+/// midnight UTC on the day given here.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CalendarSchedule {
+    /// A new period starts every day.
+    Daily,
+
+    /// A new period starts every week on `weekday`, where 0 is Monday
+    /// and 6 is Sunday.
+    Weekly{weekday: u8},
+
+    /// A new period starts every month on day `day` of the month. In
+    /// months that are too short for this day the period starts on
+    /// the last day of the month instead, so `Monthly{day: 31}` is
+    /// always the last day of the month.
+    Monthly{day: u8},
+
+    /// A new period starts every quarter, that is on the 1st of
+    /// January, April, July and October.
+    Quarterly,
+}
+
+impl CalendarSchedule {
+    /// Returns true if the schedule's day is one that exists.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            CalendarSchedule::Daily | CalendarSchedule::Quarterly => true,
+            CalendarSchedule::Weekly{weekday} => *weekday <= 6,
+            CalendarSchedule::Monthly{day} => 1 <= *day && *day <= 31,
+        }
+    }
+
+    /// Returns the Unix time at which the first period after the one
+    /// containing `now` starts.
+    pub fn next_period_start(&self, now: i64) -> i64 {
+        let today = now.div_euclid(SECONDS_PER_DAY);
+        let day = match self {
+            CalendarSchedule::Daily => today + 1,
+            CalendarSchedule::Weekly{weekday} => {
+                let days_ahead = (*weekday as i64 - weekday_of(today)).rem_euclid(7);
+                today + if days_ahead == 0 { 7 } else { days_ahead }
+            },
+            CalendarSchedule::Monthly{day} => {
+                let (year, month, _) = civil_from_days(today);
+                let this_month = days_from_civil(year, month,
+                                                 std::cmp::min(*day as u32,
+                                                               days_in_month(year, month)));
+                if this_month > today {
+                    this_month
+                } else {
+                    let (year, month) = add_months(year, month, 1);
+                    days_from_civil(year, month,
+                                    std::cmp::min(*day as u32, days_in_month(year, month)))
+                }
+            },
+            CalendarSchedule::Quarterly => {
+                let (year, month, _) = civil_from_days(today);
+                let (year, month) = add_months(year, month - (month - 1) % 3, 3);
+                days_from_civil(year, month, 1)
+            },
+        };
+        day * SECONDS_PER_DAY
+    }
+}
+
+/// Converts a number of days since 1 January 1970 into a `(year,
+/// month, day)` date. Months and days count from 1.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a `(year, month, day)` date into the number of days
+/// since 1 January 1970. Months and days count from 1.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The number of days in `month` of `year`.
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns true if `year` has a 29th of February.
+pub fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// The day of the week of a number of days since 1 January 1970,
+/// where 0 is Monday and 6 is Sunday.
+pub fn weekday_of(days: i64) -> i64 {
+    // 1 January 1970 was a Thursday
+    (days + 3).rem_euclid(7)
+}
+
+/// Moves `months` months on from `month` of `year`.
+fn add_months(year: i64, month: u32, months: u32) -> (i64, u32) {
+    let index = month - 1 + months;
+    (year + (index / 12) as i64, index % 12 + 1)
+}
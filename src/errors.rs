@@ -0,0 +1,249 @@
+//! Provides the error codes used in the panic messages of this
+//! package.
+//!
+//! Every deliberate panic in this package has a message that starts
+//! with a 2000-series error code followed by a short description,
+//! e.g. `"2013 only trusted can request allowance"`. The codes are
+//! stable, so if you have the panic message out of a failed
+//! transaction receipt you can use [EscrowError::from_message] to find
+//! out what went wrong.
+
+use std::fmt;
+
+/// Declares the `EscrowError` enum along with the code and message
+/// of each variant, so that these are all listed in one place.
+macro_rules! escrow_errors {
+    ($($(#[$doc:meta])* $name:ident = $code:literal $message:literal,)*) => {
+        /// All the reasons this package will deliberately panic for.
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub enum EscrowError {
+            $($(#[$doc])* $name,)*
+        }
+
+        impl EscrowError {
+            /// Every error there is, in order of their codes.
+            pub const ALL: &'static [EscrowError] = &[$(EscrowError::$name,)*];
+
+            /// The numeric code of this error.
+            pub const fn code(self) -> u16 {
+                match self {
+                    $(EscrowError::$name => $code,)*
+                }
+            }
+
+            /// The full panic message for this error, that is its
+            /// code followed by its description.
+            pub const fn as_str(self) -> &'static str {
+                match self {
+                    $(EscrowError::$name => concat!($code, " ", $message),)*
+                }
+            }
+        }
+    };
+}
+
+escrow_errors! {
+    /// Tried to increase a Fungible allowance.
+    AllowanceIncreaseFungible = 2000 "allowance increase not allowed",
+    /// Tried to increase the amount of a NonFungible allowance.
+    AllowanceIncreaseNonFungible = 2001 "allowance increase not allowed",
+    /// Tried to set an amount on a NonFungible allowance that only
+    /// has an nflids set.
+    AllowanceIncreaseNoAmount = 2002 "allowance increase not allowed",
+    NegativeAllowance = 2003 "allowance can't be negative",
+    NewMaxNotWhole = 2004 "new_max must be a whole number",
+    ReduceFungibleByNflids = 2005 "use reduce_allowance_to_amount for Fungible allowance",
+    /// The NonFungible allowance has no nflids set.
+    NoNflidsInAllowance = 2006 "no nflids to remove from",
+    /// The allowance has no limit to reduce nflids from.
+    NoNflidsInUnlimited = 2007 "no nflids to remove from",
+    AllowanceNotYetValid = 2009 "allowance not yet valid",
+    InsufficientFungibleAllowance = 2010 "insufficient allowance",
+    AllowanceExpired = 2011 "allowance no longer valid",
+    InsufficientNonFungibleAllowance = 2012 "insufficient allowance",
+    NotTrusted = 2013 "only trusted can request allowance",
+    ZeroDeal = 2014 "deal cannot be for zero tokens",
+    NotDealPayer = 2015 "only the payer can approve the deal",
+    NoDealArbiter = 2016 "deal has no arbiter",
+    NotDealArbiter = 2017 "only the arbiter can settle the deal",
+    DealDeadlineNotReached = 2018 "deal deadline not yet reached",
+    DealNotOpen = 2019 "deal is not open",
+    HashLockNotFound = 2021 "hash lock not found",
+    HashLockClosed = 2022 "hash lock is closed",
+    NotHashLockRecipient = 2023 "only the recipient can claim the hash lock",
+    HashLockExpired = 2024 "hash lock has expired",
+    NotHashLockOwner = 2025 "only the owner can reclaim the hash lock",
+    HashLockNotExpired = 2026 "hash lock has not yet expired",
+    ClaimCodeInUse = 2027 "claim code already in use",
+    ClaimCodeNotFound = 2028 "claim code not found",
+    ClaimCodeClosed = 2029 "claim code is closed",
+    ClaimCodeExpired = 2030 "claim code has expired",
+    NotClaimCodeOwner = 2031 "only the owner can cancel the claim code",
+    StandingOrderAmountNotPositive = 2032 "standing order amount must be positive",
+    StandingOrderPeriodNotPositive = 2033 "standing order period must be positive",
+    StandingOrderNotFound = 2034 "standing order not found",
+    StreamNotFungible = 2035 "only fungible resources can be streamed",
+    StreamRateNotPositive = 2036 "stream rate must be positive",
+    StreamNotFound = 2037 "stream not found",
+    NotStreamRecipient = 2038 "only the recipient can claim from the stream",
+    StreamClosed = 2039 "stream is closed",
+    ReservationWithoutMax = 2040 "reserved allowance must have a max quantity",
+    RepeatingReservation = 2041 "repeating allowance cannot be reserved",
+    ReservationInUse = 2042 "allowance can still use its reservation",
+    BelowPriorityFloor = 2043 "allowance cannot go below its priority floor",
+    NegativeFloor = 2044 "floor cannot be negative",
+    FundsNotAvailable = 2045 "funds not available in pool",
+    AllowanceRevoked = 2046 "allowance has been revoked",
+    AllowanceExhausted = 2047 "allowance is exhausted",
+    AllowanceNotForPool = 2048 "allowance is not for this pool",
+    PoolNotFound = 2049 "pool not found",
+    ResourceNotFound = 2050 "resource not found",
+    AllowanceNotForEscrow = 2051 "allowance is not for this escrow",
+    SubsidyNotXrd = 2052 "only XRD can be used for subsidy",
+    NegativeMaxAmount = 2053 "max_amount cannot be negative",
+    AmountToTakeNotWhole = 2054 "amount to take must be whole number",
+    FungibleQuantityForNonFungible = 2055 "fungible TokenQuantity used for non-fungible resource",
+    NegativeQuantity = 2056 "cannot ask for negative amounts",
+    NonFungibleQuantityForFungible = 2057 "non-fungible TokenQuantity used for fungible resource",
+    /// MockDex only takes XRD as payment.
+    MockDexOnlyXrd = 2058 "only XRD can be paid",
+    /// MockDex uses exactly one Allowance per trade.
+    MockDexOneAllowance = 2059 "only one Allowance per trade",
+    /// MockDex only trades MEME.
+    MockDexOnlyMeme = 2060 "only MEME can be sold",
+    /// The ranges of a `NonFungibleRanges` quantity are out of order
+    /// or overlapping.
+    MalformedIdRanges = 2061 "id ranges must be sorted and disjoint",
+    StoredIdsNotNonFungible = 2062 "stored ids need a NonFungible max quantity",
+    NoStoredIds = 2063 "allowance does not have stored ids",
+    InvalidSchedule = 2064 "calendar schedule has no such day",
+    /// An Allowance was minted with a `max_uses` of zero.
+    ZeroMaxUses = 2065 "max uses must be at least one",
+    /// A single use of an Allowance took less than its
+    /// `min_per_use`.
+    BelowMinPerUse = 2066 "amount is below the allowance's minimum per use",
+    /// A single use of an Allowance took more than its
+    /// `max_per_use`.
+    AboveMaxPerUse = 2067 "amount is above the allowance's maximum per use",
+    /// An Allowance's per-use limits are negative, out of order,
+    /// fractional for a non-fungible, or the minimum is more than
+    /// the Allowance's `max_amount`.
+    InvalidPerUseLimits = 2068 "per-use limits do not fit the allowance",
+    /// An Allowance's `max_percentage` isn't above 0 and at most
+    /// 100.
+    InvalidPercentage = 2069 "percentage must be above 0 and at most 100",
+    /// A single use of an Allowance took more than its
+    /// `max_percentage` of the funds it draws on.
+    AbovePercentageOfBalance = 2070 "amount is above the allowance's share of the pool",
+    /// An Allowance's co-signer threshold is negative.
+    NegativeCoSignerThreshold = 2071 "co-signer threshold cannot be negative",
+    /// The badge used isn't one of the pool's guardians.
+    NotGuardian = 2072 "only a guardian of the pool can do this",
+    /// The guardian threshold is zero or more than there are
+    /// guardians.
+    InvalidGuardians = 2073 "guardian threshold must be between 1 and the number of guardians",
+    /// The guardians have already agreed on a different new owner.
+    RecoveryInProgress = 2074 "a different recovery is already underway",
+    /// There is no recovery to cancel or complete.
+    NoRecovery = 2075 "pool has no recovery underway",
+    /// The recovery hasn't been approved by enough guardians yet, or
+    /// its challenge period is still running.
+    RecoveryNotReady = 2076 "recovery is not ready to complete",
+    /// A pool can only be recovered to a badge that has never had a
+    /// pool.
+    NewOwnerHasPool = 2077 "new owner already has or had a pool",
+    /// Inheritance needs at least one beneficiary, all shares must
+    /// be positive and the inactivity period can't be negative.
+    InvalidInheritance = 2078 "invalid inheritance",
+    /// The pool has no beneficiaries.
+    NoInheritance = 2079 "pool has no inheritance",
+    /// The badge named is not a beneficiary of the pool.
+    NotBeneficiary = 2080 "not a beneficiary of the pool",
+    /// The pool owner hasn't yet been inactive for the inheritance
+    /// period.
+    OwnerStillActive = 2081 "pool owner is still active",
+    /// Each beneficiary can claim their share of each resource once.
+    ShareAlreadyClaimed = 2082 "share already claimed",
+    /// Only a sole beneficiary can take over a whole pool.
+    SharedInheritance = 2083 "pool has more than one beneficiary",
+    /// A savings limit can't be negative and its period must be
+    /// positive.
+    InvalidSavingsLock = 2084 "invalid savings lock",
+    /// A savings lock that still holds can only be replaced by one
+    /// that is at least as strict.
+    SavingsLockShortened = 2085 "savings lock cannot be loosened",
+    /// The pool owner can't take out these funds while they're
+    /// locked.
+    SavingsLocked = 2086 "funds are locked",
+    /// The pool owner has already taken out all the savings lock
+    /// allows them this period.
+    SavingsLimitExceeded = 2087 "savings lock limit exceeded",
+    /// The owner has taken out as much as the withdrawal threshold
+    /// allows this period, so the rest must go through
+    /// request_withdraw.
+    AboveWithdrawalThreshold = 2088 "withdrawal must be requested",
+    /// A withdrawal delay and its period must be more than zero, and
+    /// its thresholds can't be negative.
+    InvalidWithdrawalDelay = 2089 "invalid withdrawal delay",
+    /// Withdrawals can only be requested from a pool that has a
+    /// withdrawal delay.
+    NoWithdrawalDelay = 2090 "pool has no withdrawal delay",
+    /// There is no withdrawal request with that id.
+    WithdrawalNotFound = 2091 "withdrawal not found",
+    /// The withdrawal request has already been finalized or
+    /// cancelled.
+    WithdrawalClosed = 2092 "withdrawal is closed",
+    /// The withdrawal request is still waiting out its delay.
+    WithdrawalNotReady = 2093 "withdrawal is not ready to finalize",
+    /// Only the withdrawal delay's canceller badge can cancel
+    /// withdrawals.
+    NotWithdrawalCanceller = 2094 "not the withdrawal canceller",
+    /// A deal can't be opened with a deadline that has already
+    /// passed.
+    DealDeadlinePassed = 2095 "deal deadline has already passed",
+    /// A hash lock can't be made for zero tokens.
+    ZeroHashLock = 2096 "hash lock cannot be for zero tokens",
+    /// The preimage given does not hash to the lock's hash.
+    WrongPreimage = 2097 "preimage does not match hash lock",
+    /// A claim code that pays out funds needs an Allowance with a
+    /// `max_quantity`.
+    FundsClaimCodeWithoutMax = 2098 "funds claim code needs a max_quantity",
+    /// A standing order must be able to make at least one missed
+    /// payment.
+    StandingOrderNoCatchUp = 2099 "standing order max_catch_up must be at least one",
+    /// A standing order for a non-fungible resource must pay whole
+    /// NFTs.
+    StandingOrderAmountNotWhole = 2100 "standing order amount must be whole",
+    /// The recipient's account would not accept the payment.
+    PaymentRefused = 2101 "recipient refused the payment",
+    /// Guardians were named without a challenge period for the
+    /// owner to cancel a recovery in.
+    NoChallengePeriod = 2102 "guardians need a challenge period",
+    /// A sole beneficiary without a pool of their own must take
+    /// over the whole pool rather than claim a share of it.
+    MustTakeOver = 2103 "sole beneficiary must take over the pool",
+    /// While a savings lock holds the owner can't give out new ways
+    /// of taking funds from the pool, nor loosen the existing ones.
+    SavingsLockHolds = 2104 "not allowed while a savings lock holds",
+}
+
+impl EscrowError {
+    /// Finds the error with the given `code`, if there is one.
+    pub fn from_code(code: u16) -> Option<EscrowError> {
+        EscrowError::ALL.iter().find(|e| e.code() == code).copied()
+    }
+
+    /// Finds the error a panic message is for, by looking at the
+    /// code it starts with. Returns `None` if the message doesn't
+    /// start with one of our codes.
+    pub fn from_message(message: &str) -> Option<EscrowError> {
+        let code = message.split(' ').next()?.parse::<u16>().ok()?;
+        EscrowError::from_code(code)
+    }
+}
+
+impl fmt::Display for EscrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -104,6 +104,11 @@
 //! without them having to involve you directly every single time they
 //! need to make a withdrawal.
 //!
+//! If theirs is a monthly allowance, make it a `Scheduled` Allowance
+//! with a `Monthly` schedule. It can then be used once in each
+//! calendar month, starting on the day of the month you choose, no
+//! matter which day it was last used on.
+//!
 //! # Subsidies
 //!
 //! You can subsidize (internally it uses `lock_fee` or
@@ -154,6 +159,229 @@
 //! when calling [deposit_funds] and they will receive back an
 //! Allowance for that amount of the deposit.
 //!
+//! # Reserved Allowances
+//!
+//! Normally the Allowances you issue can together promise more funds
+//! than your pool holds, and whoever gets there first gets paid. If
+//! you need to give someone a firm guarantee, e.g. an exchange taking
+//! a firm order from you, you can make their Allowance reserved when
+//! minting it. This moves its max quantity out of your pool's free
+//! funds and sets it aside for that Allowance only. Neither you nor
+//! your other Allowances can touch those funds, and [read_funds] no
+//! longer counts them; use [read_reserved_funds] to see them.
+//!
+//! The reservation shrinks as the Allowance is used, and whatever is
+//! left goes back to your free funds when the Allowance is burnt by
+//! this component. If the Allowance expires, or gets burnt some
+//! other way (e.g. after you recall it), anyone can call
+//! [release_reservation] to return its funds to your pool.
+//!
+//! # Everything, or everything except
+//!
+//! Besides exact amounts and sets of nflids, a TokenQuantity can be
+//! `All`, meaning every token there is, or `NonFungibleExcept`,
+//! meaning any NFTs other than a given set. So you can withdraw all
+//! the MEME in your pool in one go, or mint an Allowance for any NFTs
+//! of a collection except ids 1 to 10 which are your rare ones. When
+//! someone uses such an Allowance we pick the NFTs they get out of
+//! the pool before checking the Allowance, so excluded NFTs can never
+//! leave through it.
+//!
+//! # Large sets of nflids
+//!
+//! An Allowance for a set of named nflids normally carries that set
+//! in its NFT data, which gets expensive once the set is large. For
+//! integer ids you can use ranges, see below. For other ids you can
+//! instead mint the Allowance with stored ids, which keeps the set in
+//! the Escrow component and only its size in the NFT. You can then
+//! grow the set in batches with [add_allowance_ids], and using the
+//! Allowance costs the same however large the set is.
+//!
+//! # Priority tiers
+//!
+//! When several Allowances compete for the same funds, whichever is
+//! used first normally wins. To give some Allowances precedence, put
+//! them in priority tiers when minting them and set a floor for each
+//! tier with [set_priority_floor]. An Allowance cannot take a vault
+//! below the floor of its tier. For example, if your trading
+//! Allowances are in a tier with a floor of 5000 XRD then the last
+//! 5000 XRD in the pool are left for your payroll Allowance in a tier
+//! without a floor.
+//!
+//! # Usage history
+//!
+//! Each Allowance keeps count of how it has been used: how many times,
+//! when it was last used, how much has been withdrawn with it and how
+//! much XRD it has locked for fees. You can read these off the
+//! Allowance NFT's data. You can also limit how many times an
+//! Allowance may be used, e.g. to make a monthly Allowance that stops
+//! after 12 payments.
+//!
+//! # Per-use limits
+//!
+//! An Allowance can also set the least and the most that a single use
+//! of it may take. A merchant Allowance for 1000 XRD could for
+//! example charge no less than 1 XRD and no more than 50 XRD at a
+//! time, so it can neither be drained in one go nor be used to
+//! pester you with dust. Only the very last use may take less than
+//! the minimum, so as to use up what is left. For non-fungibles the
+//! limits are on the number of NFTs taken. Fees locked with the
+//! Allowance count as a use like any other.
+//!
+//! Instead of, or as well as, a fixed amount an Allowance can be
+//! limited to a percentage of whatever the pool holds at the moment
+//! it is used. A market maker could for example take at most 10% of
+//! your MEME each time, however much MEME that happens to be.
+//!
+//! # Co-signed Allowances
+//!
+//! For large withdrawals you may want a second pair of eyes. An
+//! Allowance can name a co-signer badge and a threshold, and any use
+//! of it that takes it above the threshold then only works if a proof
+//! of the co-signer badge is in the auth zone as well. Uses are added
+//! up since the co-signer last signed off, so a large payment can't
+//! slip through as a string of small ones. So your treasurer can pay
+//! small invoices alone but needs the CFO to sign off every so often,
+//! without you as the pool owner having to take part.
+//!
+//! # Deals
+//!
+//! A pool owner can lock up funds from their pool in a Deal for the
+//! benefit of someone else, the payee. The funds stay locked until
+//! the owner (the payer) approves the deal, at which point they are
+//! released into the payee's pool. If the deal isn't closed before
+//! its deadline, anyone can send the funds back to the payer's pool.
+//!
+//! A Deal can optionally name an arbiter badge. The holder of that
+//! badge can settle the deal at any time while it is open, deciding
+//! how much of the funds go to the payee while the rest goes back to
+//! the payer.
+//!
+//! Each Deal is represented by a receipt NFT that is given to the
+//! payer, and which records the parties, the funds and the current
+//! state of the deal. Both parties can look up this information using
+//! the receipt's local id.
+//!
+//! # Hash time-locked swaps
+//!
+//! To swap funds in your pool for assets on another ledger, you can
+//! lock them up under the hash of a secret and a deadline. The
+//! intended recipient can claim the funds by revealing the secret
+//! (the preimage of the hash) before the deadline, and doing so
+//! publishes the secret in an event so that the counterparty can
+//! complete their side of the swap. If the funds aren't claimed in
+//! time you can reclaim them back into your pool. Each lock gets its
+//! own lock id, which you pass on to the recipient so they know
+//! which lock to claim.
+//!
+//! # Claim codes
+//!
+//! Sometimes you want to give someone an Allowance, or some funds,
+//! without knowing their account in advance. For this you can mint
+//! the Allowance with a claim code: the Escrow holds on to the
+//! Allowance under the hash of a secret of your choosing, and
+//! whoever presents that secret before the claim code expires gets
+//! it. Or, if you so choose, they get the funds the Allowance is for
+//! instead. You can then pass the secret along to its recipient
+//! through whatever channel is convenient. Each claim code can only
+//! be redeemed once, and you can cancel it while it's still
+//! unredeemed.
+//!
+//! # Standing orders
+//!
+//! Allowances let others pull funds from your pool, but sometimes you
+//! want to push payments instead, e.g. paying rent every 30 days into
+//! your landlord's Escrow pool or account. For this you can set up
+//! standing orders on your pool. Each standing order pays a fixed
+//! amount to its recipient once every period until its end date.
+//!
+//! Payments are made when someone calls [execute_due_orders], which
+//! anyone can do. This way an off-ledger keeper service, or indeed
+//! the recipient, can make sure payments go out on time. If nobody
+//! has called it for a while then missed payments are caught up on,
+//! up to a limit you set for each standing order. A payment that your
+//! pool can't afford, or that the recipient's account won't accept,
+//! stays outstanding until a later call can make it.
+//!
+//! # Payment streams
+//!
+//! Where a `Repeating` Allowance pays out in fixed chunks, a payment
+//! stream pays out continuously. You set aside some funding from your
+//! pool for the stream, and funds accrue to its recipient at a fixed
+//! rate per second until that funding runs out. The recipient can
+//! claim what has accrued at any time. You can top up, pause and
+//! resume the stream, and if you cancel it the recipient still gets
+//! what had accrued up to that point.
+//!
+//! # Time bounds
+//!
+//! The times at which an Allowance becomes valid and stops being
+//! valid, the deadlines of Deals and hash locks, and the expiry of
+//! claim codes are all given as a [TimeBound]. This is either a Unix
+//! time or a consensus epoch. The ledger's Unix time comes from the
+//! validators proposing blocks and is only accurate to the minute,
+//! whereas epochs are decided by consensus itself, so if you would
+//! rather not trust the clock you can use epochs instead. Standing
+//! orders and payment streams always run on Unix time, as do the
+//! delays between uses of a `Repeating` or `Scheduled` Allowance.
+//!
+//! # Recovering a pool
+//!
+//! Your pool is keyed on your badge, so if you lose that badge
+//! everything in the pool is stuck. To guard against this you can
+//! name guardians for your pool, e.g. a few friends' badges or badges
+//! you keep in cold storage, and how many of them it takes to
+//! recover it. Each guardian approves one new owner badge at a time,
+//! and when enough guardians approve the same one a challenge period
+//! starts, during which you can still cancel
+//! the recovery if you do in fact have your badge. After that anyone
+//! can complete the recovery, moving the pool with all its funds,
+//! Allowances and settings to the new owner.
+//!
+//! # Inheritance
+//!
+//! If you want someone to get your funds should you stop using them,
+//! you can name beneficiaries for your pool, each with a share, and
+//! how long you must have been inactive before they can claim. Any
+//! call you make with your badge counts as activity. Once the
+//! inactivity period has run out each beneficiary's share of each
+//! resource can be paid into their own pool, or if there is just the
+//! one beneficiary they can take over the whole pool instead. A sole
+//! beneficiary who has never had a pool of their own can only take
+//! over, so that nobody can block the takeover by paying out a share
+//! and so giving them a pool.
+//!
+//! # Savings locks
+//!
+//! The enthusiasm you need to curb may be your own. You can lock
+//! your pool, or just some of the resources in it, until a point in
+//! time, either entirely or so that you can take out no more than a
+//! set amount per period. A lock can't be loosened or shortened
+//! while it holds. It doesn't stop anyone depositing into your pool,
+//! and the Allowances you have given out keep working as before.
+//! Payments on your standing orders and inheritance shares paid out
+//! do count against the lock. While it holds you also can't mint
+//! Allowances without reserved funds, add standing orders, or
+//! loosen your inheritance. Note that fees you lock with [subsidize]
+//! count in full against your limit even if not all of them end up
+//! being spent.
+//!
+//! # Delayed withdrawals
+//!
+//! Should someone steal your badge they could empty your pool. To
+//! make that harder you can set a withdrawal delay: withdrawals above
+//! a threshold must then be requested, and are only paid out once a
+//! delay has passed. During that time a separate canceller badge,
+//! which you keep somewhere safe, can cancel the request and put the
+//! funds back in the pool. Each step is announced in an event so
+//! that you can watch for requests you didn't make. The threshold
+//! applies to everything you take out over a period, so it can't be
+//! got around by splitting a withdrawal up, and it covers all the
+//! ways you can move funds out of the pool such as fees, Deals, hash
+//! locks, streams and standing orders. While the delay is set,
+//! minting Allowances and changing your guardians or inheritance
+//! also need the canceller badge.
+//!
 //! # Public API
 //!
 //! - [instantiate_escrow] Create a new Escrow instance.
@@ -162,11 +390,17 @@
 //!
 //! - [read_funds] See how many funds you have available.
 //!
+//! - [read_reserved_funds] See how many funds are reserved for
+//! Allowances.
+//!
 //! - [withdraw] Pull funds out of your Escrow pool.
 //!
 //! - [withdraw_with_allowance] Use an Allowance to pull funds out of
 //! someone's Escrow pool.
 //!
+//! - [withdraw_with_allowance_up_to] Use an Allowance to pull as much
+//! as is available, up to some quantity, out of someone's Escrow pool.
+//!
 //! - [withdraw_all_of] Pull all funds of one type out of your Escrow
 //! pool.
 //!
@@ -181,12 +415,59 @@
 //!
 //! - [mint_allowance] Create an Allowance for your Escrow pool.
 //!
+//! - [add_allowance_ids] Add nflids to an Allowance with stored ids.
+//!
+//! - [release_reservation] Return the funds reserved for an expired
+//! or burnt Allowance to its pool.
+//!
+//! - [allowance_status] See whether an Allowance can be used right
+//! now.
+//!
+//! - [revoke_allowance] Stop an Allowance for your Escrow pool from
+//! being used.
+//!
+//! - [quote_allowance] Find out what would happen if an Allowance
+//! were used right now, without using it.
+//!
+//! - [set_priority_floor] Set the floor for a priority tier of
+//! Allowances.
+//!
+//! - [read_priority_floor] See the floor for a priority tier.
+//!
+//! - [redeem_claim_code] Redeem a claim code by providing its secret.
+//!
+//! - [cancel_claim_code] Cancel a claim code you have created.
+//!
 //! - [reduce_allowance_to_amount] Reduce the amount of funds
 //! available in an Allowance you control.
 //!
 //! - [reduce_allowance_by_nflids] Reduce the set of non-fungible ids
 //! available in an Allowance you control.
 //!
+//! - [add_standing_order] Set up regular payments out of your Escrow
+//! pool.
+//!
+//! - [cancel_standing_order] Stop a standing order.
+//!
+//! - [execute_due_orders] Make any payments that have fallen due on a
+//! pool's standing orders.
+//!
+//! - [start_stream] Start streaming funds from your Escrow pool to
+//! someone.
+//!
+//! - [top_up_stream] Add more funding to a stream.
+//!
+//! - [pause_stream] Pause a stream.
+//!
+//! - [resume_stream] Resume a paused stream.
+//!
+//! - [cancel_stream] End a stream, paying out what has accrued.
+//!
+//! - [claim_stream] Claim the funds that have accrued to you on a
+//! stream.
+//!
+//! - [read_stream] See how many funds have accrued on a stream.
+//!
 //! - [add_trusted_nfgid] Add a non-fungible global id to the list of
 //! badges you trust to be given automatic Allowances when depositing
 //! to your Escrow pool.
@@ -205,17 +486,73 @@
 //!
 //! - [is_resource_trusted] Checks if a given resource is trusted.
 //!
+//! - [open_deal] Lock up funds from your pool in a Deal with someone.
+//!
+//! - [approve_deal] Release the funds of a Deal you're paying for.
+//!
+//! - [settle_deal] As arbiter, split the funds of a Deal between its
+//! parties.
+//!
+//! - [refund_deal] Return the funds of an expired Deal to its payer.
+//!
+//! - [lock_with_hash] Lock up funds from your pool under a hash.
+//!
+//! - [claim_hash_lock] Claim hash locked funds by revealing the
+//! preimage.
+//!
+//! - [reclaim_hash_lock] Take back hash locked funds after the
+//! deadline.
+//!
+//! - [set_guardians] Name the guardians that can recover your pool.
+//!
+//! - [approve_recovery] As guardian, approve moving a pool to a new
+//! owner.
+//!
+//! - [cancel_recovery] Stop a recovery of your pool.
+//!
+//! - [complete_recovery] Move a pool to its new owner once the
+//! challenge period is over.
+//!
+//! - [read_recovery] See the recovery underway for a pool.
+//!
+//! - [set_inheritance] Name the beneficiaries of your pool.
+//!
+//! - [claim_inheritance] Pay out a beneficiary's share of an inactive
+//! owner's pool, or hand it over to them.
+//!
+//! - [read_inheritance] See the beneficiaries of a pool.
+//!
+//! - [read_last_active] See when a pool owner was last active.
+//!
+//! - [set_savings_lock] Lock away your own funds for a while.
+//!
+//! - [read_savings_lock] See the savings lock on a pool.
+//!
+//! - [set_withdrawal_delay] Make large withdrawals from your pool
+//! wait.
+//!
+//! - [request_withdraw] Ask for a large withdrawal.
+//!
+//! - [finalize_withdraw] Pay out a withdrawal once its delay has
+//! passed.
+//!
+//! - [cancel_withdraw] As canceller, stop a withdrawal.
+//!
 //!
 //! # About error messages
 //!
-//! Many error messages that are produced on panics in this code are
+//! All error messages that are produced on panics in this code are
 //! preceded with an error code, starting at error code 2000 for this
 //! project. They are there to make each error easily recognizable in
 //! the test suite so that I can easily test that a transaction that
 //! is intended to fail fails for the correct reason.
 //!
-//! Note that as this is a fairly new idea, not all the error messages
-//! have codes at this point.
+//! The codes are all listed in the [EscrowError] enum, which you can
+//! also use to map the panic message of a failed transaction back to
+//! its cause.
+//!
+//! Panics raised by the Radix Engine itself, e.g. when trying to take
+//! more funds out of a vault than it holds, don't carry our codes.
 //!
 //! # About IndexSet sizes for non-fungible local ids
 //!
@@ -224,34 +561,93 @@
 //! implementation is suitable for a moderate number of
 //! nonfungible-ids in that set, but not very many.
 //!
+//! If your non-fungibles have integer local ids you can instead use
+//! `TokenQuantity::NonFungibleRanges`, which describes the ids as
+//! ranges. An Allowance for all of ids 1 to 10,000 except for id 42
+//! is then just two ranges, and using the Allowance or reducing it by
+//! nflids works on those ranges without ever listing out the ids in
+//! them. Actually withdrawing the NFTs still needs their ids listed
+//! of course, so keep each withdrawal moderately sized.
+//!
+//! [EscrowError]: crate::errors::EscrowError
+//! [TimeBound]: crate::time_bound::TimeBound
 //! [instantiate_escrow]: crate::escrow::Escrow::instantiate_escrow
 //! [deposit_funds]: crate::escrow::Escrow::deposit_funds
 //! [read_funds]: crate::escrow::Escrow::read_funds
+//! [read_reserved_funds]: crate::escrow::Escrow::read_reserved_funds
 //! [withdraw]: crate::escrow::Escrow::withdraw
 //! [withdraw_with_allowance]: crate::escrow::Escrow::withdraw_with_allowance
+//! [withdraw_with_allowance_up_to]: crate::escrow::Escrow::withdraw_with_allowance_up_to
 //! [withdraw_all_of]: crate::escrow::Escrow::withdraw_all_of
 //! [subsidize]: crate::escrow::Escrow::subsidize
 //! [subsidize_with_allowance]: crate::escrow::Escrow::subsidize_with_allowance
 //! [subsidize_contingent]: crate::escrow::Escrow::subsidize_contingent
 //! [mint_allowance]: crate::escrow::Escrow::mint_allowance
+//! [add_allowance_ids]: crate::escrow::Escrow::add_allowance_ids
+//! [release_reservation]: crate::escrow::Escrow::release_reservation
+//! [allowance_status]: crate::escrow::Escrow::allowance_status
+//! [revoke_allowance]: crate::escrow::Escrow::revoke_allowance
+//! [quote_allowance]: crate::escrow::Escrow::quote_allowance
+//! [set_priority_floor]: crate::escrow::Escrow::set_priority_floor
+//! [read_priority_floor]: crate::escrow::Escrow::read_priority_floor
+//! [redeem_claim_code]: crate::escrow::Escrow::redeem_claim_code
+//! [cancel_claim_code]: crate::escrow::Escrow::cancel_claim_code
 //! [reduce_allowance_to_amount]: crate::escrow::Escrow::reduce_allowance_to_amount
 //! [reduce_allowance_by_nflids]: crate::escrow::Escrow::reduce_allowance_by_nflids
+//! [add_standing_order]: crate::escrow::Escrow::add_standing_order
+//! [cancel_standing_order]: crate::escrow::Escrow::cancel_standing_order
+//! [execute_due_orders]: crate::escrow::Escrow::execute_due_orders
+//! [start_stream]: crate::escrow::Escrow::start_stream
+//! [top_up_stream]: crate::escrow::Escrow::top_up_stream
+//! [pause_stream]: crate::escrow::Escrow::pause_stream
+//! [resume_stream]: crate::escrow::Escrow::resume_stream
+//! [cancel_stream]: crate::escrow::Escrow::cancel_stream
+//! [claim_stream]: crate::escrow::Escrow::claim_stream
+//! [read_stream]: crate::escrow::Escrow::read_stream
 //! [add_trusted_nfgid]: crate::escrow::Escrow::add_trusted_nfgid
 //! [remove_trusted_nfgid]: crate::escrow::Escrow::remove_trusted_nfgid
 //! [is_nfgid_trusted]: crate::escrow::Escrow::is_nfgid_trusted
 //! [add_trusted_resource]: crate::escrow::Escrow::add_trusted_resource
 //! [remove_trusted_resource]: crate::escrow::Escrow::remove_trusted_resource
 //! [is_resource_trusted]: crate::escrow::Escrow::is_resource_trusted
+//! [open_deal]: crate::escrow::Escrow::open_deal
+//! [approve_deal]: crate::escrow::Escrow::approve_deal
+//! [settle_deal]: crate::escrow::Escrow::settle_deal
+//! [refund_deal]: crate::escrow::Escrow::refund_deal
+//! [lock_with_hash]: crate::escrow::Escrow::lock_with_hash
+//! [claim_hash_lock]: crate::escrow::Escrow::claim_hash_lock
+//! [reclaim_hash_lock]: crate::escrow::Escrow::reclaim_hash_lock
+//! [set_guardians]: crate::escrow::Escrow::set_guardians
+//! [approve_recovery]: crate::escrow::Escrow::approve_recovery
+//! [cancel_recovery]: crate::escrow::Escrow::cancel_recovery
+//! [complete_recovery]: crate::escrow::Escrow::complete_recovery
+//! [read_recovery]: crate::escrow::Escrow::read_recovery
+//! [set_inheritance]: crate::escrow::Escrow::set_inheritance
+//! [claim_inheritance]: crate::escrow::Escrow::claim_inheritance
+//! [read_inheritance]: crate::escrow::Escrow::read_inheritance
+//! [read_last_active]: crate::escrow::Escrow::read_last_active
+//! [set_savings_lock]: crate::escrow::Escrow::set_savings_lock
+//! [read_savings_lock]: crate::escrow::Escrow::read_savings_lock
+//! [set_withdrawal_delay]: crate::escrow::Escrow::set_withdrawal_delay
+//! [request_withdraw]: crate::escrow::Escrow::request_withdraw
+//! [finalize_withdraw]: crate::escrow::Escrow::finalize_withdraw
+//! [cancel_withdraw]: crate::escrow::Escrow::cancel_withdraw
 
 use scrypto::prelude::*;
 
 pub mod util;
 pub mod token_quantity;
 pub mod mock_dex;
+pub mod errors;
+pub mod calendar;
+pub mod time_bound;
 
 use radix_engine_common::ManifestSbor;
-use util::{unix_time_now, length_of_option_set, unchecked_proof_to_nfgid};
-use token_quantity::TokenQuantity;
+use util::{unix_time_now, unchecked_proof_to_nfgid, dec_to_u64};
+use token_quantity::{NflidSet, TokenQuantity};
+use errors::EscrowError;
+use calendar::CalendarSchedule;
+use time_bound::{Now, TimeBound};
 
 /// Defines the lifecycle of an Allowance NFT. Every Allowance follows
 /// exactly one of these strategies.
@@ -279,8 +675,20 @@ pub enum AllowanceLifeCycle {
     /// minute.
     ///
     /// A `Repeating` allowance NFT will never be burnt by this
-    /// component.
+    /// component. Give it a `max_uses` if it should only be used so
+    /// many times.
     Repeating{min_delay: Option<i64>},
+
+    /// The allowance can be used once in each period of `schedule`,
+    /// each time for up to `max_amount` tokens. Unlike with
+    /// `Repeating`, the next use doesn't depend on when the last one
+    /// was made, so e.g. a `Monthly{day: 1}` allowance can always be
+    /// used on the 1st whether it was last used on the 1st or the
+    /// 20th of the previous month.
+    ///
+    /// A `Scheduled` allowance NFT will never be burnt by this
+    /// component.
+    Scheduled{schedule: CalendarSchedule},
 }
 
 /// An Allowance NFT allows its owner to extract some amount of tokens
@@ -301,18 +709,19 @@ pub struct AllowanceNfData {
     /// The Escrow pool this allowance is associated with.
     pub escrow_pool: (ComponentAddress, NonFungibleGlobalId),
     
-    /// If set, this is the latest Unix time at which the allowance can
-    /// be used.
-    pub valid_until: Option<i64>,
+    /// If set, this is the latest time at which the allowance can be
+    /// used.
+    pub valid_until: Option<TimeBound>,
 
-    /// The allowance cannot be used until this Unix time. Set to zero
-    /// if you don't want to use this.
+    /// The allowance cannot be used until this time. Set to
+    /// `UnixTime(0)` if you don't want to use this.
     ///
     /// In addition to determining the first possible use time, this
     /// field is also used for tracking the next allowed use if the
-    /// allowance type is `Repeating` with a delay set.
+    /// allowance type is `Repeating` with a delay set, or
+    /// `Scheduled`. That next use is always given as a Unix time.
     #[mutable]
-    pub valid_from: i64,
+    pub valid_from: TimeBound,
 
     /// How to deal with this allowance being used multiple times.
     pub life_cycle: AllowanceLifeCycle,
@@ -324,15 +733,460 @@ pub struct AllowanceNfData {
     /// `None` then any amount can be taken.
     #[mutable]
     pub max_amount: Option<TokenQuantity>,
+
+    /// If true then the funds for this allowance have been set aside
+    /// in the pool, and are guaranteed to be available to it.
+    pub reserved: bool,
+
+    /// The priority tier of this allowance. The pool owner can set a
+    /// floor for each tier below which allowances in that tier cannot
+    /// take a pool vault.
+    pub priority: u8,
+
+    /// Set to true if the pool owner has revoked this allowance.
+    #[mutable]
+    pub revoked: bool,
+
+    /// If set, the allowance has a set of nflids that it may take
+    /// which is stored in the Escrow component rather than in
+    /// `max_amount`. Only the size of that set is kept here.
+    #[mutable]
+    pub stored_ids: Option<StoredIds>,
+
+    /// If set, the allowance can be used at most this many times,
+    /// whatever its life cycle. Once it has been used that many times
+    /// it is `Exhausted`.
+    pub max_uses: Option<u64>,
+
+    /// If set, each use of the allowance must take at least this
+    /// many tokens, except that the last use may take whatever is
+    /// left of `max_amount` even if that is less. For non-fungibles
+    /// this is a number of NFTs. Fees locked with
+    /// [subsidize_with_allowance] count as a use too.
+    ///
+    /// [subsidize_with_allowance]: crate::escrow::Escrow::subsidize_with_allowance
+    pub min_per_use: Option<Decimal>,
+
+    /// If set, each use of the allowance can take at most this many
+    /// tokens. For non-fungibles this is a number of NFTs. Fees
+    /// locked with [subsidize_with_allowance] count as a use too.
+    ///
+    /// [subsidize_with_allowance]: crate::escrow::Escrow::subsidize_with_allowance
+    pub max_per_use: Option<Decimal>,
+
+    /// If set, each use of the allowance can take at most this
+    /// percentage of the funds it draws on, as they are at the time
+    /// of use. The result is rounded down to the resource's
+    /// divisibility, so for non-fungibles to a whole number of NFTs.
+    ///
+    /// This limit is on top of `max_amount`, not instead of it. An
+    /// `Accumulating` allowance still can't take more than its
+    /// `max_amount` in total, and a `Repeating` or `Scheduled` one
+    /// can take the lesser of its `max_amount` and the percentage
+    /// each time. With no `max_amount` only the percentage applies.
+    pub max_percentage: Option<Decimal>,
+
+    /// If set, larger uses of the allowance also need the approval
+    /// of a second badge.
+    pub co_signer: Option<CoSigner>,
+
+    /// What the allowance has been used for so far.
+    #[mutable]
+    pub usage: AllowanceUsage,
+}
+
+/// Keeps count of the nflids stored for an Allowance, see
+/// [AllowanceOptions::stored_ids].
+#[derive(ScryptoSbor, Clone, PartialEq, Eq, Debug)]
+pub struct StoredIds {
+    /// How many nflids have been stored for the Allowance.
+    pub permitted: u64,
+    /// How many of those have been taken.
+    pub consumed: u64,
+}
+
+/// A second badge that must approve larger uses of an Allowance, see
+/// [AllowanceNfData::co_signer].
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub struct CoSigner {
+    /// A proof of this badge must be in the auth zone when a use
+    /// takes the Allowance over `threshold`.
+    pub approver: NonFungibleGlobalId,
+
+    /// Uses don't need the approver for as long as they add up to
+    /// no more than this since the approver last signed off on one.
+    /// This way a large withdrawal can't get around the approver by
+    /// being split into small ones. For non-fungibles this is a
+    /// number of NFTs. Set it to zero to have every use approved.
+    pub threshold: Decimal,
+}
+
+/// Keeps track of how an Allowance has been used, see
+/// [AllowanceNfData::usage].
+#[derive(ScryptoSbor, Clone, PartialEq, Eq, Debug)]
+pub struct AllowanceUsage {
+    /// The total quantity withdrawn with the Allowance. For
+    /// non-fungibles this is the number of NFTs.
+    pub total_withdrawn: Decimal,
+    /// The total amount of XRD locked for fees with the Allowance.
+    pub total_subsidy: Decimal,
+    /// How many times the Allowance has been used.
+    pub use_count: u64,
+    /// The Unix time the Allowance was last used at, if it has been
+    /// used at all.
+    pub last_used: Option<i64>,
+    /// The total quantity taken with the Allowance since its
+    /// co-signer last approved a use. This is always zero if it has
+    /// no co-signer.
+    pub since_co_signed: Decimal,
+}
+
+/// Where an Allowance stands at a given point in time, see
+/// [AllowanceNfData::status].
+#[derive(ScryptoSbor, Clone, PartialEq, Eq, Debug)]
+pub enum AllowanceStatus {
+    /// The allowance can't be used until its `valid_from` time.
+    NotYetValid,
+
+    /// The allowance is past its `valid_until` time and can never be
+    /// used again.
+    Expired,
+
+    /// The allowance is a `Repeating` or `Scheduled` one that was
+    /// recently used. It can next be used at Unix time `until`.
+    CoolingDown{until: i64},
+
+    /// There is nothing left in the allowance, or it has been used
+    /// as many times as its `max_uses` permits.
+    Exhausted,
+
+    /// The pool owner has revoked the allowance.
+    Revoked,
+
+    /// The allowance can be used right now for up to `remaining`
+    /// tokens, or any amount if this is `None`. Note that the pool
+    /// may not actually hold that much.
+    ///
+    /// The allowance's stored ids, if any, are not in `remaining`.
+    /// See [AllowanceNfData::stored_ids_left] for those.
+    Usable{remaining: Option<TokenQuantity>},
+}
+
+/// Additional settings for a new Allowance, beyond those that every
+/// Allowance has. The default value gives a plain Allowance.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Default, Debug)]
+pub struct AllowanceOptions {
+    /// Set aside `max_quantity` from the pool for this Allowance so
+    /// that neither the pool owner nor other Allowances can take
+    /// it. Only `OneOff` and `Accumulating` Allowances with a
+    /// `max_quantity` can be reserved.
+    pub reserved: bool,
+
+    /// The priority tier to put the Allowance in. Defaults to tier 0.
+    pub priority: u8,
+
+    /// Keep the nflids named in a `NonFungible` `max_quantity` in
+    /// the Escrow component instead of in the Allowance NFT. Taking
+    /// from such an Allowance costs the same no matter how many ids
+    /// it has, so use this for large sets of string or RUID ids. You
+    /// can add more ids later with [add_allowance_ids].
+    ///
+    /// [add_allowance_ids]: crate::escrow::Escrow::add_allowance_ids
+    pub stored_ids: bool,
+
+    /// Limit how many times the Allowance can be used. Unlike the
+    /// Allowance's other limits this works the same for every life
+    /// cycle, so e.g. a monthly `Scheduled` Allowance with a
+    /// `max_uses` of 12 runs for a year. Must be at least one.
+    pub max_uses: Option<u64>,
+
+    /// The least a single use of the Allowance may take, e.g. to stop
+    /// it from being used for dust. Cannot be more than
+    /// `max_quantity`.
+    pub min_per_use: Option<Decimal>,
+
+    /// The most a single use of the Allowance may take, whatever is
+    /// left of its `max_quantity`. Cannot be less than
+    /// `min_per_use`.
+    pub max_per_use: Option<Decimal>,
+
+    /// The most a single use of the Allowance may take as a
+    /// percentage of what is in the pool at the time, e.g. `10` for
+    /// 10%. Must be above 0 and at most 100. See
+    /// [AllowanceNfData::max_percentage].
+    pub max_percentage: Option<Decimal>,
+
+    /// Require a proof of a second badge for uses of the Allowance
+    /// above a threshold. This gives you a four-eyes check on large
+    /// withdrawals without having to take part in them yourself.
+    pub co_signer: Option<CoSigner>,
+
+    /// Have the Escrow hold on to the Allowance under a claim code
+    /// instead of handing it to you. Whoever redeems the claim code
+    /// with [redeem_claim_code] gets the Allowance, or the funds it
+    /// is for. See [ClaimCodeTerms].
+    ///
+    /// [redeem_claim_code]: crate::escrow::Escrow::redeem_claim_code
+    pub claim_code: Option<ClaimCodeTerms>,
+}
+
+/// How an Allowance held under a claim code can be redeemed, see
+/// [AllowanceOptions::claim_code].
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct ClaimCodeTerms {
+    /// The hash of the secret that redeems the claim code. Each hash
+    /// can only ever be used for one claim code.
+    pub code_hash: Hash,
+
+    /// The claim code cannot be redeemed after this time. It must
+    /// not already have passed.
+    pub expires: TimeBound,
+
+    /// If true, redeeming the claim code uses the Allowance for its
+    /// full `max_quantity` and hands over the funds, instead of
+    /// handing over the Allowance itself. The Allowance must then
+    /// have a `max_quantity`, and unless it is reserved the funds
+    /// must be in the pool at the time of redemption.
+    pub pay_out_funds: bool,
+}
+
+/// How an Allowance changes when it is used, as determined by
+/// [AllowanceNfData::after_use].
+struct AllowanceUpdate {
+    /// The Allowance is spent and should be burnt.
+    burn: bool,
+    /// If set, the new `max_amount` for the Allowance.
+    max_amount: Option<TokenQuantity>,
+    /// If set, the new `valid_from` for the Allowance.
+    valid_from: Option<TimeBound>,
+    /// If set, the new `stored_ids` counters for the Allowance.
+    stored_ids: Option<StoredIds>,
+    /// The new `usage` of the Allowance.
+    usage: AllowanceUsage,
+}
+
+/// The answer to a [quote_allowance] call.
+///
+/// [quote_allowance]: crate::escrow::Escrow::quote_allowance
+#[derive(ScryptoSbor, Clone, PartialEq, Eq, Debug)]
+pub struct AllowanceQuote {
+    /// The most tokens the Allowance could take right now. This
+    /// considers the Allowance's own limits as well as the funds
+    /// available to it in the pool. For non-fungibles this is the
+    /// number of NFTs.
+    pub max_available: Decimal,
+
+    /// The status of the Allowance.
+    pub status: AllowanceStatus,
+
+    /// Whether taking the quoted quantity would burn the Allowance.
+    pub would_burn: bool,
+
+    /// The earliest time the Allowance can be used, which may be
+    /// now. This is `None` if it has expired.
+    pub next_use: Option<TimeBound>,
+
+    /// If taking the quoted quantity right now would fail, this is
+    /// the error message it would fail with.
+    pub failure: Option<String>,
 }
 
 impl AllowanceNfData {
-    /// Checks our time-based validity.
-    pub fn is_valid(&self) -> bool {
-        let now = unix_time_now();
-        self.valid_from >= now &&
-            (self.valid_until.is_none()
-            || self.valid_until.unwrap() < now)
+    /// Determines the status of the allowance at time `now`.
+    /// This is the same check that the Escrow makes before letting
+    /// the allowance be used, so integrators can rely on it to tell
+    /// whether an allowance is currently worth anything.
+    ///
+    /// Note that we tell a `Repeating` allowance with a `min_delay`,
+    /// or a `Scheduled` one, is cooling down by it having been used
+    /// before. Otherwise, and always when `valid_from` is an epoch,
+    /// it is reported as `NotYetValid`.
+    pub fn status(&self, now: &Now) -> AllowanceStatus {
+        if self.revoked {
+            AllowanceStatus::Revoked
+        } else if self.valid_until.map_or(false, |valid_until| valid_until.is_before(now)) {
+            AllowanceStatus::Expired
+        } else if (self.max_amount.as_ref().map_or(false, |max| max.is_zero())
+                   && self.stored_ids_left() == 0)
+            || self.max_uses.map_or(false, |max_uses| self.usage.use_count >= max_uses)
+        {
+            AllowanceStatus::Exhausted
+        } else if self.valid_from.is_after(now) {
+            match (&self.life_cycle, self.valid_from) {
+                (AllowanceLifeCycle::Repeating{min_delay: Some(_)},
+                 TimeBound::UnixTime(valid_from))
+                if self.usage.last_used.is_some() =>
+                    AllowanceStatus::CoolingDown{until: valid_from},
+                (AllowanceLifeCycle::Scheduled{..}, TimeBound::UnixTime(valid_from))
+                if self.usage.last_used.is_some() =>
+                    AllowanceStatus::CoolingDown{until: valid_from},
+                _ => AllowanceStatus::NotYetValid,
+            }
+        } else {
+            AllowanceStatus::Usable{remaining: self.max_amount.clone()}
+        }
+    }
+
+    /// The number of stored ids the allowance can still take.
+    pub fn stored_ids_left(&self) -> u64 {
+        self.stored_ids.as_ref().map_or(0, |stored| stored.permitted - stored.consumed)
+    }
+
+    /// Checks that the allowance can support taking `amount` at time
+    /// `now`, returning the error if it can't. Any of the
+    /// allowance's stored ids must already have been removed from
+    /// `amount`.
+    fn check_use(&self, amount: &TokenQuantity, now: &Now) -> Result<(), EscrowError> {
+        match self.status(now) {
+            AllowanceStatus::Usable{..} => {},
+            AllowanceStatus::NotYetValid | AllowanceStatus::CoolingDown{..} =>
+                return Err(EscrowError::AllowanceNotYetValid),
+            AllowanceStatus::Expired =>
+                return Err(EscrowError::AllowanceExpired),
+            AllowanceStatus::Exhausted =>
+                return Err(EscrowError::AllowanceExhausted),
+            AllowanceStatus::Revoked =>
+                return Err(EscrowError::AllowanceRevoked),
+        }
+
+        // Check that the allowance is big enough for this
+        // withdrawal.
+        self.remaining_after(amount)?;
+        Ok(())
+    }
+
+    /// Determines whether a use of the allowance that takes `taken`
+    /// needs its co-signer's approval, which it does if it brings
+    /// what has been taken since the last approved use above the
+    /// co-signer's threshold.
+    fn needs_co_signer(&self, taken: Decimal) -> bool {
+        self.co_signer.as_ref().map_or(false, |co_signer|
+            self.usage.since_co_signed + taken > co_signer.threshold)
+    }
+
+    /// Checks that taking `amount` in a single use is within the
+    /// allowance's per-use limits. Unlike with [check_use],
+    /// `amount` here includes any stored ids being taken.
+    ///
+    /// Taking everything that is left of the allowance is always
+    /// allowed by `min_per_use`, so that no remainder gets stranded
+    /// below it.
+    ///
+    /// [check_use]: AllowanceNfData::check_use
+    fn check_per_use(&self, amount: &TokenQuantity) -> Result<(), EscrowError> {
+        let amount = amount.to_amount();
+        let left = self.max_amount.as_ref().and_then(
+            |max| max.to_amount().checked_add(Decimal::from(self.stored_ids_left())));
+        if self.min_per_use.map_or(false, |min| amount < min)
+            && left != Some(amount)
+        {
+            Err(EscrowError::BelowMinPerUse)
+        } else if self.max_per_use.map_or(false, |max| amount > max) {
+            Err(EscrowError::AboveMaxPerUse)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The most that a single use of the allowance may take out of
+    /// `vault` under its `max_percentage`, rounded down to the
+    /// resource's divisibility. This is `None` if the allowance has
+    /// no percentage limit.
+    fn percentage_of(&self, vault: &Vault) -> Option<Decimal> {
+        self.max_percentage.map(|percentage| {
+            let divisibility = ResourceManager::from(vault.resource_address())
+                .resource_type().divisibility().unwrap_or(0);
+            (vault.amount() * percentage / dec!("100"))
+                .checked_round(divisibility, RoundingMode::ToZero).unwrap()
+        })
+    }
+
+    /// Works out what is left of the allowance's `max_amount` after
+    /// taking `amount` with it, or the error if it can't cover
+    /// `amount`. This is `None` if the allowance has no limit.
+    fn remaining_after(&self, amount: &TokenQuantity)
+                       -> Result<Option<TokenQuantity>, EscrowError>
+    {
+        let max_quantity = match &self.max_amount {
+            Some(max_quantity) => max_quantity,
+            // This means there is no limit
+            None => return Ok(None),
+        };
+        match max_quantity.checked_sub(amount) {
+            Some(remaining) => Ok(Some(remaining)),
+            None => match max_quantity {
+                TokenQuantity::Fungible(_) => Err(EscrowError::InsufficientFungibleAllowance),
+                _ if amount.to_amount().checked_round(0, RoundingMode::ToZero)
+                    != Some(amount.to_amount()) => Err(EscrowError::AmountToTakeNotWhole),
+                _ => Err(EscrowError::InsufficientNonFungibleAllowance),
+            },
+        }
+    }
+
+    /// Determines how the allowance changes when `amount` is taken
+    /// with it at time `now`, along with `stored_taken` of its
+    /// stored ids. If `subsidy` is set then `amount` is XRD locked
+    /// for fees rather than withdrawn. The use must already have
+    /// passed [check_use].
+    ///
+    /// [check_use]: AllowanceNfData::check_use
+    fn after_use(&self, amount: &TokenQuantity, stored_taken: u64, subsidy: bool, now: &Now)
+                 -> AllowanceUpdate
+    {
+        let taken = amount.to_amount() + Decimal::from(stored_taken);
+        let mut usage = self.usage.clone();
+        usage.use_count += 1;
+        usage.last_used = Some(now.unix_time);
+        if subsidy {
+            usage.total_subsidy += taken;
+        } else {
+            usage.total_withdrawn += taken;
+        }
+        if self.needs_co_signer(taken) {
+            usage.since_co_signed = Decimal::ZERO;
+        } else if self.co_signer.is_some() {
+            usage.since_co_signed += taken;
+        }
+        let mut update = AllowanceUpdate { burn: false, max_amount: None, valid_from: None,
+                                           stored_ids: None, usage };
+
+        match self.life_cycle {
+            AllowanceLifeCycle::OneOff => {
+                update.burn = true;
+            },
+            AllowanceLifeCycle::Accumulating => {
+                if stored_taken > 0 {
+                    update.stored_ids = self.stored_ids.as_ref().map(|stored| StoredIds {
+                        permitted: stored.permitted,
+                        consumed: stored.consumed + stored_taken,
+                    });
+                }
+                let stored_left = self.stored_ids_left() - stored_taken;
+                match self.remaining_after(amount).unwrap_or_else(|error| panic!("{}", error)) {
+                    Some(remaining) => {
+                        if remaining.is_zero() && stored_left == 0 {
+                            // No tokens left in allowance
+                            update.burn = true;
+                        } else {
+                            update.max_amount = Some(remaining);
+                        }
+                    },
+                    None => {
+                        update.burn = true;
+                    },
+                }
+            },
+            AllowanceLifeCycle::Repeating{min_delay} => {
+                if let Some(min_delay) = min_delay {
+                    update.valid_from = Some(TimeBound::UnixTime(now.unix_time + min_delay));
+                }
+            },
+            AllowanceLifeCycle::Scheduled{schedule} => {
+                update.valid_from = Some(TimeBound::UnixTime(
+                    schedule.next_period_start(now.unix_time)));
+            },
+        }
+        update
     }
 }
 
@@ -352,64 +1206,649 @@ struct Pool {
     trusted_res: KeyValueStore<ResourceAddress, bool>,
     /// Contains all the pool funds.
     vaults: KeyValueStore<ResourceAddress, Vault>,
+    /// All standing orders ever made for this pool, keyed by their
+    /// order id.
+    standing_orders: KeyValueStore<u64, StandingOrder>,
+    /// The ids of those standing orders that haven't yet ended or
+    /// been cancelled.
+    active_standing_orders: Vec<u64>,
+    /// The id to give the next standing order made for this pool.
+    next_standing_order_id: u64,
+    /// All payment streams ever started from this pool, keyed by
+    /// their stream id.
+    streams: KeyValueStore<u64, Stream>,
+    /// The id to give the next stream started from this pool.
+    next_stream_id: u64,
+    /// Funds set aside for reserved Allowances, keyed by the local
+    /// id of the Allowance.
+    reservations: KeyValueStore<NonFungibleLocalId, Vault>,
+    /// The total amount held in `reservations`, per resource.
+    reserved_amounts: KeyValueStore<ResourceAddress, Decimal>,
+    /// Allowances in a priority tier cannot take a vault below the
+    /// floor set for that resource and tier here.
+    priority_floors: KeyValueStore<(ResourceAddress, u8), Decimal>,
+    /// The nflids that Allowances with stored ids may take, keyed by
+    /// the local id of the Allowance. Each nflid maps to whether it
+    /// has already been taken.
+    allowance_ids: KeyValueStore<NonFungibleLocalId, KeyValueStore<NonFungibleLocalId, bool>>,
+    /// If set, these badges can move the pool to a new owner.
+    guardians: Option<Guardians>,
+    /// The recovery the guardians have started, if any.
+    recovery: Option<Recovery>,
+    /// The Unix time of the owner's most recent call on this pool.
+    last_active: i64,
+    /// If set, who can claim the pool once its owner has been
+    /// inactive for long enough.
+    inheritance: Option<Inheritance>,
+    /// The inheritance shares claimed so far, by beneficiary and
+    /// resource.
+    inheritance_claimed: IndexSet<(NonFungibleGlobalId, ResourceAddress)>,
+    /// Savings locks on the owner's own use of the pool, keyed by the
+    /// resource they lock, or by `None` for a lock on the whole pool.
+    savings_locks: KeyValueStore<Option<ResourceAddress>, SavingsLock>,
+    /// The keys of all savings locks ever set on the pool.
+    savings_lock_keys: IndexSet<Option<ResourceAddress>>,
+    /// For each savings lock with a limit, and each resource it
+    /// covers, the Unix time the current period started and how
+    /// much has been taken in it.
+    savings_taken: KeyValueStore<(Option<ResourceAddress>, ResourceAddress), (i64, Decimal)>,
+    /// If set, large withdrawals by the owner must be requested and
+    /// then wait out a delay.
+    withdrawal_delay: Option<WithdrawalDelay>,
+    /// For each resource, the Unix time the current withdrawal delay
+    /// period started and how much the owner has taken out in it.
+    delay_taken: KeyValueStore<ResourceAddress, (i64, Decimal)>,
+    /// All withdrawal requests ever made for this pool, keyed by
+    /// their id.
+    pending_withdrawals: KeyValueStore<u64, PendingWithdrawal>,
+    /// The id to give the next withdrawal request for this pool.
+    next_withdrawal_id: u64,
 }
 
-#[blueprint]
-mod escrow {
-    use crate::AllowanceLifeCycle;
+impl Pool {
+    /// Asserts that a proof of the canceller badge of the pool's
+    /// withdrawal delay is in the auth zone, if the pool has one.
+    fn assert_canceller(&self) {
+        if let Some(delay) = &self.withdrawal_delay {
+            Runtime::assert_access_rule(rule!(require(delay.canceller.clone())));
+        }
+    }
 
-    /// An Escrow is just a bunch of pools, each pool tied to the
-    /// badge of its owner.
-    struct Escrow {
-        pools: KeyValueStore<NonFungibleGlobalId, Pool>,
+    /// Returns the withdrawal threshold for `resource` together with
+    /// when its current period started and how much the owner has
+    /// taken out in it, or `None` if the resource has no threshold.
+    fn withdrawal_period(&self, resource: &ResourceAddress) -> Option<(Decimal, i64, Decimal)> {
+        let delay = self.withdrawal_delay.as_ref()?;
+        let threshold = *delay.thresholds.get(resource)?;
+        let now = unix_time_now();
+        let (start, so_far) = self.delay_taken.get(resource)
+            .map(|taken| *taken)
+            .filter(|(start, _)| now < start + delay.period)
+            .unwrap_or((now, Decimal::ZERO));
+        Some((threshold, start, so_far))
     }
 
-    impl Escrow {
-        /// Note that the owner of an Escrow component has no
-        /// particular power over it or its users.
-        pub fn instantiate_escrow() -> Global<Escrow>
-        {
-            Self {
-                pools: KeyValueStore::new(),
-            }
-            .instantiate()
-                .prepare_to_globalize(OwnerRole::None)
-                .globalize()
+    /// Returns how much more of `resource` the owner can take out
+    /// right now without going through [request_withdraw], or `None`
+    /// if there's no limit to it.
+    ///
+    /// [request_withdraw]: crate::escrow::Escrow::request_withdraw
+    fn withdrawal_headroom(&self, resource: &ResourceAddress) -> Option<Decimal> {
+        self.withdrawal_period(resource)
+            .map(|(threshold, _, so_far)| std::cmp::max(threshold - so_far, Decimal::ZERO))
+    }
+
+    /// Counts `taken` of `resource` against the pool's withdrawal
+    /// threshold, panicking if the owner must request it instead.
+    fn take_under_withdrawal_delay(&mut self, resource: &ResourceAddress, taken: Decimal) {
+        if taken.is_zero() { return }
+        if let Some((threshold, start, so_far)) = self.withdrawal_period(resource) {
+            assert!(so_far + taken <= threshold,
+                    "{}", EscrowError::AboveWithdrawalThreshold);
+            self.delay_taken.insert(*resource, (start, so_far + taken));
         }
+    }
 
-        /// Anyone can deposit funds into any escrow pool.
-        ///
-        /// The `funds` are deposited into the pool owned by `owner`.
-        ///
-        /// If the depositor provides a proof `allowance_requestor`
-        /// showing that they are a trusted agent they will receive
-        /// back an Allowance to withdraw the amount that they
-        /// deposited in this call.
-        pub fn deposit_funds(&mut self,
-                             owner: NonFungibleGlobalId,
-                             funds: Bucket,
-                             allowance_requestor: Option<Proof>)
-                             -> Option<Bucket>
-        {
-            let owner_nfgid = NonFungibleGlobalId::new(
-                owner.resource_address(),
-                owner.local_id().clone());
+    /// Counts `taken` of `resource` against everything that limits
+    /// what the owner can take out of the pool, panicking if
+    /// something doesn't allow it.
+    fn take_as_owner(&mut self, resource: &ResourceAddress, taken: Decimal) {
+        self.take_under_savings_locks(resource, taken);
+        self.take_under_withdrawal_delay(resource, taken);
+    }
 
-            {
-                let pool = self.get_or_add_pool(&owner_nfgid);
+    /// Returns how much more of `resource` the owner can take out
+    /// right now, or `None` if there's no limit to it.
+    fn owner_headroom(&self, resource: &ResourceAddress) -> Option<Decimal> {
+        match (self.savings_headroom(resource), self.withdrawal_headroom(resource)) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
 
-                // Create this resource vault if we don't have it already.
-                if pool.vaults.get(&funds.resource_address()).is_none() {
-                    pool.vaults.insert(funds.resource_address(),
-                                       Vault::new(funds.resource_address()));
-                }
+    /// Takes `returned` of `resource` back off what has been counted
+    /// as taken out by the owner, for funds that were taken out but
+    /// then came straight back.
+    fn return_as_owner(&mut self, resource: &ResourceAddress, returned: Decimal) {
+        self.return_under_savings_locks(resource, returned);
+        if let Some((_, start, so_far)) = self.withdrawal_period(resource) {
+            self.delay_taken.insert(*resource,
+                                    (start, std::cmp::max(so_far - returned, Decimal::ZERO)));
+        }
+    }
+
+    /// Returns the savings locks that cover `resource` and still
+    /// hold, with the key each is stored under.
+    fn holding_savings_locks(&self, resource: &ResourceAddress)
+                             -> Vec<(Option<ResourceAddress>, SavingsLock)>
+    {
+        let now = Now::from_ledger();
+        [None, Some(*resource)].into_iter()
+            .filter_map(|key| self.savings_locks.get(&key).map(|lock| (key, lock.clone())))
+            .filter(|(_, lock)| lock.until.is_after(&now))
+            .collect()
+    }
+
+    /// Determines if any of the pool's savings locks still holds.
+    fn any_savings_lock_holds(&self) -> bool {
+        let now = Now::from_ledger();
+        self.savings_lock_keys.iter().any(|key|
+            self.savings_locks.get(key).map_or(false, |lock| lock.until.is_after(&now)))
+    }
+
+    /// Returns when the current period of the savings lock stored
+    /// under `key` started for `resource`, and how much of it has
+    /// been taken in that period.
+    fn savings_period(&self,
+                      key: Option<ResourceAddress>,
+                      resource: &ResourceAddress,
+                      limit: &SavingsLimit) -> (i64, Decimal)
+    {
+        let now = unix_time_now();
+        self.savings_taken.get(&(key, *resource))
+            .map(|taken| *taken)
+            .filter(|(start, _)| now < start + limit.period)
+            .unwrap_or((now, Decimal::ZERO))
+    }
+
+    /// Returns how much more of `resource` the savings locks that
+    /// cover it allow to be taken right now, or `None` if no lock
+    /// holds.
+    fn savings_headroom(&self, resource: &ResourceAddress) -> Option<Decimal> {
+        self.holding_savings_locks(resource).into_iter()
+            .map(|(key, lock)| match lock.limit {
+                None => Decimal::ZERO,
+                Some(limit) => {
+                    let (_, so_far) = self.savings_period(key, resource, &limit);
+                    std::cmp::max(limit.amount - so_far, Decimal::ZERO)
+                },
+            })
+            .min()
+    }
+
+    /// Counts `taken` of `resource` against the savings locks that
+    /// cover it, panicking if one of them doesn't allow it.
+    fn take_under_savings_locks(&mut self, resource: &ResourceAddress, taken: Decimal) {
+        if taken.is_zero() { return }
+        for (key, lock) in self.holding_savings_locks(resource) {
+            let limit = lock.limit.expect(EscrowError::SavingsLocked.as_str());
+            let (start, so_far) = self.savings_period(key, resource, &limit);
+            assert!(so_far + taken <= limit.amount,
+                    "{}", EscrowError::SavingsLimitExceeded);
+            self.savings_taken.insert((key, *resource), (start, so_far + taken));
+        }
+    }
+
+    /// Takes `returned` of `resource` back off what has been counted
+    /// against the savings locks that cover it, for funds that were
+    /// taken out but then came straight back.
+    fn return_under_savings_locks(&mut self, resource: &ResourceAddress, returned: Decimal) {
+        for (key, lock) in self.holding_savings_locks(resource) {
+            if let Some(limit) = lock.limit {
+                let (start, so_far) = self.savings_period(key, resource, &limit);
+                self.savings_taken.insert(
+                    (key, *resource),
+                    (start, std::cmp::max(so_far - returned, Decimal::ZERO)));
             }
+        }
+    }
+}
 
-            // Create allowance if requested and allowed
-            let maybe_allowance_bucket =
-                if allowance_requestor.is_none() { None } else
-            {
-                let requestor = allowance_requestor.unwrap().skip_checking();
+/// Describes where a payment out of a pool goes.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub enum PaymentRecipient {
+    /// Pay into the pool owned by this badge, on this Escrow
+    /// component.
+    EscrowPool(ComponentAddress, NonFungibleGlobalId),
+
+    /// Pay into this account. Note that if the account doesn't
+    /// accept deposits of the resource paid, standing order payments
+    /// to it are left outstanding and other payments fail.
+    Account(ComponentAddress),
+}
+
+/// A standing order pays a fixed amount out of its pool once every
+/// period, for as long as the pool can afford it.
+#[derive(ScryptoSbor)]
+struct StandingOrder {
+    /// The resource to pay out.
+    resource: ResourceAddress,
+    /// How much to pay each period. For a non-fungible resource this
+    /// is the number of arbitrary NFTs to pay.
+    amount: Decimal,
+    /// Where the payments go.
+    recipient: PaymentRecipient,
+    /// The number of seconds between payments.
+    period: i64,
+    /// The Unix time of the next payment that hasn't been made yet.
+    next_due: i64,
+    /// If set, no payments fall due after this Unix time.
+    end: Option<i64>,
+    /// The maximum number of missed payments that will be made up
+    /// for in one go. Payments missed beyond this are skipped.
+    max_catch_up: u64,
+}
+
+/// A payment stream continuously accrues funds for its recipient at
+/// a fixed rate per second, for as long as its funding lasts. The
+/// recipient can claim whatever has accrued at any time.
+#[derive(ScryptoSbor)]
+struct Stream {
+    /// The badge that can claim the accrued funds.
+    recipient: NonFungibleGlobalId,
+    /// How many tokens accrue per second.
+    rate: Decimal,
+    /// The Unix time up to which `accrued` has been calculated.
+    accruing_since: i64,
+    /// Funds that have accrued to the recipient but not yet been
+    /// claimed.
+    accrued: Decimal,
+    /// While paused, no funds accrue.
+    paused: bool,
+    /// False once the stream has been cancelled.
+    open: bool,
+    /// The funding for this stream, including the accrued funds.
+    vault: Vault,
+}
+
+impl Stream {
+    /// Brings `accrued` up to date with the Unix time `now`. Funds
+    /// stop accruing when the stream runs out of funding.
+    fn accrue(&mut self, now: i64) {
+        if !self.paused && self.open && now > self.accruing_since {
+            let earned = self.rate * Decimal::from(now - self.accruing_since);
+            self.accrued = std::cmp::min(self.accrued + earned, self.vault.amount());
+        }
+        self.accruing_since = now;
+    }
+
+    /// Takes the accrued funds out of the stream. Because the rate
+    /// can have more decimals than the resource can carry, we round
+    /// down and leave any remainder accrued.
+    fn take_accrued(&mut self) -> Bucket {
+        let funds = self.vault.take_advanced(self.accrued,
+                                             WithdrawStrategy::Rounded(RoundingMode::ToZero));
+        self.accrued -= funds.amount();
+        funds
+    }
+}
+
+/// The stages a Deal goes through. A Deal starts out `Open` and ends
+/// up in exactly one of the other states.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub enum DealState {
+    /// The funds are locked up, waiting for the payer to approve, the
+    /// arbiter to settle or the deadline to pass.
+    Open,
+
+    /// The payer approved the deal and the payee received all the
+    /// funds.
+    Released,
+
+    /// The deadline passed without the deal being closed and the
+    /// funds went back to the payer.
+    Refunded,
+
+    /// The arbiter split the funds between payee and payer. The
+    /// quantity shown is what went to the payee, the payer received
+    /// the rest.
+    Settled{to_payee: TokenQuantity},
+}
+
+/// A Deal receipt NFT describes a two-party deal where the payer has
+/// locked funds from their pool for the benefit of the payee. Anyone
+/// who knows its local id can look up the state of the deal.
+///
+/// Funds released to a party are deposited into that party's pool in
+/// the Escrow that holds the deal, from where they can withdraw them
+/// with their badge.
+#[derive(ScryptoSbor, NonFungibleData)]
+pub struct DealNfData {
+    /// The pool the funds were taken from, and which they will be
+    /// returned to on a refund.
+    pub payer: NonFungibleGlobalId,
+
+    /// The party the funds are intended for.
+    pub payee: NonFungibleGlobalId,
+
+    /// If set, the holder of this badge can settle the deal by
+    /// splitting the funds between the parties.
+    pub arbiter: Option<NonFungibleGlobalId>,
+
+    /// The resource locked in this deal.
+    pub resource: ResourceAddress,
+
+    /// The tokens locked in this deal.
+    pub quantity: TokenQuantity,
+
+    /// After this time anyone can return the funds to the payer, if
+    /// the deal is still open.
+    pub deadline: TimeBound,
+
+    /// The current state of the deal.
+    #[mutable]
+    pub state: DealState,
+}
+
+/// Funds locked up under a hash, waiting to be claimed by revealing
+/// the preimage of that hash, or reclaimed by their owner once the
+/// deadline has passed.
+#[derive(ScryptoSbor)]
+struct HashLock {
+    /// The pool the funds were taken from, and which they will be
+    /// returned to when reclaimed.
+    owner: NonFungibleGlobalId,
+    /// The funds are claimed by revealing the preimage of this
+    /// hash.
+    hash_lock: Hash,
+    /// Only the holder of this badge can claim the funds.
+    recipient: NonFungibleGlobalId,
+    /// The funds can be claimed up until this time, and reclaimed
+    /// after it.
+    deadline: TimeBound,
+    /// False once the funds have been claimed or reclaimed.
+    open: bool,
+    /// Contains the locked funds.
+    vault: Vault,
+}
+
+/// An Allowance held by the Escrow on behalf of whoever knows the
+/// secret whose hash it is stored under.
+#[derive(ScryptoSbor)]
+struct ClaimCode {
+    /// The pool this claim code was made for.
+    owner: NonFungibleGlobalId,
+    /// The claim code cannot be redeemed after this time.
+    expires: TimeBound,
+    /// False once the claim code has been redeemed or cancelled.
+    open: bool,
+    /// If true the redeemer receives the funds of the Allowance
+    /// rather than the Allowance itself.
+    pay_out_funds: bool,
+    /// Contains the Allowance NFT.
+    allowance: Vault,
+}
+
+/// The badges that can jointly recover a pool whose owner has lost
+/// their badge.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub struct Guardians {
+    /// The guardian badges.
+    pub badges: IndexSet<NonFungibleGlobalId>,
+    /// How many of the guardians must approve a recovery.
+    pub threshold: u64,
+    /// Once enough guardians have approved a recovery, the pool owner
+    /// has this many seconds to cancel it before it can complete.
+    /// This must be more than zero.
+    pub challenge_period: i64,
+}
+
+/// A recovery of a pool that its guardians have started.
+#[derive(ScryptoSbor, Clone, PartialEq, Eq, Debug)]
+pub struct Recovery {
+    /// Set once enough guardians have approved it, this is the badge
+    /// that is to become the new owner of the pool.
+    pub new_owner: Option<NonFungibleGlobalId>,
+    /// The guardians that have approved a recovery so far, each with
+    /// the new owner they approved.
+    pub approvals: IndexMap<NonFungibleGlobalId, NonFungibleGlobalId>,
+    /// Set once enough guardians have approved, this is the Unix
+    /// time from which the recovery can be completed.
+    pub completes_at: Option<i64>,
+}
+
+/// The beneficiaries that can claim a pool once its owner has gone
+/// quiet.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub struct Inheritance {
+    /// Each beneficiary's share of the pool. Shares are relative to
+    /// each other, so shares of 1 and 3 split the pool a quarter and
+    /// three quarters.
+    pub shares: IndexMap<NonFungibleGlobalId, Decimal>,
+    /// The beneficiaries can claim once the pool owner has been
+    /// inactive for this many seconds.
+    pub inactivity_period: i64,
+}
+
+impl Inheritance {
+    /// Determines if this inheritance is at least as strict as
+    /// `other`, that is if it has the same beneficiaries with the
+    /// same shares and they must wait at least as long.
+    fn is_as_strict_as(&self, other: &Inheritance) -> bool {
+        self.shares == other.shares
+            && self.inactivity_period >= other.inactivity_period
+    }
+}
+
+/// Locks a pool owner out of some or all of their own funds until a
+/// point in time, for when you want to put money aside and not be
+/// tempted to spend it.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub struct SavingsLock {
+    /// The lock holds until this time.
+    pub until: TimeBound,
+    /// While the lock holds the owner can take out this much per
+    /// period. If `None` they can't take out anything.
+    pub limit: Option<SavingsLimit>,
+}
+
+/// How much a pool owner can take out under a savings lock.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub struct SavingsLimit {
+    /// The most that can be taken out per period, of each resource
+    /// the lock covers. For non-fungibles this is a number of NFTs.
+    pub amount: Decimal,
+    /// The length of a period in seconds. A period starts with the
+    /// first withdrawal made after the previous one ended.
+    pub period: i64,
+}
+
+impl SavingsLock {
+    /// Determines if this lock is at least as strict as `other`, that
+    /// is if it lasts at least as long and allows no more to be
+    /// taken out.
+    fn is_as_strict_as(&self, other: &SavingsLock) -> bool {
+        let lasts = match (&self.until, &other.until) {
+            (TimeBound::UnixTime(a), TimeBound::UnixTime(b)) => a >= b,
+            (TimeBound::Epoch(a), TimeBound::Epoch(b)) => a >= b,
+            _ => false,
+        };
+        let limits = match (&self.limit, &other.limit) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(a), Some(b)) => a.amount <= b.amount && a.period >= b.period,
+        };
+        lasts && limits
+    }
+}
+
+/// Makes large withdrawals by a pool owner wait before they go
+/// through, so that a thief with the owner badge can be stopped.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub struct WithdrawalDelay {
+    /// The owner can take out no more than this amount of each
+    /// resource per period without requesting it. This counts
+    /// everything the owner takes out, not just withdrawals.
+    /// Resources not listed can be taken out at once.
+    pub thresholds: IndexMap<ResourceAddress, Decimal>,
+    /// The length of a period in seconds. A period starts with the
+    /// first withdrawal made after the previous one ended. Must be
+    /// more than zero.
+    pub period: i64,
+    /// How many seconds a request must wait before it can be
+    /// finalized. Must be more than zero.
+    pub delay: i64,
+    /// This badge can cancel withdrawal requests.
+    pub canceller: NonFungibleGlobalId,
+}
+
+/// A withdrawal the pool owner has requested.
+#[derive(ScryptoSbor)]
+struct PendingWithdrawal {
+    /// Where the funds go once the withdrawal is finalized.
+    recipient: PaymentRecipient,
+    /// The Unix time from which the withdrawal can be finalized.
+    finalizes_at: i64,
+    /// False once the withdrawal has been finalized or cancelled.
+    open: bool,
+    /// The funds being withdrawn.
+    vault: Vault,
+}
+
+/// Emitted when a pool owner requests a withdrawal.
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct WithdrawalRequestedEvent {
+    pub owner: NonFungibleGlobalId,
+    pub withdrawal_id: u64,
+    pub resource: ResourceAddress,
+    pub amount: Decimal,
+    pub finalizes_at: i64,
+}
+
+/// Emitted when a requested withdrawal is paid out.
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct WithdrawalFinalizedEvent {
+    pub owner: NonFungibleGlobalId,
+    pub withdrawal_id: u64,
+    pub recipient: PaymentRecipient,
+}
+
+/// Emitted when a requested withdrawal is cancelled and its funds
+/// returned to the pool.
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct WithdrawalCancelledEvent {
+    pub owner: NonFungibleGlobalId,
+    pub withdrawal_id: u64,
+    pub canceller: NonFungibleGlobalId,
+}
+
+/// Emitted when the funds of a hash lock are claimed. This reveals
+/// the preimage so that the counterparty can use it to complete their
+/// side of a swap on another ledger.
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct HashLockClaimedEvent {
+    pub lock_id: u64,
+    pub hash_lock: Hash,
+    pub preimage: Vec<u8>,
+}
+
+#[blueprint]
+#[events(HashLockClaimedEvent,
+         WithdrawalRequestedEvent,
+         WithdrawalFinalizedEvent,
+         WithdrawalCancelledEvent)]
+mod escrow {
+    use crate::AllowanceLifeCycle;
+
+    /// An Escrow is just a bunch of pools, each pool tied to the
+    /// badge of its owner.
+    struct Escrow {
+        pools: KeyValueStore<NonFungibleGlobalId, Pool>,
+        /// Pools that have been recovered to a new owner, keyed by
+        /// their old owner.
+        moved_pools: KeyValueStore<NonFungibleGlobalId, NonFungibleGlobalId>,
+        /// Our Deal receipts are of this non-fungible resource.
+        deal_receipt_res: ResourceAddress,
+        /// Holds the funds locked up in open Deals, keyed by the
+        /// local id of the Deal receipt.
+        deal_vaults: KeyValueStore<NonFungibleLocalId, Vault>,
+        /// All hash locks ever made, keyed by their lock id.
+        hash_locks: KeyValueStore<u64, HashLock>,
+        /// The lock id to give the next hash lock.
+        next_hash_lock_id: u64,
+        /// All claim codes ever made, keyed by the hash of their
+        /// secret. Closed ones are kept so that a hash can never be
+        /// reused.
+        claim_codes: KeyValueStore<Hash, ClaimCode>,
+    }
+
+    impl Escrow {
+        /// Note that the owner of an Escrow component has no
+        /// particular power over it or its users.
+        pub fn instantiate_escrow() -> Global<Escrow>
+        {
+            let (address_reservation, component_address) =
+                Runtime::allocate_component_address(Escrow::blueprint_id());
+
+            let deal_receipt_mgr =
+                ResourceBuilder::new_ruid_non_fungible::<DealNfData>(
+                    OwnerRole::None)
+                .metadata(metadata!(init {
+                    "name" => "Escrow deal receipt", locked; }))
+
+            // Receipts are minted and updated only by the Escrow
+            // component.
+                .mint_roles(mint_roles!(
+                    minter => rule!(require(global_caller(component_address)));
+                    minter_updater => rule!(deny_all);))
+                .non_fungible_data_update_roles(non_fungible_data_update_roles!(
+                    non_fungible_data_updater =>
+                        rule!(require(global_caller(component_address)));
+                    non_fungible_data_updater_updater => rule!(deny_all);
+                ))
+                .create_with_no_initial_supply();
+
+            Self {
+                pools: KeyValueStore::new(),
+                moved_pools: KeyValueStore::new(),
+                deal_receipt_res: deal_receipt_mgr.address(),
+                deal_vaults: KeyValueStore::new(),
+                hash_locks: KeyValueStore::new(),
+                next_hash_lock_id: 0,
+                claim_codes: KeyValueStore::new(),
+            }
+            .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .with_address(address_reservation)
+                .globalize()
+        }
+
+        /// Anyone can deposit funds into any escrow pool.
+        ///
+        /// The `funds` are deposited into the pool owned by `owner`.
+        ///
+        /// If the depositor provides a proof `allowance_requestor`
+        /// showing that they are a trusted agent they will receive
+        /// back an Allowance to withdraw the amount that they
+        /// deposited in this call.
+        pub fn deposit_funds(&mut self,
+                             owner: NonFungibleGlobalId,
+                             funds: Bucket,
+                             allowance_requestor: Option<Proof>)
+                             -> Option<Bucket>
+        {
+            // Deposits for a pool that has since been recovered go
+            // to its new owner
+            let owner = self.pool_owner(&owner);
+            let owner_nfgid = NonFungibleGlobalId::new(
+                owner.resource_address(),
+                owner.local_id().clone());
+
+            // Create allowance if requested and allowed
+            let maybe_allowance_bucket =
+                if allowance_requestor.is_none() { None } else
+            {
+                let requestor = allowance_requestor.unwrap().skip_checking();
                 assert!(self.is_resource_trusted(owner.clone(),
                                                  requestor.resource_address())
                         || self.is_nfgid_trusted(
@@ -417,7 +1856,7 @@ mod escrow {
                             NonFungibleGlobalId::new(
                                 requestor.resource_address(),
                                 requestor.as_non_fungible().non_fungible_local_id())),
-                        "2013 only trusted can request allowance");
+                        "{}", EscrowError::NotTrusted);
                 let max_amount =
                     if funds.resource_address().is_fungible() {
                         TokenQuantity::Fungible(funds.amount())
@@ -432,15 +1871,16 @@ mod escrow {
                     (Runtime::global_address(), owner),
                     pool_mgr,
                     None,
-                    0,
+                    TimeBound::UnixTime(0),
                     AllowanceLifeCycle::Accumulating,
                     funds.resource_address(),
-                    Some(max_amount)))
+                    Some(max_amount),
+                    None,
+                    AllowanceOptions::default()))
             };
             
             // Pool the funds
-            let mut pool = self.get_or_add_pool(&owner_nfgid);
-            pool.vaults.get_mut(&funds.resource_address()).unwrap().put(funds);
+            self.put_in_pool(&owner_nfgid, funds);
 
             maybe_allowance_bucket
         }
@@ -448,6 +1888,11 @@ mod escrow {
         /// Returns the amount of tokens available for the named
         /// resource in the named pool. If the pool doesn't exist or
         /// doesn't have that resource we return zero.
+        ///
+        /// Funds that are reserved for Allowances are not included,
+        /// use [read_reserved_funds] for those.
+        ///
+        /// [read_reserved_funds]: crate::escrow::Escrow::read_reserved_funds
         pub fn read_funds(&self,
                           owner: NonFungibleGlobalId,
                           resource: ResourceAddress) -> Decimal
@@ -462,40 +1907,209 @@ mod escrow {
             Decimal::ZERO
         }
 
+        /// Returns the amount of tokens of the named resource that
+        /// are reserved for Allowances in the named pool. These funds
+        /// are not included in [read_funds].
+        ///
+        /// [read_funds]: crate::escrow::Escrow::read_funds
+        pub fn read_reserved_funds(&self,
+                                   owner: NonFungibleGlobalId,
+                                   resource: ResourceAddress) -> Decimal
+        {
+            if let Some(pool) = self.pools.get(&owner) {
+                if let Some(amount) = pool.reserved_amounts.get(&resource) {
+                    return *amount
+                }
+            }
+            Decimal::ZERO
+        }
+
+        /// The pool owner can set a floor for a priority tier of
+        /// Allowances. Allowances in that tier then cannot take the
+        /// pool's `resource` vault below `floor`, leaving those funds
+        /// for Allowances in other tiers. Set it to zero to remove
+        /// the floor. This doesn't affect reserved Allowances, nor
+        /// the pool owner's own withdrawals.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn set_priority_floor(&mut self,
+                                  owner: Proof,
+                                  resource: ResourceAddress,
+                                  priority: u8,
+                                  floor: Decimal)
+        {
+            assert!(!floor.is_negative(), "{}", EscrowError::NegativeFloor);
+            let owner = self.authenticate_owner(owner);
+            let pool = self.get_or_add_pool(&owner);
+            pool.priority_floors.insert((resource, priority), floor);
+        }
+
+        /// Returns the floor set for a priority tier on a pool's
+        /// `resource` vault, or zero if no floor is set.
+        pub fn read_priority_floor(&self,
+                                   owner: NonFungibleGlobalId,
+                                   resource: ResourceAddress,
+                                   priority: u8) -> Decimal
+        {
+            if let Some(pool) = self.pools.get(&owner) {
+                if let Some(floor) = pool.priority_floors.get(&(resource, priority)) {
+                    return *floor
+                }
+            }
+            Decimal::ZERO
+        }
+
+        /// Returns the current status of `allowance`, telling whether
+        /// it can be used right now and if not then why.
+        pub fn allowance_status(&self,
+                                allowance: NonFungibleGlobalId) -> AllowanceStatus
+        {
+            let nfdata: AllowanceNfData =
+                ResourceManager::from(allowance.resource_address())
+                .get_non_fungible_data(allowance.local_id());
+            nfdata.status(&Now::from_ledger())
+        }
+
+        /// The owner of a pool can revoke an Allowance issued for
+        /// it. A revoked Allowance can no longer be used, though its
+        /// holder keeps the NFT. Any funds reserved for it are
+        /// returned to the pool.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn revoke_allowance(&mut self,
+                                owner: Proof,
+                                allowance: NonFungibleGlobalId)
+        {
+            let owner = self.authenticate_owner(owner);
+            let allowance_res =
+                self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str())
+                    .allowance_badge_res;
+            assert!(allowance.resource_address() == allowance_res,
+                    "{}", EscrowError::AllowanceNotForPool);
+            ResourceManager::from(allowance_res)
+                .update_non_fungible_data(allowance.local_id(), "revoked", true);
+            self.release_reserved_funds(&owner, allowance.local_id());
+        }
+
+        /// The pool owner can add more nflids to an Allowance that
+        /// was minted with stored ids. Use this to build up sets that
+        /// are too large to pass in a single transaction.
+        ///
+        /// Any reservation the Allowance has does not grow, so
+        /// the added nflids are taken from the pool's free funds.
+        pub fn add_allowance_ids(&mut self,
+                                 owner: Proof,
+                                 allowance: NonFungibleGlobalId,
+                                 nflids: IndexSet<NonFungibleLocalId>)
+        {
+            let owner = self.authenticate_owner(owner);
+            let mut pool = self.pools.get_mut(&owner).expect(EscrowError::PoolNotFound.as_str());
+            assert!(allowance.resource_address() == pool.allowance_badge_res,
+                    "{}", EscrowError::AllowanceNotForPool);
+            let allowance_mgr = ResourceManager::from(pool.allowance_badge_res);
+            let nfdata: AllowanceNfData =
+                allowance_mgr.get_non_fungible_data(allowance.local_id());
+            let mut stored_ids = nfdata.stored_ids.expect(EscrowError::NoStoredIds.as_str());
+
+            let ids = pool.allowance_ids.get_mut(allowance.local_id()).unwrap();
+            for nflid in nflids {
+                if ids.get(&nflid).is_none() {
+                    ids.insert(nflid, false);
+                    stored_ids.permitted += 1;
+                }
+            }
+            allowance_mgr.update_non_fungible_data(allowance.local_id(),
+                                                   "stored_ids",
+                                                   Some(stored_ids));
+        }
+
+        /// Anyone can ask what would happen if `allowance` were used
+        /// right now to withdraw `quantity` from its pool. This
+        /// changes nothing, it just reports back how much the
+        /// Allowance could take, whether the withdrawal would burn
+        /// it, when it can next be used, and if the withdrawal would
+        /// fail then why.
+        ///
+        /// The checks made are the same as those made by
+        /// [withdraw_with_allowance].
+        ///
+        /// [withdraw_with_allowance]: crate::escrow::Escrow::withdraw_with_allowance
+        pub fn quote_allowance(&self,
+                               allowance: NonFungibleGlobalId,
+                               quantity: TokenQuantity) -> AllowanceQuote
+        {
+            let nfdata: AllowanceNfData =
+                ResourceManager::from(allowance.resource_address())
+                .get_non_fungible_data(allowance.local_id());
+            let now = Now::from_ledger();
+
+            let status = nfdata.status(&now);
+
+            let (quantity, short) = self.resolve_for_allowance(&allowance, &nfdata, &quantity)
+                .unwrap_or((quantity, 0));
+            let (pool_available, pool_failure) =
+                self.funds_for_allowance(&allowance, &nfdata, &quantity);
+            let pool_failure =
+                if short > 0 { Some(EscrowError::FundsNotAvailable) } else { pool_failure };
+            let per_use_failure = nfdata.check_per_use(&quantity).err();
+            let stored = self.free_stored_ids(allowance.local_id(), &nfdata, &quantity);
+            let quantity = quantity.without_nflids(&stored);
+            let failure = nfdata.check_use(&quantity, &now).err().or(per_use_failure)
+                .or(pool_failure)
+                .map(|error| error.to_string());
+            let pool_available =
+                std::cmp::min(pool_available, nfdata.max_per_use.unwrap_or(Decimal::MAX));
+
+            let (max_available, next_use) = match &status {
+                AllowanceStatus::Usable{remaining: Some(remaining)} =>
+                    (std::cmp::min(remaining.to_amount()
+                                   .checked_add(Decimal::from(nfdata.stored_ids_left()))
+                                   .unwrap_or(Decimal::MAX),
+                                   pool_available),
+                     Some(TimeBound::UnixTime(now.unix_time))),
+                AllowanceStatus::Usable{remaining: None} =>
+                    (pool_available, Some(TimeBound::UnixTime(now.unix_time))),
+                AllowanceStatus::NotYetValid =>
+                    (Decimal::ZERO, Some(nfdata.valid_from)),
+                AllowanceStatus::CoolingDown{until} =>
+                    (Decimal::ZERO, Some(TimeBound::UnixTime(*until))),
+                AllowanceStatus::Expired
+                    | AllowanceStatus::Exhausted
+                    | AllowanceStatus::Revoked =>
+                    (Decimal::ZERO, None),
+            };
+
+            AllowanceQuote {
+                max_available,
+                status,
+                would_burn: failure.is_none()
+                    && nfdata.after_use(&quantity, stored.len() as u64, false, &now).burn,
+                next_use,
+                failure,
+            }
+        }
+
         /// The pool owner can use this function to withdraw funds
         /// from their pool. `caller` must be a proof of the pool
         /// owner.
         ///
         /// If the requested tokens aren't available we will panic.
+        /// We also panic if the withdrawal would take the owner above
+        /// the pool's withdrawal threshold for the resource, in which
+        /// case it must be made with [request_withdraw] instead.
+        ///
+        /// [request_withdraw]: crate::escrow::Escrow::request_withdraw
         pub fn withdraw(&mut self,
                         caller: Proof,
                         resource: ResourceAddress,
                         quantity: TokenQuantity) -> Bucket
         {
-            let (take_nflids, take_amount) = quantity.extract_max_values();
+            let owner = self.authenticate_owner(caller);
             self.operate_on_vault(
-                &unchecked_proof_to_nfgid(caller),
+                &owner,
                 &resource,
                 None,
-                |mut v| {
-                    let mut bucket: Option<Bucket> = None;
-
-                    // First take the named nflids: if we do this the
-                    // other way around they may no longer be
-                    // available when we try.
-                    if let Some(nflids) = &take_nflids {
-                        bucket = Some(v.as_non_fungible().take_non_fungibles(&nflids).into());
-                    }
-                    // Then take the necessary amount of arbitrary tokens.
-                    if let Some(amount) = take_amount {
-                        if let Some(ref mut bucket) = bucket {
-                            bucket.put(v.take(amount));
-                        } else {
-                            bucket = Some(v.take(amount));
-                        }
-                    }
-                    bucket
-                })
+                |mut v| Some(quantity.take_from_vault(&mut v)))
                 .unwrap()
         }
 
@@ -507,45 +2121,111 @@ mod escrow {
         /// burn it if it's now spent. For this reason it may or may
         /// not be returned back to the caller, and its authorization
         /// details may have changed if it *is* returned.
+        ///
+        /// If the Allowance has a co-signer and `quantity` is above
+        /// its threshold, a proof of the approver badge must be in
+        /// the auth zone.
         pub fn withdraw_with_allowance(&mut self,
                                        allowance: Bucket,
                                        quantity: TokenQuantity)
                                        -> (Bucket, Option<Bucket>)
         {
-            let allowance_resaddr = allowance.resource_address();
-            let (owner_nfgid, withdraw_resource, allowance) =
-                self.use_allowance(allowance, quantity.clone());
+            let allowance_nfgid = NonFungibleGlobalId::new(
+                allowance.resource_address(),
+                allowance.as_non_fungible().non_fungible_local_id());
+
+            // Pick out the NFTs to take now, so that the Allowance is
+            // checked against exactly those.
+            let nfdata: AllowanceNfData = ResourceManager::from(allowance.resource_address())
+                .get_non_fungible_data(allowance_nfgid.local_id());
+            let (quantity, short) =
+                self.resolve_for_allowance(&allowance_nfgid, &nfdata, &quantity)
+                .unwrap_or_else(|error| panic!("{}", error));
+            assert!(short == 0, "{}", EscrowError::FundsNotAvailable);
+
+            let (nfdata, allowance) =
+                self.use_allowance(allowance, quantity.clone(), false);
+            let owner_nfgid = self.pool_owner(&nfdata.escrow_pool.1);
 
             // Note the allowance NFT may have been burned by this
             // point
 
-            let (take_nflids, take_amount) = quantity.extract_max_values();
-            
-            (self.operate_on_vault(
+            let funds = self.operate_on_vault(
                 &owner_nfgid,
-                &withdraw_resource,
-                Some(allowance_resaddr),
-                |mut v| {
-                    let mut bucket: Option<Bucket> = None;
-
-                    // First take the named nflids: if we do this the
-                    // other way around they may no longer be
-                    // available when we try.
-                    if let Some(nflids) = &take_nflids {
-                        bucket = Some(v.as_non_fungible().take_non_fungibles(&nflids).into());
-                    }
-                    // Then take the necessary amount of arbitrary tokens.
-                    if let Some(amount) = take_amount {
-                        if let Some(ref mut bucket) = bucket {
-                            bucket.put(v.take(amount));
-                        } else {
-                            bucket = Some(v.take(amount));
-                        }
+                &nfdata.for_resource,
+                Some((&allowance_nfgid, &nfdata)),
+                |mut v| Some(quantity.take_from_vault(&mut v)))
+                .unwrap();
+
+            if allowance.is_none() {
+                self.release_reserved_funds(&owner_nfgid, allowance_nfgid.local_id());
+            }
+
+            (funds, allowance)
+        }
+
+        /// Like [withdraw_with_allowance] except that instead of
+        /// panicking when the pool doesn't hold the full `quantity`
+        /// we take as much of it as we can. That is, we take no more
+        /// than the Allowance permits, including its `max_per_use`
+        /// and `max_percentage`, and no more than the pool holds
+        /// above the Allowance's priority floor. The Allowance
+        /// is only debited for what was actually taken, and if
+        /// nothing could be taken it isn't used at all. Nor is it if
+        /// what could be taken falls short of its `min_per_use`.
+        ///
+        /// Returns the funds taken, the quantity that was taken, the
+        /// non-fungible local ids that were asked for but couldn't be
+        /// taken, and the Allowance if it hasn't been burnt.
+        ///
+        /// We still panic if the Allowance cannot currently be used,
+        /// e.g. because it has expired.
+        ///
+        /// [withdraw_with_allowance]: crate::escrow::Escrow::withdraw_with_allowance
+        pub fn withdraw_with_allowance_up_to(&mut self,
+                                             allowance: Bucket,
+                                             quantity: TokenQuantity)
+                                             -> (Bucket,
+                                                 TokenQuantity,
+                                                 IndexSet<NonFungibleLocalId>,
+                                                 Option<Bucket>)
+        {
+            let allowance_nfgid = NonFungibleGlobalId::new(
+                allowance.resource_address(),
+                allowance.as_non_fungible().non_fungible_local_id());
+            let nfdata: AllowanceNfData = ResourceManager::from(allowance.resource_address())
+                .get_non_fungible_data(allowance_nfgid.local_id());
+
+            let (achieved, missing) =
+                self.fit_to_allowance(&allowance_nfgid, &nfdata, &quantity);
+            let (achieved, missing) =
+                if matches!(nfdata.check_per_use(&achieved), Err(EscrowError::BelowMinPerUse)) {
+                    // Too little for a single use, so we take nothing
+                    match achieved {
+                        TokenQuantity::NonFungible(taken, amount) => {
+                            let mut missing = missing;
+                            missing.extend(taken.unwrap_or_default());
+                            (TokenQuantity::NonFungible(None, amount.map(|_| 0)), missing)
+                        },
+                        _ => (TokenQuantity::Fungible(Decimal::ZERO), missing),
                     }
-                    bucket
-                })
-             .unwrap(),
-             allowance)
+                } else {
+                    (achieved, missing)
+                };
+            // Stored ids are paid for by the store, not by max_amount
+            let stored = self.free_stored_ids(allowance_nfgid.local_id(), &nfdata, &achieved);
+            if let Err(error) = nfdata.check_use(&achieved.without_nflids(&stored),
+                                                 &Now::from_ledger()) {
+                panic!("{}", error);
+            }
+            if achieved.is_zero() {
+                return (Bucket::new(nfdata.for_resource), achieved, missing, Some(allowance))
+            }
+
+            let (funds, allowance) =
+                self.withdraw_with_allowance(allowance, achieved.clone());
+
+            (funds, achieved, missing, allowance)
         }
 
         /// The owner of a pool can call this convenience function to
@@ -561,7 +2241,8 @@ mod escrow {
                                resource: ResourceAddress)
                                -> Bucket
         {
-            self.operate_on_vault(&unchecked_proof_to_nfgid(caller),
+            let owner = self.authenticate_owner(caller);
+            self.operate_on_vault(&owner,
                                   &resource,
                                   None,
                                   |mut v| Some(v.take_all()))
@@ -576,7 +2257,8 @@ mod escrow {
                          caller: Proof,
                          amount: Decimal)
         {
-            self.operate_on_vault(&unchecked_proof_to_nfgid(caller),
+            let owner = self.authenticate_owner(caller);
+            self.operate_on_vault(&owner,
                                   &XRD,
                                   None,
                                   |v| {v.as_fungible().lock_fee(amount); None});
@@ -590,25 +2272,35 @@ mod escrow {
         ///
         /// Note that the Allowance may get burned during execution
         /// and if so will not be returned to the caller.
+        ///
+        /// This counts as a use of the Allowance, so `amount` must be
+        /// within its per-use limits.
         pub fn subsidize_with_allowance(&mut self,
                          allowance: Bucket,
                          amount: Decimal) -> Option<Bucket>
         {
-            let allowance_resaddr = allowance.resource_address();
-            let (owner_nfgid, withdraw_resource, allowance) =
-                self.use_allowance(allowance, TokenQuantity::Fungible(amount));
+            let allowance_nfgid = NonFungibleGlobalId::new(
+                allowance.resource_address(),
+                allowance.as_non_fungible().non_fungible_local_id());
+            let (nfdata, allowance) =
+                self.use_allowance(allowance, TokenQuantity::Fungible(amount), true);
+            let owner_nfgid = self.pool_owner(&nfdata.escrow_pool.1);
 
             // Note the allowance NFT may have been burned by this
             // point
 
-            assert_eq!(XRD, withdraw_resource,
-                       "only XRD can by used for subsidy");
+            assert!(XRD == nfdata.for_resource,
+                    "{}", EscrowError::SubsidyNotXrd);
                 
             self.operate_on_vault(&owner_nfgid,
                                   &XRD,
-                                  Some(allowance_resaddr),
+                                  Some((&allowance_nfgid, &nfdata)),
                                   |v| {v.as_fungible().lock_fee(amount); None});
 
+            if allowance.is_none() {
+                self.release_reserved_funds(&owner_nfgid, allowance_nfgid.local_id());
+            }
+
             allowance
         }
 
@@ -620,8 +2312,9 @@ mod escrow {
                                     caller: Proof,
                                     amount: Decimal)
         {
+            let owner = self.authenticate_owner(caller);
             self.operate_on_vault(
-                &unchecked_proof_to_nfgid(caller),
+                &owner,
                 &XRD,
                 None,
                 |v| {v.as_fungible().lock_contingent_fee(amount); None});
@@ -637,89 +2330,262 @@ mod escrow {
         /// the doc for the AllowanceNfData struct for details), and
         /// the newly created allowance will be returned out of this
         /// function.
+        ///
+        /// Use `options` for any further settings, see the doc for
+        /// the AllowanceOptions struct. If you're reserving funds for
+        /// the allowance they are taken out of the pool now, and so
+        /// must be available. If you're putting the allowance under
+        /// a claim code then the Escrow holds on to it, and an empty
+        /// bucket is returned instead.
+        ///
+        /// While a savings lock on `for_resource` holds, only
+        /// Allowances with reserved funds can be minted. If the pool
+        /// has a withdrawal delay, a proof of its canceller badge
+        /// must be in the auth zone.
         pub fn mint_allowance(&mut self,
                               owner: Proof,
-                              valid_until: Option<i64>,
-                              valid_from: i64,
+                              valid_until: Option<TimeBound>,
+                              valid_from: TimeBound,
                               life_cycle: AllowanceLifeCycle,
                               for_resource: ResourceAddress,
-                              max_quantity: Option<TokenQuantity>) -> Bucket
+                              max_quantity: Option<TokenQuantity>,
+                              options: AllowanceOptions) -> Bucket
         {
-            if let Some(max_quantity) = &max_quantity {
-                let amount;
-                match max_quantity {
-                    TokenQuantity::NonFungible(_, max_amount) => {
-                        amount = max_amount.map(|v|Decimal::from(v))
-                    },
-                    TokenQuantity::Fungible(max_amount) => {
-                        amount = Some(*max_amount)
-                    },
-                }
-                assert!(!amount.unwrap_or_default().is_negative(),
-                        "max_amount cannot be negative");
+            let claim_code = options.claim_code.clone();
+            if let Some(terms) = &claim_code {
+                assert!(self.claim_codes.get(&terms.code_hash).is_none(),
+                        "{}", EscrowError::ClaimCodeInUse);
+                assert!(!terms.expires.is_before(&Now::from_ledger()),
+                        "{}", EscrowError::ClaimCodeExpired);
+                assert!(!terms.pay_out_funds || max_quantity.is_some(),
+                        "{}", EscrowError::FundsClaimCodeWithoutMax);
             }
 
             // Access control is effectively enforced through our pool
             // lookup further down.
-            let owner = unchecked_proof_to_nfgid(owner);
+            let owner = self.authenticate_owner(owner);
+            if let Some(pool) = self.pools.get(&owner) {
+                pool.assert_canceller();
+                assert!(options.reserved || pool.holding_savings_locks(&for_resource).is_empty(),
+                        "{}", EscrowError::SavingsLockHolds);
+            }
 
-            let pool_mgr = ResourceManager::from(
-                self.get_or_add_pool(&owner).allowance_badge_res);
+            let allowance = self.mint_allowance_for_pool(&owner,
+                                                         valid_until,
+                                                         valid_from,
+                                                         life_cycle,
+                                                         for_resource,
+                                                         max_quantity,
+                                                         options);
 
-            self.create_allowance(
-                (Runtime::global_address(), owner),
-                pool_mgr,
-                valid_until,
-                valid_from,
-                life_cycle,
-                for_resource,
-                max_quantity)
+            if let Some(terms) = claim_code {
+                let allowance_res = allowance.resource_address();
+                self.claim_codes.insert(
+                    terms.code_hash,
+                    ClaimCode {
+                        owner,
+                        expires: terms.expires,
+                        open: true,
+                        pay_out_funds: terms.pay_out_funds,
+                        allowance: Vault::with_bucket(allowance),
+                    });
+                Bucket::new(allowance_res)
+            } else {
+                allowance
+            }
         }
 
-        /// Anyone who holds an `allowance` NFT can voluntarily reduce
-        /// its max amount by calling this function. Just provide a
-        /// proof that you control the allowance and you're good.
+        /// Returns the funds reserved for an Allowance to the pool,
+        /// once that Allowance can no longer use them. Anyone can
+        /// call this, and it succeeds if the Allowance has expired,
+        /// been revoked or no longer exists.
         ///
-        /// Make sure that `new_max` is lower than (or equal to) the
-        /// `max_amount` currently in the allowance or this function
-        /// will panic. Also don't send in a negative number.
+        /// Reserved funds are released automatically when the
+        /// Allowance gets burnt by this component. If the Allowance
+        /// is burnt by someone else then this function must be
+        /// called to release them. A pool owner who has recalled the
+        /// Allowance can instead release its reservation without
+        /// burning it, using [release_recalled_reservation].
         ///
-        /// On a NonFungible type allowance this only reduces the
-        /// numerical amount, never the set of nflids available if
-        /// any.
-        pub fn reduce_allowance_to_amount(&self,
-                                          allowance: Proof,
-                                          new_max: Decimal)
+        /// [release_recalled_reservation]: crate::escrow::Escrow::release_recalled_reservation
+        pub fn release_reservation(&mut self,
+                                   owner: NonFungibleGlobalId,
+                                   allowance_id: NonFungibleLocalId)
         {
-            assert!(!new_max.is_negative(),
-                    "2003 allowance can't be negative");
-            
-            // Access control is effectively achieved through the
-            // use of the proof's resource address and nflid later.
-            let allowance = allowance.skip_checking();
-
-            let nfdata: AllowanceNfData =
-                ResourceManager::from(allowance.resource_address())
-                .get_non_fungible_data(&allowance.as_non_fungible().non_fungible_local_id());
+            let owner = self.pool_owner(&owner);
+            let allowance_mgr = ResourceManager::from(
+                self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str())
+                    .allowance_badge_res);
+            if allowance_mgr.non_fungible_exists(&allowance_id) {
+                let nfdata: AllowanceNfData =
+                    allowance_mgr.get_non_fungible_data(&allowance_id);
+                assert!(matches!(nfdata.status(&Now::from_ledger()),
+                                 AllowanceStatus::Expired
+                                 | AllowanceStatus::Exhausted
+                                 | AllowanceStatus::Revoked),
+                        "{}", EscrowError::ReservationInUse);
+            }
+            self.release_reserved_funds(&owner, &allowance_id);
+        }
 
-            let new_token_quantity: TokenQuantity;
+        /// The owner of a pool who has recalled one of its
+        /// Allowances can return the funds reserved for it to the
+        /// pool, whether or not it is still valid. The Allowance is
+        /// left as it is, but with its reservation gone it can no
+        /// longer take any funds.
+        ///
+        /// The `owner` must be a proof of the pool owner, and the
+        /// `allowance` a proof of the recalled Allowance.
+        pub fn release_recalled_reservation(&mut self,
+                                            owner: Proof,
+                                            allowance: Proof)
+        {
+            let owner = self.authenticate_owner(owner);
+            // Access control is effectively achieved by checking the
+            // proof's resource address against the owner's pool.
+            let allowance = allowance.skip_checking();
+            let allowance_res =
+                self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str())
+                    .allowance_badge_res;
+            assert!(allowance.resource_address() == allowance_res,
+                    "{}", EscrowError::AllowanceNotForPool);
+            self.release_reserved_funds(&owner,
+                                        &allowance.as_non_fungible().non_fungible_local_id());
+        }
+
+        /// Anyone who knows the `secret` of a claim code can redeem
+        /// it, receiving either the Allowance NFT or the funds it
+        /// represents. Each claim code can only be redeemed once.
+        /// Claim codes are made by passing a `claim_code` option to
+        /// [mint_allowance].
+        ///
+        /// Note that the secret becomes public as part of the
+        /// redeeming transaction, so it's no longer a secret after
+        /// this.
+        ///
+        /// [mint_allowance]: crate::escrow::Escrow::mint_allowance
+        pub fn redeem_claim_code(&mut self,
+                                 secret: Vec<u8>) -> Bucket
+        {
+            let code_hash = hash(&secret);
+            let (pay_out_funds, allowance) = {
+                let mut code = self.claim_codes.get_mut(&code_hash)
+                    .expect(EscrowError::ClaimCodeNotFound.as_str());
+                assert!(code.open, "{}", EscrowError::ClaimCodeClosed);
+                assert!(!code.expires.is_before(&Now::from_ledger()),
+                        "{}", EscrowError::ClaimCodeExpired);
+                code.open = false;
+                (code.pay_out_funds, code.allowance.take_all())
+            };
+            if !pay_out_funds {
+                return allowance;
+            }
+
+            let allowance_id = allowance.as_non_fungible().non_fungible_local_id();
+            let nfdata: AllowanceNfData = ResourceManager::from(allowance.resource_address())
+                .get_non_fungible_data(&allowance_id);
+            let (funds, allowance) =
+                self.withdraw_with_allowance(allowance, nfdata.max_amount.unwrap());
+
+            // The Allowance was only ever a vehicle for the funds.
+            if let Some(allowance) = allowance {
+                allowance.burn();
+                let owner = self.pool_owner(&nfdata.escrow_pool.1);
+                self.release_reserved_funds(&owner, &allowance_id);
+            }
+
+            funds
+        }
+
+        /// The owner of a pool can cancel a claim code that hasn't
+        /// been redeemed yet. Its Allowance is burnt.
+        ///
+        /// The `owner` must be a proof of the current owner of the
+        /// pool the claim code was made from, which is not the one
+        /// who made it if the pool has since been recovered.
+        pub fn cancel_claim_code(&mut self,
+                                 owner: Proof,
+                                 code_hash: Hash)
+        {
+            let owner = self.authenticate_owner(owner);
+            let (open, code_owner) = {
+                let code = self.claim_codes.get(&code_hash)
+                    .expect(EscrowError::ClaimCodeNotFound.as_str());
+                (code.open, code.owner.clone())
+            };
+            assert!(open, "{}", EscrowError::ClaimCodeClosed);
+            assert!(self.pool_owner(&code_owner) == owner,
+                    "{}", EscrowError::NotClaimCodeOwner);
+            let mut code = self.claim_codes.get_mut(&code_hash).unwrap();
+            code.open = false;
+
+            let allowance = code.allowance.take_all();
+            drop(code);
+
+            let allowance_id = allowance.as_non_fungible().non_fungible_local_id();
+            allowance.burn();
+            self.release_reserved_funds(&owner, &allowance_id);
+        }
+
+        /// Anyone who holds an `allowance` NFT can voluntarily reduce
+        /// its max amount by calling this function. Just provide a
+        /// proof that you control the allowance and you're good.
+        ///
+        /// Make sure that `new_max` is lower than (or equal to) the
+        /// `max_amount` currently in the allowance or this function
+        /// will panic. Also don't send in a negative number.
+        ///
+        /// On a NonFungible type allowance this only reduces the
+        /// numerical amount, never the set of nflids available if
+        /// any.
+        pub fn reduce_allowance_to_amount(&self,
+                                          allowance: Proof,
+                                          new_max: Decimal)
+        {
+            assert!(!new_max.is_negative(),
+                    "{}", EscrowError::NegativeAllowance);
+            
+            // Access control is effectively achieved through the
+            // use of the proof's resource address and nflid later.
+            let allowance = allowance.skip_checking();
+
+            let nfdata: AllowanceNfData =
+                ResourceManager::from(allowance.resource_address())
+                .get_non_fungible_data(&allowance.as_non_fungible().non_fungible_local_id());
+
+            let new_token_quantity: TokenQuantity;
 
             if let Some(max_amount) = nfdata.max_amount {
                 match max_amount {
                     TokenQuantity::Fungible(amount) => {
-                        assert!(amount >= new_max, "2000 allowance increase not allowed");
+                        assert!(amount >= new_max, "{}", EscrowError::AllowanceIncreaseFungible);
+                        new_token_quantity = TokenQuantity::Fungible(new_max);
+                    },
+                    TokenQuantity::All => {
+                        // This is the same as having no limit
                         new_token_quantity = TokenQuantity::Fungible(new_max);
                     },
-                    TokenQuantity::NonFungible(nflids, amount) => {
+                    TokenQuantity::NonFungibleExcept(excluded, amount) => {
+                        // Without a number there was no limit so any
+                        // number is a reduction
+                        assert!(amount.map_or(true, |amount| new_max <= Decimal::from(amount)),
+                                "{}", EscrowError::AllowanceIncreaseNonFungible);
+                        new_token_quantity = TokenQuantity::NonFungibleExcept(
+                            excluded,
+                            Some(u64::try_from(new_max).expect(
+                                EscrowError::NewMaxNotWhole.as_str())));
+                    },
+                    max_amount => {
+                        let (nflids, amount) = max_amount.extract_max_values();
                         if let Some(amount) = amount {
-                            assert!(new_max < amount.into(),
-                                    "2001 allowance increase not allowed");
-                            new_token_quantity = TokenQuantity::NonFungible(
+                            assert!(new_max < amount,
+                                    "{}", EscrowError::AllowanceIncreaseNonFungible);
+                            new_token_quantity = TokenQuantity::non_fungible(
                                 nflids,
                                 Some(u64::try_from(new_max).expect(
-                                    "2004 new_max must be a whole number")));
+                                    EscrowError::NewMaxNotWhole.as_str())));
                         } else {
-                            panic!("2002 allowance increase not allowed");
+                            panic!("{}", EscrowError::AllowanceIncreaseNoAmount);
                         }
                     },
                 }
@@ -746,12 +2612,14 @@ mod escrow {
         /// ignored in our processing of them.
         ///
         /// On a NonFungible type allowance this only reduces the
-        /// nflids set, never the additional fixed amount.
+        /// nflids set, never the additional fixed amount. If the
+        /// allowance has stored ids then it is those that are
+        /// reduced.
         ///
         /// This function will panic if you try to call it on a
         /// Fungible allowance, or if your allowance doesn't currently
         /// have an nflids set defined.
-        pub fn reduce_allowance_by_nflids(&self,
+        pub fn reduce_allowance_by_nflids(&mut self,
                                           allowance: Proof,
                                           to_remove: IndexSet<NonFungibleLocalId>)
         {
@@ -764,26 +2632,62 @@ mod escrow {
                 ResourceManager::from(allowance.resource_address())
                 .get_non_fungible_data(&allowance.as_non_fungible().non_fungible_local_id());
 
+            if let Some(stored_ids) = nfdata.stored_ids {
+                // The nflids are in our store, and since we're about
+                // to change that we need to be sure this is actually
+                // one of our Allowances.
+                let allowance_id = allowance.as_non_fungible().non_fungible_local_id();
+                let owner = self.pool_owner(&nfdata.escrow_pool.1);
+                let mut pool = self.pools.get_mut(&owner)
+                    .expect(EscrowError::PoolNotFound.as_str());
+                assert!(pool.allowance_badge_res == allowance.resource_address(),
+                        "{}", EscrowError::AllowanceNotForPool);
+                let ids = pool.allowance_ids.get_mut(&allowance_id).unwrap();
+                let mut removed = 0;
+                for nflid in &to_remove {
+                    if ids.get(nflid).map_or(false, |taken| !*taken) {
+                        ids.remove(nflid);
+                        removed += 1;
+                    }
+                }
+                ResourceManager::from(allowance.resource_address())
+                    .update_non_fungible_data(&allowance_id,
+                                              "stored_ids",
+                                              Some(StoredIds {
+                                                  permitted: stored_ids.permitted - removed,
+                                                  consumed: stored_ids.consumed,
+                                              }));
+                return
+            }
+
             let new_token_quantity: TokenQuantity;
 
             if let Some(max_amount) = nfdata.max_amount {
                 match max_amount {
                     TokenQuantity::Fungible(..) => {
-                        panic!("2005 use reduce_allowance_to_amount for Fungible allowance");
+                        panic!("{}", EscrowError::ReduceFungibleByNflids);
                     },
-                    TokenQuantity::NonFungible(nflids, amount) => {
-                        if let Some(nflids) = nflids {
-                            let new_nflids = Some(nflids.difference(&to_remove).cloned().collect());
-                            new_token_quantity = TokenQuantity::NonFungible(
-                                new_nflids,
-                                amount);
-                        } else {
-                            panic!("2006 no nflids to remove from");
-                        }
+                    TokenQuantity::NonFungible(None, _) => {
+                        panic!("{}", EscrowError::NoNflidsInAllowance);
+                    },
+                    TokenQuantity::All => {
+                        panic!("{}", EscrowError::NoNflidsInUnlimited);
+                    },
+                    TokenQuantity::NonFungibleExcept(excluded, amount) => {
+                        // Removing nflids from what we may take means
+                        // adding them to what we exclude
+                        new_token_quantity = TokenQuantity::NonFungibleExcept(
+                            excluded.union(&NflidSet::Ids(to_remove)),
+                            amount);
+                    },
+                    max_amount => {
+                        // This doesn't expand any ranges, no matter
+                        // how large they are
+                        new_token_quantity = max_amount.without_nflids(&to_remove);
                     },
                 }
             } else {
-                panic!("2007 no nflids to remove from");
+                panic!("{}", EscrowError::NoNflidsInUnlimited);
             }
 
             ResourceManager::from(allowance.resource_address())
@@ -792,87 +2696,1139 @@ mod escrow {
                                           Some(new_token_quantity));
         }
 
-        /// The pool owner can add a non-fungible global id that is
-        /// trusted to receive automatically generated Allowances when
-        /// calling deposit_funds.
-        pub fn add_trusted_nfgid(&mut self,
-                                 owner: Proof,
-                                 add_nfgid: NonFungibleGlobalId)
+        /// The pool owner can add a non-fungible global id that is
+        /// trusted to receive automatically generated Allowances when
+        /// calling deposit_funds.
+        pub fn add_trusted_nfgid(&mut self,
+                                 owner: Proof,
+                                 add_nfgid: NonFungibleGlobalId)
+        {
+            let owner_nfgid = self.authenticate_owner(owner);
+            let pool = self.get_or_add_pool(&owner_nfgid);
+            pool.trusted_nfgids.insert(add_nfgid, true);
+        }
+
+        /// The pool owner can remove a non-fungible global id from
+        /// those trusted to receive automatically generated
+        /// Allowances when calling deposit_funds.
+        pub fn remove_trusted_nfgid(&mut self,
+                                    owner: Proof,
+                                    remove_nfgid: NonFungibleGlobalId)
+        {
+            let owner_nfgid = self.authenticate_owner(owner);
+            let pool = self.get_or_add_pool(&owner_nfgid);
+            pool.trusted_nfgids.insert(remove_nfgid, false);
+        }
+
+        /// Determines if a given non-fungible global id is currently
+        /// trusted to receive automatically generated Allowances when
+        /// calling deposit_funds.
+        pub fn is_nfgid_trusted(&self,
+                                owner: NonFungibleGlobalId,
+                                candidate: NonFungibleGlobalId) -> bool
+        {
+            if let Some(pool) = self.pools.get(&owner) {
+                self.is_nfgid_trusted_by_pool(&pool, candidate)
+            } else {
+                false
+            }
+        }
+
+        /// The pool owner can add an entire resource, all tokens of
+        /// which are trusted to receive automatically generated
+        /// Allowances when calling deposit_funds.
+        ///
+        /// Note that if you add a fungible resource as trusted, trust
+        /// can be established by presenting proof for a very tiny
+        /// amount of tokens - down to the finest resolution of that
+        /// resource (e.g. 1e-18 tokens). If you want *everyone* to be
+        /// trusted then add XRD as a trusted resource and anyone can
+        /// present a valid Proof if they want to.
+        pub fn add_trusted_resource(&mut self,
+                                    owner: Proof,
+                                    add_resource: ResourceAddress)
+        {
+            let owner_nfgid = self.authenticate_owner(owner);
+            let pool = self.get_or_add_pool(&owner_nfgid);
+            pool.trusted_res.insert(add_resource, true);
+        }
+
+        /// The pool owner can remove a resource from those which are
+        /// trusted to receive automatically generated Allowances when
+        /// calling deposit_funds.
+        pub fn remove_trusted_resource(&mut self,
+                                       owner: Proof,
+                                       remove_resource: ResourceAddress)
+        {
+            let owner_nfgid = self.authenticate_owner(owner);
+            let pool = self.get_or_add_pool(&owner_nfgid);
+            pool.trusted_res.insert(remove_resource, false);
+        }
+        
+        /// Determines if a given resource is currently trusted to
+        /// receive automatically generated Allowances when calling
+        /// deposit_funds.
+        pub fn is_resource_trusted(&self,
+                                   owner: NonFungibleGlobalId,
+                                   candidate: ResourceAddress) -> bool
+        {
+            if let Some(pool) = self.pools.get(&owner) {
+                self.is_resource_trusted_by_pool(pool, candidate)
+            } else {
+                false
+            }
+        }
+
+        /// The owner of a pool can lock up funds from it in a Deal
+        /// for the benefit of `payee`. The funds are released to
+        /// `payee` when the payer calls [approve_deal], and they go
+        /// back to the payer if the deal is still open after
+        /// `deadline` (see [refund_deal]). If an `arbiter` is named,
+        /// that badge holder can instead decide how to split the
+        /// funds by calling [settle_deal].
+        ///
+        /// The `payer` must be a proof of the pool owner. The
+        /// `deadline` must not already have passed.
+        ///
+        /// Returns a Deal receipt NFT describing the deal. Its local
+        /// id is used to identify the deal in all further calls.
+        ///
+        /// [approve_deal]: crate::escrow::Escrow::approve_deal
+        /// [refund_deal]: crate::escrow::Escrow::refund_deal
+        /// [settle_deal]: crate::escrow::Escrow::settle_deal
+        pub fn open_deal(&mut self,
+                         payer: Proof,
+                         payee: NonFungibleGlobalId,
+                         arbiter: Option<NonFungibleGlobalId>,
+                         resource: ResourceAddress,
+                         quantity: TokenQuantity,
+                         deadline: TimeBound) -> Bucket
+        {
+            assert!(!quantity.is_zero(), "{}", EscrowError::ZeroDeal);
+            let mut sanity = IndexMap::new();
+            sanity.insert(resource, quantity.clone());
+            TokenQuantity::check_token_quantity_sanity(&sanity);
+            assert!(!deadline.is_before(&Now::from_ledger()),
+                    "{}", EscrowError::DealDeadlinePassed);
+
+            let payer = self.authenticate_owner(payer);
+            let funds = self.operate_on_vault(
+                &payer,
+                &resource,
+                None,
+                |mut v| Some(quantity.take_from_vault(&mut v)))
+                .unwrap();
+
+            let receipt = ResourceManager::from(self.deal_receipt_res)
+                .mint_ruid_non_fungible(
+                    DealNfData {
+                        payer,
+                        payee,
+                        arbiter,
+                        resource,
+                        quantity,
+                        deadline,
+                        state: DealState::Open,
+                    }
+                );
+            self.deal_vaults.insert(receipt.as_non_fungible().non_fungible_local_id(),
+                                    Vault::with_bucket(funds));
+
+            receipt
+        }
+
+        /// The payer of an open Deal can approve it, which releases
+        /// all its funds into the payee's pool.
+        ///
+        /// The `payer` must be a proof of the current owner of the
+        /// payer's pool. If that pool has been recovered since the
+        /// Deal was opened, this is its new owner.
+        pub fn approve_deal(&mut self,
+                            payer: Proof,
+                            deal_id: NonFungibleLocalId)
+        {
+            let deal = self.get_open_deal(&deal_id);
+            let payer = self.authenticate_owner(payer);
+            assert!(self.pool_owner(&deal.payer) == payer,
+                    "{}", EscrowError::NotDealPayer);
+
+            let funds = self.deal_vaults.get_mut(&deal_id).unwrap().take_all();
+            self.set_deal_state(&deal_id, DealState::Released);
+            self.put_in_pool(&deal.payee, funds);
+        }
+
+        /// The arbiter of an open Deal can settle it by sending
+        /// `to_payee` to the payee's pool, and the remainder back to
+        /// the payer's pool.
+        ///
+        /// The `arbiter` must be a proof of the arbiter badge named
+        /// in the Deal.
+        pub fn settle_deal(&mut self,
+                           arbiter: Proof,
+                           deal_id: NonFungibleLocalId,
+                           to_payee: TokenQuantity)
+        {
+            let deal = self.get_open_deal(&deal_id);
+            assert!(deal.arbiter.is_some(),
+                    "{}", EscrowError::NoDealArbiter);
+            assert!(deal.arbiter == Some(unchecked_proof_to_nfgid(arbiter)),
+                    "{}", EscrowError::NotDealArbiter);
+
+            let (payee_funds, payer_funds) = {
+                let mut vault = self.deal_vaults.get_mut(&deal_id).unwrap();
+                (to_payee.take_from_vault(&mut vault), vault.take_all())
+            };
+            self.set_deal_state(&deal_id, DealState::Settled{to_payee});
+            self.put_in_pool(&deal.payee, payee_funds);
+            self.return_to_pool(&deal.payer, payer_funds);
+        }
+
+        /// Once the deadline of an open Deal has passed, anyone can
+        /// call this function to return its funds to the payer's
+        /// pool.
+        pub fn refund_deal(&mut self,
+                           deal_id: NonFungibleLocalId)
+        {
+            let deal = self.get_open_deal(&deal_id);
+            assert!(deal.deadline.is_before(&Now::from_ledger()),
+                    "{}", EscrowError::DealDeadlineNotReached);
+
+            let funds = self.deal_vaults.get_mut(&deal_id).unwrap().take_all();
+            self.set_deal_state(&deal_id, DealState::Refunded);
+            self.return_to_pool(&deal.payer, funds);
+        }
+
+        /// The owner of a pool can lock up funds from it under
+        /// `hash_lock`, for the benefit of `recipient`. This is
+        /// intended for atomic swaps with other ledgers.
+        ///
+        /// The recipient can claim the funds up until `deadline` by
+        /// calling [claim_hash_lock] with the preimage of
+        /// `hash_lock`. After the deadline the owner can take them
+        /// back by calling [reclaim_hash_lock]. The deadline must not
+        /// already have passed.
+        ///
+        /// Note that we use Scrypto's `hash` function, i.e. the
+        /// preimage must hash to `hash_lock` using Blake2b-256. Locks
+        /// are told apart by their lock id and not by their hash, so
+        /// nobody can get in the way of your lock by using your hash
+        /// first. Once a preimage has been revealed, don't lock
+        /// anything else under its hash.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        ///
+        /// Returns the lock id of the new hash lock.
+        ///
+        /// [claim_hash_lock]: crate::escrow::Escrow::claim_hash_lock
+        /// [reclaim_hash_lock]: crate::escrow::Escrow::reclaim_hash_lock
+        pub fn lock_with_hash(&mut self,
+                              owner: Proof,
+                              recipient: NonFungibleGlobalId,
+                              resource: ResourceAddress,
+                              quantity: TokenQuantity,
+                              hash_lock: Hash,
+                              deadline: TimeBound) -> u64
+        {
+            assert!(!quantity.is_zero(), "{}", EscrowError::ZeroHashLock);
+            let mut sanity = IndexMap::new();
+            sanity.insert(resource, quantity.clone());
+            TokenQuantity::check_token_quantity_sanity(&sanity);
+            assert!(!deadline.is_before(&Now::from_ledger()),
+                    "{}", EscrowError::HashLockExpired);
+
+            let owner = self.authenticate_owner(owner);
+            let funds = self.operate_on_vault(
+                &owner,
+                &resource,
+                None,
+                |mut v| Some(quantity.take_from_vault(&mut v)))
+                .unwrap();
+
+            let lock_id = self.next_hash_lock_id;
+            self.next_hash_lock_id += 1;
+            self.hash_locks.insert(
+                lock_id,
+                HashLock {
+                    owner,
+                    hash_lock,
+                    recipient,
+                    deadline,
+                    open: true,
+                    vault: Vault::with_bucket(funds),
+                });
+
+            lock_id
+        }
+
+        /// The recipient of hash lock `lock_id` can claim its funds
+        /// by providing the `preimage` of its hash. This must be
+        /// done no later than the lock's deadline.
+        ///
+        /// The preimage is published in a [HashLockClaimedEvent] so
+        /// that the counterparty can use it on the other ledger.
+        ///
+        /// The `recipient` must be a proof of the recipient badge
+        /// named in the lock.
+        ///
+        /// [HashLockClaimedEvent]: crate::HashLockClaimedEvent
+        pub fn claim_hash_lock(&mut self,
+                               recipient: Proof,
+                               lock_id: u64,
+                               preimage: Vec<u8>) -> Bucket
+        {
+            let hash_lock = hash(&preimage);
+            let funds = {
+                let mut lock = self.hash_locks.get_mut(&lock_id)
+                    .expect(EscrowError::HashLockNotFound.as_str());
+                assert!(lock.open, "{}", EscrowError::HashLockClosed);
+                assert!(lock.recipient == unchecked_proof_to_nfgid(recipient),
+                        "{}", EscrowError::NotHashLockRecipient);
+                assert!(lock.hash_lock == hash_lock,
+                        "{}", EscrowError::WrongPreimage);
+                assert!(!lock.deadline.is_before(&Now::from_ledger()),
+                        "{}", EscrowError::HashLockExpired);
+                lock.open = false;
+                lock.vault.take_all()
+            };
+
+            Runtime::emit_event(HashLockClaimedEvent { lock_id, hash_lock, preimage });
+
+            funds
+        }
+
+        /// Once the deadline of hash lock `lock_id` has passed
+        /// without it being claimed, its owner can return its funds
+        /// to their pool.
+        ///
+        /// The `owner` must be a proof of the current owner of the
+        /// pool the lock was made from, which is not the one who made
+        /// it if the pool has since been recovered.
+        pub fn reclaim_hash_lock(&mut self,
+                                 owner: Proof,
+                                 lock_id: u64)
+        {
+            let owner = self.authenticate_owner(owner);
+            let (open, lock_owner) = {
+                let lock = self.hash_locks.get(&lock_id)
+                    .expect(EscrowError::HashLockNotFound.as_str());
+                (lock.open, lock.owner.clone())
+            };
+            assert!(open, "{}", EscrowError::HashLockClosed);
+            assert!(self.pool_owner(&lock_owner) == owner,
+                    "{}", EscrowError::NotHashLockOwner);
+            let funds = {
+                let mut lock = self.hash_locks.get_mut(&lock_id).unwrap();
+                assert!(lock.deadline.is_before(&Now::from_ledger()),
+                        "{}", EscrowError::HashLockNotExpired);
+                lock.open = false;
+                lock.vault.take_all()
+            };
+
+            self.return_to_pool(&owner, funds);
+        }
+
+        /// The owner of a pool can set up a standing order that pays
+        /// `amount` of `resource` to `recipient` once every `period`
+        /// seconds, starting at `first_due`. If `end` is set then no
+        /// payments fall due after that Unix time.
+        ///
+        /// Payments are made whenever someone calls
+        /// [execute_due_orders]. If that hasn't happened for a while
+        /// then up to `max_catch_up` missed payments are made in one
+        /// go, and any further missed payments are skipped. This
+        /// means `max_catch_up` must be at least one. For a
+        /// non-fungible `resource` the `amount` must be a whole
+        /// number. Standing orders can't be added while a savings
+        /// lock on `resource` holds.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        ///
+        /// Returns the id of the new standing order.
+        ///
+        /// [execute_due_orders]: crate::escrow::Escrow::execute_due_orders
+        pub fn add_standing_order(&mut self,
+                                  owner: Proof,
+                                  resource: ResourceAddress,
+                                  amount: Decimal,
+                                  recipient: PaymentRecipient,
+                                  first_due: i64,
+                                  period: i64,
+                                  end: Option<i64>,
+                                  max_catch_up: u64) -> u64
+        {
+            assert!(amount.is_positive(),
+                    "{}", EscrowError::StandingOrderAmountNotPositive);
+            assert!(period > 0,
+                    "{}", EscrowError::StandingOrderPeriodNotPositive);
+            assert!(max_catch_up > 0,
+                    "{}", EscrowError::StandingOrderNoCatchUp);
+            assert!(resource.is_fungible()
+                    || amount.checked_round(0, RoundingMode::ToZero) == Some(amount),
+                    "{}", EscrowError::StandingOrderAmountNotWhole);
+
+            let owner = self.authenticate_owner(owner);
+            let mut pool = self.get_or_add_pool(&owner);
+            assert!(pool.holding_savings_locks(&resource).is_empty(),
+                    "{}", EscrowError::SavingsLockHolds);
+            let order_id = pool.next_standing_order_id;
+            pool.next_standing_order_id += 1;
+            pool.standing_orders.insert(
+                order_id,
+                StandingOrder {
+                    resource,
+                    amount,
+                    recipient,
+                    period,
+                    next_due: first_due,
+                    end,
+                    max_catch_up,
+                });
+            pool.active_standing_orders.push(order_id);
+
+            order_id
+        }
+
+        /// The owner of a pool can cancel one of its standing orders.
+        /// No further payments will be made for it.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn cancel_standing_order(&mut self,
+                                     owner: Proof,
+                                     order_id: u64)
+        {
+            let owner = self.authenticate_owner(owner);
+            let mut pool = self.get_or_add_pool(&owner);
+            assert!(pool.active_standing_orders.contains(&order_id),
+                    "{}", EscrowError::StandingOrderNotFound);
+            pool.active_standing_orders.retain(|id| *id != order_id);
+        }
+
+        /// Anyone can call this function to make all the payments
+        /// that have fallen due on the standing orders of the pool
+        /// owned by `owner`. This lets an off-ledger keeper, or the
+        /// recipients themselves, see to it that payments are made
+        /// on time.
+        ///
+        /// A payment that the pool can't currently afford is left
+        /// outstanding and will be made on a later call if funds have
+        /// arrived by then. The same goes for a payment that the
+        /// recipient's account refuses to accept, and for one that
+        /// the pool's savings locks or withdrawal threshold don't
+        /// allow yet.
+        pub fn execute_due_orders(&mut self,
+                                  owner: NonFungibleGlobalId)
+        {
+            // Keepers may still know the pool by an owner it has
+            // since been recovered from
+            let owner = self.pool_owner(&owner);
+            let now = unix_time_now();
+            let mut payments: Vec<(u64, PaymentRecipient, Bucket)> = Vec::new();
+            {
+                let mut pool_entry = self.pools.get_mut(&owner)
+                    .expect(EscrowError::PoolNotFound.as_str());
+                let pool: &mut Pool = &mut pool_entry;
+                let mut still_active = Vec::new();
+
+                for order_id in pool.active_standing_orders.clone() {
+                    let mut order = pool.standing_orders.get_mut(&order_id).unwrap();
+                    let last_due = match order.end {
+                        Some(end) => std::cmp::min(now, end),
+                        None => now,
+                    };
+                    let mut taken = Decimal::ZERO;
+                    if order.next_due <= last_due {
+                        let due_count = ((last_due - order.next_due) / order.period + 1) as u64;
+                        let skipped = due_count.saturating_sub(order.max_catch_up);
+                        let mut paid = due_count - skipped;
+
+                        let headroom = pool.owner_headroom(&order.resource);
+                        if let Some(mut vault) = pool.vaults.get_mut(&order.resource) {
+                            let available = match headroom {
+                                Some(headroom) => std::cmp::min(vault.amount(), headroom),
+                                None => vault.amount(),
+                            };
+                            let affordable = (available / order.amount)
+                                .checked_round(0, RoundingMode::ToZero).unwrap();
+                            paid = dec_to_u64(std::cmp::min(Decimal::from(paid), affordable));
+                            if paid > 0 {
+                                payments.push((order_id,
+                                               order.recipient.clone(),
+                                               vault.take(order.amount * Decimal::from(paid))));
+                            }
+                        } else {
+                            paid = 0;
+                        }
+                        taken = order.amount * Decimal::from(paid);
+                        order.next_due += (skipped + paid) as i64 * order.period;
+                    }
+
+                    let ended = match order.end {
+                        Some(end) => order.next_due > end,
+                        None => false,
+                    };
+                    if !ended {
+                        still_active.push(order_id);
+                    }
+                    let resource = order.resource;
+                    drop(order);
+                    pool.take_as_owner(&resource, taken);
+                }
+
+                pool.active_standing_orders = still_active;
+            }
+
+            for (order_id, recipient, funds) in payments {
+                if let Some(refused) = self.pay_recipient(recipient, funds) {
+                    // Put the funds back and wind the order back to
+                    // the first of the refused payments.
+                    let mut pool_entry = self.pools.get_mut(&owner).unwrap();
+                    let pool: &mut Pool = &mut pool_entry;
+                    let mut order = pool.standing_orders.get_mut(&order_id).unwrap();
+                    let refused_count = dec_to_u64(refused.amount() / order.amount);
+                    order.next_due -= refused_count as i64 * order.period;
+                    let resource = order.resource;
+                    drop(order);
+                    pool.return_as_owner(&resource, refused.amount());
+                    pool.vaults.get_mut(&resource).unwrap().put(refused);
+                    if !pool.active_standing_orders.contains(&order_id) {
+                        pool.active_standing_orders.push(order_id);
+                    }
+                }
+            }
+        }
+
+        /// The owner of a pool can start a payment stream from it to
+        /// `recipient`. Funds accrue to the recipient at `rate`
+        /// tokens per second, and the recipient can claim them at any
+        /// time using [claim_stream].
+        ///
+        /// The stream is funded by moving `funding` tokens of
+        /// `resource` out of the pool, and funds stop accruing when
+        /// that runs out. Use [top_up_stream] to add more. Only
+        /// fungible resources can be streamed.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        ///
+        /// Returns the id of the new stream.
+        ///
+        /// [claim_stream]: crate::escrow::Escrow::claim_stream
+        /// [top_up_stream]: crate::escrow::Escrow::top_up_stream
+        pub fn start_stream(&mut self,
+                            owner: Proof,
+                            recipient: NonFungibleGlobalId,
+                            resource: ResourceAddress,
+                            rate: Decimal,
+                            funding: Decimal) -> u64
+        {
+            assert!(resource.is_fungible(),
+                    "{}", EscrowError::StreamNotFungible);
+            assert!(rate.is_positive(),
+                    "{}", EscrowError::StreamRateNotPositive);
+
+            let owner = self.authenticate_owner(owner);
+            let funds = self.operate_on_vault(&owner,
+                                              &resource,
+                                              None,
+                                              |mut v| Some(v.take(funding)))
+                .unwrap();
+
+            let mut pool = self.get_or_add_pool(&owner);
+            let stream_id = pool.next_stream_id;
+            pool.next_stream_id += 1;
+            pool.streams.insert(
+                stream_id,
+                Stream {
+                    recipient,
+                    rate,
+                    accruing_since: unix_time_now(),
+                    accrued: Decimal::ZERO,
+                    paused: false,
+                    open: true,
+                    vault: Vault::with_bucket(funds),
+                });
+
+            stream_id
+        }
+
+        /// The owner of a pool can add `amount` more funding from the
+        /// pool to one of its streams.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn top_up_stream(&mut self,
+                             owner: Proof,
+                             stream_id: u64,
+                             amount: Decimal)
+        {
+            let owner = self.authenticate_owner(owner);
+            let now = unix_time_now();
+            let mut pool_entry = self.pools.get_mut(&owner)
+                .expect(EscrowError::PoolNotFound.as_str());
+            let pool: &mut Pool = &mut pool_entry;
+            let mut stream = pool.streams.get_mut(&stream_id)
+                .expect(EscrowError::StreamNotFound.as_str());
+            assert!(stream.open, "{}", EscrowError::StreamClosed);
+
+            // Bring the stream up to date first, so that the new
+            // funding doesn't retroactively accrue.
+            stream.accrue(now);
+            let resource = stream.vault.resource_address();
+            let funds = pool.vaults.get_mut(&resource)
+                .expect(EscrowError::ResourceNotFound.as_str())
+                .take(amount);
+            stream.vault.put(funds);
+            drop(stream);
+            pool.take_as_owner(&resource, amount);
+        }
+
+        /// The owner of a pool can pause one of its streams. No funds
+        /// accrue while it's paused, but what has already accrued can
+        /// still be claimed.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn pause_stream(&mut self,
+                            owner: Proof,
+                            stream_id: u64)
+        {
+            let owner = self.authenticate_owner(owner);
+            let mut stream = self.get_open_stream(&owner, stream_id);
+            stream.accrue(unix_time_now());
+            stream.paused = true;
+        }
+
+        /// The owner of a pool can resume a paused stream.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn resume_stream(&mut self,
+                             owner: Proof,
+                             stream_id: u64)
+        {
+            let owner = self.authenticate_owner(owner);
+            let mut stream = self.get_open_stream(&owner, stream_id);
+            stream.accrue(unix_time_now());
+            stream.paused = false;
+        }
+
+        /// The owner of a pool can cancel one of its streams. The
+        /// funds that have accrued so far are paid into the
+        /// recipient's pool on this Escrow, and the remaining funding
+        /// goes back to the owner's pool.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn cancel_stream(&mut self,
+                             owner: Proof,
+                             stream_id: u64)
+        {
+            let owner = self.authenticate_owner(owner);
+            let (recipient, accrued, remainder) = {
+                let mut stream = self.get_open_stream(&owner, stream_id);
+                stream.accrue(unix_time_now());
+                stream.open = false;
+                let accrued = stream.take_accrued();
+                (stream.recipient.clone(), accrued, stream.vault.take_all())
+            };
+
+            self.put_in_pool(&recipient, accrued);
+            self.return_to_pool(&owner, remainder);
+        }
+
+        /// The recipient of a stream can claim the funds that have
+        /// accrued to them so far.
+        ///
+        /// The `recipient` must be a proof of the recipient badge
+        /// named in the stream.
+        pub fn claim_stream(&mut self,
+                            recipient: Proof,
+                            owner: NonFungibleGlobalId,
+                            stream_id: u64) -> Bucket
+        {
+            let recipient = unchecked_proof_to_nfgid(recipient);
+            let owner = self.pool_owner(&owner);
+            let mut stream = self.get_open_stream(&owner, stream_id);
+            assert!(stream.recipient == recipient,
+                    "{}", EscrowError::NotStreamRecipient);
+            stream.accrue(unix_time_now());
+            stream.take_accrued()
+        }
+
+        /// Returns the funds that have accrued on a stream and can be
+        /// claimed right now, and the funding that remains beyond
+        /// that.
+        pub fn read_stream(&self,
+                           owner: NonFungibleGlobalId,
+                           stream_id: u64) -> (Decimal, Decimal)
+        {
+            let owner = self.pool_owner(&owner);
+            let pool = self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str());
+            let stream = pool.streams.get(&stream_id).expect(EscrowError::StreamNotFound.as_str());
+            let mut accrued = stream.accrued;
+            if !stream.paused && stream.open {
+                let now = unix_time_now();
+                if now > stream.accruing_since {
+                    accrued = std::cmp::min(
+                        accrued + stream.rate * Decimal::from(now - stream.accruing_since),
+                        stream.vault.amount());
+                }
+            }
+            (accrued, stream.vault.amount() - accrued)
+        }
+
+        /// The owner of a pool can name guardians that can together
+        /// recover the pool should the owner lose their badge. Pass
+        /// `None` to have no guardians. This also cancels any
+        /// recovery underway. If the pool has a withdrawal delay, a
+        /// proof of its canceller badge must be in the auth zone.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn set_guardians(&mut self,
+                             owner: Proof,
+                             guardians: Option<Guardians>)
+        {
+            let owner = self.authenticate_owner(owner);
+            if let Some(guardians) = &guardians {
+                assert!(guardians.threshold >= 1
+                        && guardians.threshold <= guardians.badges.len() as u64,
+                        "{}", EscrowError::InvalidGuardians);
+                assert!(guardians.challenge_period > 0,
+                        "{}", EscrowError::NoChallengePeriod);
+            }
+            let mut pool = self.pools.get_mut(&owner).expect(EscrowError::PoolNotFound.as_str());
+            pool.assert_canceller();
+            pool.guardians = guardians;
+            pool.recovery = None;
+        }
+
+        /// A guardian of the pool owned by `owner` can approve moving
+        /// the pool to `new_owner`. Once enough guardians have
+        /// approved the same `new_owner` the challenge period starts,
+        /// after which anyone can call [complete_recovery].
+        ///
+        /// Each guardian's approval counts for one `new_owner` at a
+        /// time, so approving a different one moves the guardian's
+        /// approval over to it while leaving the other guardians'
+        /// approvals as they are. Once the challenge period has
+        /// started no other `new_owner` can be approved.
+        ///
+        /// The `guardian` must be a proof of one of the pool's
+        /// guardian badges.
+        ///
+        /// [complete_recovery]: crate::escrow::Escrow::complete_recovery
+        pub fn approve_recovery(&mut self,
+                                guardian: Proof,
+                                owner: NonFungibleGlobalId,
+                                new_owner: NonFungibleGlobalId)
+        {
+            let guardian = unchecked_proof_to_nfgid(guardian);
+            let mut pool_entry = self.pools.get_mut(&owner)
+                .expect(EscrowError::PoolNotFound.as_str());
+            let pool: &mut Pool = &mut pool_entry;
+            let guardians = pool.guardians.as_ref()
+                .expect(EscrowError::NotGuardian.as_str());
+            assert!(guardians.badges.contains(&guardian), "{}", EscrowError::NotGuardian);
+
+            let mut recovery = pool.recovery.take().unwrap_or(Recovery {
+                new_owner: None,
+                approvals: IndexMap::new(),
+                completes_at: None,
+            });
+            if let Some(agreed) = &recovery.new_owner {
+                assert!(*agreed == new_owner, "{}", EscrowError::RecoveryInProgress);
+            }
+            recovery.approvals.insert(guardian, new_owner.clone());
+            let approved = recovery.approvals.values()
+                .filter(|candidate| **candidate == new_owner)
+                .count() as u64;
+            if recovery.new_owner.is_none() && approved >= guardians.threshold {
+                recovery.new_owner = Some(new_owner);
+                recovery.completes_at = Some(unix_time_now() + guardians.challenge_period);
+            }
+            pool.recovery = Some(recovery);
+        }
+
+        /// The owner of a pool can cancel a recovery of it that is
+        /// underway.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn cancel_recovery(&mut self,
+                               owner: Proof)
+        {
+            let owner = self.authenticate_owner(owner);
+            let mut pool = self.pools.get_mut(&owner).expect(EscrowError::PoolNotFound.as_str());
+            assert!(pool.recovery.is_some(), "{}", EscrowError::NoRecovery);
+            pool.recovery = None;
+        }
+
+        /// Anyone can complete a recovery once its challenge period
+        /// is over. This moves the pool owned by `owner`, with
+        /// everything in it, to its new owner, and makes the new
+        /// owner the one who can recall the pool's Allowances.
+        ///
+        /// Allowances, Deals and so on that refer to the pool by its
+        /// old owner keep working, and funds sent to the old owner's
+        /// pool go to the new owner instead.
+        pub fn complete_recovery(&mut self,
+                                 owner: NonFungibleGlobalId)
+        {
+            let new_owner = {
+                let pool = self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str());
+                let recovery = pool.recovery.as_ref().expect(EscrowError::NoRecovery.as_str());
+                assert!(recovery.completes_at.map_or(false, |at| at <= unix_time_now()),
+                        "{}", EscrowError::RecoveryNotReady);
+                recovery.new_owner.clone().unwrap()
+            };
+            self.move_pool(&owner, &new_owner);
+        }
+
+        /// Returns the recovery underway for the pool owned by
+        /// `owner`, if any.
+        pub fn read_recovery(&self,
+                             owner: NonFungibleGlobalId) -> Option<Recovery>
+        {
+            self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str())
+                .recovery.clone()
+        }
+
+        /// The owner of a pool can name beneficiaries that may claim
+        /// the pool if the owner stops using it. Pass `None` to have
+        /// no beneficiaries. Any call the owner makes with their
+        /// badge counts as using the pool and restarts the
+        /// inactivity period.
+        ///
+        /// Shares already claimed stay claimed if the beneficiaries
+        /// and their shares are unchanged. While a savings lock on
+        /// the pool holds, the inheritance can only be removed or
+        /// given a longer inactivity period. If the pool has a
+        /// withdrawal delay, a proof of its canceller badge must be
+        /// in the auth zone.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn set_inheritance(&mut self,
+                               owner: Proof,
+                               inheritance: Option<Inheritance>)
+        {
+            let owner = self.authenticate_owner(owner);
+            if let Some(inheritance) = &inheritance {
+                assert!(!inheritance.shares.is_empty()
+                        && inheritance.shares.values().all(|share| share.is_positive())
+                        && inheritance.inactivity_period >= 0,
+                        "{}", EscrowError::InvalidInheritance);
+            }
+            let mut pool = self.pools.get_mut(&owner).expect(EscrowError::PoolNotFound.as_str());
+            pool.assert_canceller();
+            let shares_kept = match (&inheritance, &pool.inheritance) {
+                (Some(new), Some(current)) => new.shares == current.shares,
+                _ => false,
+            };
+            if let Some(new) = &inheritance {
+                assert!(!pool.any_savings_lock_holds()
+                        || pool.inheritance.as_ref().map_or(false, |current| new.is_as_strict_as(current)),
+                        "{}", EscrowError::SavingsLockHolds);
+            }
+            pool.inheritance = inheritance;
+            if !shares_kept {
+                pool.inheritance_claimed = IndexSet::new();
+            }
+        }
+
+        /// Once the owner of a pool has been inactive for its
+        /// inheritance period, anyone can call this to pay out
+        /// `beneficiary`'s share of the pool's `resource` into the
+        /// beneficiary's own pool on this component. Each beneficiary
+        /// can claim each resource once, and their share is worked
+        /// out from what is left of it after earlier claims so that
+        /// everyone gets their fair part of the same funds. Funds
+        /// reserved for Allowances are not included, and the share
+        /// counts against the pool's savings locks. If the share is
+        /// more than the pool's withdrawal delay lets the owner take
+        /// out at once, it is requested as a withdrawal to the
+        /// beneficiary's pool instead, which the pool's canceller can
+        /// stop during the delay.
+        ///
+        /// If `resource` is `None` and `beneficiary` is the pool's
+        /// only beneficiary, they instead take over the whole pool as
+        /// its new owner, just as with [complete_recovery]. The
+        /// pool's guardians and inheritance are then cleared for the
+        /// new owner to set up anew. Since the new owner can't have or
+        /// have had a pool of their own, a sole beneficiary for whom
+        /// that holds can't have a share paid out and must take over
+        /// instead. Otherwise anyone could block their takeover by
+        /// paying out a share, which gives them a pool.
+        ///
+        /// [complete_recovery]: crate::escrow::Escrow::complete_recovery
+        pub fn claim_inheritance(&mut self,
+                                 owner: NonFungibleGlobalId,
+                                 beneficiary: NonFungibleGlobalId,
+                                 resource: Option<ResourceAddress>)
+        {
+            let sole_beneficiary = {
+                let pool = self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str());
+                let inheritance = pool.inheritance.as_ref()
+                    .expect(EscrowError::NoInheritance.as_str());
+                assert!(inheritance.shares.contains_key(&beneficiary),
+                        "{}", EscrowError::NotBeneficiary);
+                assert!(pool.last_active + inheritance.inactivity_period <= unix_time_now(),
+                        "{}", EscrowError::OwnerStillActive);
+                inheritance.shares.len() == 1
+            };
+            let can_take_over = sole_beneficiary
+                && self.pools.get(&beneficiary).is_none()
+                && self.moved_pools.get(&beneficiary).is_none();
+
+            if resource.is_none() {
+                assert!(sole_beneficiary, "{}", EscrowError::SharedInheritance);
+                self.move_pool(&owner, &beneficiary);
+                let mut pool = self.pools.get_mut(&beneficiary).unwrap();
+                pool.guardians = None;
+                pool.inheritance = None;
+                pool.inheritance_claimed = IndexSet::new();
+                pool.last_active = unix_time_now();
+                return
+            }
+            let resource = resource.unwrap();
+            assert!(!can_take_over, "{}", EscrowError::MustTakeOver);
+
+            let (funds, delayed) = {
+                let mut pool_entry = self.pools.get_mut(&owner).unwrap();
+                let pool: &mut Pool = &mut pool_entry;
+                let claim = (beneficiary.clone(), resource);
+                assert!(!pool.inheritance_claimed.contains(&claim),
+                        "{}", EscrowError::ShareAlreadyClaimed);
+                let shares = &pool.inheritance.as_ref().unwrap().shares;
+                let unclaimed = shares.iter()
+                    .filter(|(b, _)| !pool.inheritance_claimed.contains(&((*b).clone(), resource)))
+                    .fold(Decimal::ZERO, |sum, (_, share)| sum + *share);
+                let share = *shares.get(&beneficiary).unwrap();
+                let divisibility = ResourceManager::from(resource)
+                    .resource_type().divisibility().unwrap_or(0);
+                let mut vault = pool.vaults.get_mut(&resource)
+                    .expect(EscrowError::ResourceNotFound.as_str());
+                let amount = (vault.amount() * share / unclaimed)
+                    .checked_round(divisibility, RoundingMode::ToZero).unwrap();
+                let funds = vault.take(amount);
+                drop(vault);
+                let delayed = pool.withdrawal_headroom(&resource)
+                    .map_or(false, |headroom| amount > headroom);
+                if delayed {
+                    pool.take_under_savings_locks(&resource, amount);
+                } else {
+                    pool.take_as_owner(&resource, amount);
+                }
+                pool.inheritance_claimed.insert(claim);
+                (funds, delayed)
+            };
+            if delayed {
+                let recipient = PaymentRecipient::EscrowPool(Runtime::global_address(),
+                                                             beneficiary);
+                self.queue_withdrawal(&owner, funds, recipient);
+            } else {
+                self.put_in_pool(&beneficiary, funds);
+            }
+        }
+
+        /// Returns the inheritance set for the pool owned by `owner`,
+        /// if any.
+        pub fn read_inheritance(&self,
+                                owner: NonFungibleGlobalId) -> Option<Inheritance>
         {
-            let owner_nfgid = unchecked_proof_to_nfgid(owner);
-            let pool = self.get_or_add_pool(&owner_nfgid);
-            pool.trusted_nfgids.insert(add_nfgid, true);
+            self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str())
+                .inheritance.clone()
         }
 
-        /// The pool owner can remove a non-fungible global id from
-        /// those trusted to receive automatically generated
-        /// Allowances when calling deposit_funds.
-        pub fn remove_trusted_nfgid(&mut self,
-                                    owner: Proof,
-                                    remove_nfgid: NonFungibleGlobalId)
+        /// Returns the Unix time of the last call the owner of a pool
+        /// made with their badge.
+        pub fn read_last_active(&self,
+                                owner: NonFungibleGlobalId) -> i64
         {
-            let owner_nfgid = unchecked_proof_to_nfgid(owner);
-            let pool = self.get_or_add_pool(&owner_nfgid);
-            pool.trusted_nfgids.insert(remove_nfgid, false);
+            self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str())
+                .last_active
         }
 
-        /// Determines if a given non-fungible global id is currently
-        /// trusted to receive automatically generated Allowances when
-        /// calling deposit_funds.
-        pub fn is_nfgid_trusted(&self,
-                                owner: NonFungibleGlobalId,
-                                candidate: NonFungibleGlobalId) -> bool
+        /// The owner of a pool can lock their own use of a
+        /// `resource` in it, or of the whole pool if `resource` is
+        /// `None`. While the lock holds they can take out only what
+        /// its limit allows, whether by withdrawing, paying fees,
+        /// or putting funds into Deals, hash locks, streams or
+        /// reserved Allowances. Standing order payments and
+        /// inheritance shares count against the limit too. Deposits,
+        /// and Allowances without reserved funds that were minted
+        /// before the lock, are not affected.
+        ///
+        /// While the lock holds the owner can't mint Allowances
+        /// without reserved funds or add standing orders for what it
+        /// covers. While any lock on the pool holds they can't
+        /// change its inheritance other than to remove it or make
+        /// the beneficiaries wait longer.
+        ///
+        /// A lock that is still holding can only be replaced by one
+        /// that is at least as strict.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn set_savings_lock(&mut self,
+                                owner: Proof,
+                                resource: Option<ResourceAddress>,
+                                lock: SavingsLock)
         {
-            if let Some(pool) = self.pools.get(&owner) {
-                self.is_nfgid_trusted_by_pool(&pool, candidate)
-            } else {
-                false
+            let owner = self.authenticate_owner(owner);
+            if let Some(limit) = &lock.limit {
+                assert!(!limit.amount.is_negative() && limit.period > 0,
+                        "{}", EscrowError::InvalidSavingsLock);
             }
+            let mut pool = self.pools.get_mut(&owner).expect(EscrowError::PoolNotFound.as_str());
+            if let Some(current) = pool.savings_locks.get(&resource) {
+                assert!(!current.until.is_after(&Now::from_ledger())
+                        || lock.is_as_strict_as(&current),
+                        "{}", EscrowError::SavingsLockShortened);
+            }
+            pool.savings_locks.insert(resource, lock);
+            pool.savings_lock_keys.insert(resource);
         }
 
-        /// The pool owner can add an entire resource, all tokens of
-        /// which are trusted to receive automatically generated
-        /// Allowances when calling deposit_funds.
+        /// Returns the savings lock on a pool's `resource`, or on the
+        /// whole pool if `resource` is `None`. This includes locks
+        /// that no longer hold.
+        pub fn read_savings_lock(&self,
+                                 owner: NonFungibleGlobalId,
+                                 resource: Option<ResourceAddress>) -> Option<SavingsLock>
+        {
+            self.pools.get(&owner).expect(EscrowError::PoolNotFound.as_str())
+                .savings_locks.get(&resource).map(|lock| lock.clone())
+        }
+
+        /// The owner of a pool can require that withdrawals taking
+        /// them above a threshold per period are requested with
+        /// [request_withdraw], and only paid out after a delay during
+        /// which they can be cancelled. Pass `None` to withdraw at
+        /// once again.
         ///
-        /// Note that if you add a fungible resource as trusted, trust
-        /// can be established by presenting proof for a very tiny
-        /// amount of tokens - down to the finest resolution of that
-        /// resource (e.g. 1e-18 tokens). If you want *everyone* to be
-        /// trusted then add XRD as a trusted resource and anyone can
-        /// present a valid Proof if they want to.
-        pub fn add_trusted_resource(&mut self,
+        /// Everything the owner takes out of the pool counts against
+        /// the threshold, be it by withdrawing, paying fees, putting
+        /// funds into Deals, hash locks, streams or reserved
+        /// Allowances, or through standing orders and inheritance
+        /// shares. Standing order payments that don't fit are left
+        /// outstanding, and inheritance shares that don't fit are
+        /// requested on the beneficiary's behalf.
+        ///
+        /// Once a withdrawal delay is set, changing or removing it
+        /// also requires a proof of its canceller badge in the auth
+        /// zone, so that whoever has stolen the owner badge can't
+        /// simply turn it off. The same goes for minting Allowances
+        /// and changing the pool's guardians or inheritance, all of
+        /// which could otherwise be used to get around the delay.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        ///
+        /// [request_withdraw]: crate::escrow::Escrow::request_withdraw
+        pub fn set_withdrawal_delay(&mut self,
                                     owner: Proof,
-                                    add_resource: ResourceAddress)
+                                    withdrawal_delay: Option<WithdrawalDelay>)
         {
-            let owner_nfgid = unchecked_proof_to_nfgid(owner);
-            let pool = self.get_or_add_pool(&owner_nfgid);
-            pool.trusted_res.insert(add_resource, true);
+            let owner = self.authenticate_owner(owner);
+            if let Some(withdrawal_delay) = &withdrawal_delay {
+                assert!(withdrawal_delay.delay > 0
+                        && withdrawal_delay.period > 0
+                        && withdrawal_delay.thresholds.values().all(|t| !t.is_negative()),
+                        "{}", EscrowError::InvalidWithdrawalDelay);
+            }
+            let mut pool = self.pools.get_mut(&owner).expect(EscrowError::PoolNotFound.as_str());
+            pool.assert_canceller();
+            pool.withdrawal_delay = withdrawal_delay;
         }
 
-        /// The pool owner can remove a resource from those which are
-        /// trusted to receive automatically generated Allowances when
-        /// calling deposit_funds.
-        pub fn remove_trusted_resource(&mut self,
-                                       owner: Proof,
-                                       remove_resource: ResourceAddress)
+        /// The owner of a pool with a withdrawal delay can request a
+        /// withdrawal of `quantity` of `resource`, to be paid to
+        /// `recipient` once the delay has passed. The funds are taken
+        /// out of the pool at once, and return to it if the
+        /// withdrawal is cancelled.
+        ///
+        /// Returns the id of the withdrawal request.
+        ///
+        /// The `owner` must be a proof of the pool owner.
+        pub fn request_withdraw(&mut self,
+                                owner: Proof,
+                                resource: ResourceAddress,
+                                quantity: TokenQuantity,
+                                recipient: PaymentRecipient) -> u64
         {
-            let owner_nfgid = unchecked_proof_to_nfgid(owner);
-            let pool = self.get_or_add_pool(&owner_nfgid);
-            pool.trusted_res.insert(remove_resource, false);
+            let owner = self.authenticate_owner(owner);
+            let funds = {
+                let mut pool_entry = self.pools.get_mut(&owner)
+                    .expect(EscrowError::PoolNotFound.as_str());
+                let pool: &mut Pool = &mut pool_entry;
+                assert!(pool.withdrawal_delay.is_some(),
+                        "{}", EscrowError::NoWithdrawalDelay);
+                let mut vault = pool.vaults.get_mut(&resource)
+                    .expect(EscrowError::ResourceNotFound.as_str());
+                let funds = quantity.take_from_vault(&mut vault);
+                drop(vault);
+                pool.take_under_savings_locks(&resource, funds.amount());
+                funds
+            };
+
+            self.queue_withdrawal(&owner, funds, recipient)
         }
-        
-        /// Determines if a given resource is currently trusted to
-        /// receive automatically generated Allowances when calling
-        /// deposit_funds.
-        pub fn is_resource_trusted(&self,
-                                   owner: NonFungibleGlobalId,
-                                   candidate: ResourceAddress) -> bool
+
+        /// Once its delay has passed, anyone can finalize a
+        /// withdrawal request of the pool owned by `owner`, paying
+        /// its funds to the recipient the owner named.
+        pub fn finalize_withdraw(&mut self,
+                                 owner: NonFungibleGlobalId,
+                                 withdrawal_id: u64)
         {
-            if let Some(pool) = self.pools.get(&owner) {
-                self.is_resource_trusted_by_pool(pool, candidate)
-            } else {
-                false
-            }
+            let (recipient, funds) = {
+                let pool = self.pools.get_mut(&owner).expect(EscrowError::PoolNotFound.as_str());
+                let mut withdrawal = pool.pending_withdrawals.get_mut(&withdrawal_id)
+                    .expect(EscrowError::WithdrawalNotFound.as_str());
+                assert!(withdrawal.open, "{}", EscrowError::WithdrawalClosed);
+                assert!(withdrawal.finalizes_at <= unix_time_now(),
+                        "{}", EscrowError::WithdrawalNotReady);
+                withdrawal.open = false;
+                (withdrawal.recipient.clone(), withdrawal.vault.take_all())
+            };
+
+            Runtime::emit_event(WithdrawalFinalizedEvent {
+                owner,
+                withdrawal_id,
+                recipient: recipient.clone(),
+            });
+
+            let refused = self.pay_recipient(recipient, funds);
+            assert!(refused.is_none(), "{}", EscrowError::PaymentRefused);
+        }
+
+        /// The canceller badge of a pool's withdrawal delay can
+        /// cancel any of its withdrawal requests that haven't been
+        /// finalized yet, returning the funds to the pool.
+        ///
+        /// The `canceller` must be a proof of the canceller badge.
+        pub fn cancel_withdraw(&mut self,
+                               canceller: Proof,
+                               owner: NonFungibleGlobalId,
+                               withdrawal_id: u64)
+        {
+            let canceller = unchecked_proof_to_nfgid(canceller);
+            let mut pool_entry = self.pools.get_mut(&owner)
+                .expect(EscrowError::PoolNotFound.as_str());
+            let pool: &mut Pool = &mut pool_entry;
+            assert!(pool.withdrawal_delay.as_ref()
+                    .map_or(false, |delay| delay.canceller == canceller),
+                    "{}", EscrowError::NotWithdrawalCanceller);
+
+            let mut withdrawal = pool.pending_withdrawals.get_mut(&withdrawal_id)
+                .expect(EscrowError::WithdrawalNotFound.as_str());
+            assert!(withdrawal.open, "{}", EscrowError::WithdrawalClosed);
+            withdrawal.open = false;
+            let funds = withdrawal.vault.take_all();
+            drop(withdrawal);
+            // The funds never left, so they no longer count against
+            // the savings locks
+            let resource = funds.resource_address();
+            pool.return_under_savings_locks(&resource, funds.amount());
+            pool.vaults.get_mut(&resource).unwrap().put(funds);
+
+            Runtime::emit_event(WithdrawalCancelledEvent {
+                owner: owner.clone(),
+                withdrawal_id,
+                canceller,
+            });
         }
 
         //
@@ -886,134 +3842,284 @@ mod escrow {
         /// calling this function it could be gone.
         ///
         /// This function does not do the actual withdrawing, the
-        /// caller has to see to that.
+        /// caller has to see to that. To help it do so we return the
+        /// allowance's data as it was before this use.
+        ///
+        /// Set `subsidy` if the funds are to be locked for fees rather
+        /// than withdrawn, so that the allowance's usage counts them
+        /// as such.
         ///
         /// NOTE we do *not* check that the allowance has the correct
         /// resource address for the pool as we have no information on
         /// the pool. This must have been already checked by the
         /// calling party.
-        fn use_allowance(&self, allowance: Bucket, amount: TokenQuantity)
-                         -> (NonFungibleGlobalId, ResourceAddress, Option<Bucket>)
+        fn use_allowance(&mut self, allowance: Bucket, amount: TokenQuantity, subsidy: bool)
+                         -> (AllowanceNfData, Option<Bucket>)
         {
+            let allowance_mgr = ResourceManager::from(allowance.resource_address());
+            let allowance_id = allowance.as_non_fungible().non_fungible_local_id();
             let nfdata: AllowanceNfData =
-                ResourceManager::from(allowance.resource_address())
-                .get_non_fungible_data(
-                    &allowance.as_non_fungible().non_fungible_local_id());
-            let mut burn = false;
-            let owner_nfgid = nfdata.escrow_pool.1;
-            let withdraw_resource = nfdata.for_resource;
+                allowance_mgr.get_non_fungible_data(&allowance_id);
+
+            assert!(Runtime::global_address() == nfdata.escrow_pool.0,
+                    "{}", EscrowError::AllowanceNotForEscrow);
+
+            if let Err(reason) = nfdata.check_per_use(&amount) {
+                panic!("{}", reason);
+            }
+            if nfdata.needs_co_signer(amount.to_amount()) {
+                let approver = nfdata.co_signer.as_ref().unwrap().approver.clone();
+                Runtime::assert_access_rule(rule!(require(approver)));
+            }
+            if nfdata.max_percentage.is_some() {
+                let allowance_nfgid =
+                    NonFungibleGlobalId::new(allowance.resource_address(), allowance_id.clone());
+                let cap = self.with_allowance_funds(&allowance_nfgid, &nfdata,
+                                                    |vault, _| nfdata.percentage_of(vault))
+                    .unwrap_or_else(|error| panic!("{}", error))
+                    .unwrap();
+                assert!(amount.to_amount() <= cap,
+                        "{}", EscrowError::AbovePercentageOfBalance);
+            }
 
-            let (take_nflids, take_amount) = amount.extract_max_values();
+            // Stored ids are paid for by the store, not by max_amount
+            let stored = self.free_stored_ids(&allowance_id, &nfdata, &amount);
+            let amount = amount.without_nflids(&stored);
 
-            assert_eq!(Runtime::global_address(), nfdata.escrow_pool.0,
-                       "allowance is not for this escrow");
+            let now = Now::from_ledger();
+            if let Err(reason) = nfdata.check_use(&amount, &now) {
+                panic!("{}", reason);
+            }
 
-            let now = unix_time_now();
-            assert!(nfdata.valid_from <= now,
-                    "2009 allowance not yet valid");
-            assert!(nfdata.valid_until.is_none()
-                    || nfdata.valid_until.unwrap() >= now,
-                    "2011 allowance no longer valid");
-
-            // Check that the allowance is big enough for this
-            // withdrawal.
-            match nfdata.max_amount {
-                Some(TokenQuantity::NonFungible(ref max_nflids, max_amount)) => {
-                    let mut already_taken = 0;
-                    if let Some(take_nflids) = &take_nflids {
-                        if let Some(max_nflids) = max_nflids {
-                            // The NFTs we can take out of max_nflids
-                            // shouldn't count towards the max_amount
-                            // further down.
-                            already_taken = take_nflids.intersection(max_nflids).count();
+            // Update the allowance to reflect the withdrawal
+            // indicated, burning it if it's now spent.
+            let update = nfdata.after_use(&amount, stored.len() as u64, subsidy, &now);
+            if update.stored_ids.is_some() {
+                let owner = self.pool_owner(&nfdata.escrow_pool.1);
+                let mut pool = self.pools.get_mut(&owner)
+                    .expect(EscrowError::PoolNotFound.as_str());
+                let mut ids = pool.allowance_ids.get_mut(&allowance_id).unwrap();
+                for nflid in &stored {
+                    *ids.get_mut(nflid).unwrap() = true;
+                }
+            }
+            if update.burn {
+                allowance.burn();
+                return (nfdata, None)
+            }
+            if let Some(stored_ids) = update.stored_ids {
+                allowance_mgr.update_non_fungible_data(&allowance_id,
+                                                       "stored_ids",
+                                                       Some(stored_ids));
+            }
+            if let Some(max_amount) = update.max_amount {
+                allowance_mgr.update_non_fungible_data(&allowance_id,
+                                                       "max_amount",
+                                                       Some(max_amount));
+            }
+            if let Some(valid_from) = update.valid_from {
+                allowance_mgr.update_non_fungible_data(&allowance_id,
+                                                       "valid_from",
+                                                       valid_from);
+            }
+            allowance_mgr.update_non_fungible_data(&allowance_id,
+                                                   "usage",
+                                                   update.usage);
+
+            (nfdata, Some(allowance))
+        }
+
+        /// Finds those of the nflids asked for in `quantity` that are
+        /// stored for the Allowance `allowance` and haven't yet been
+        /// taken.
+        fn free_stored_ids(&self,
+                           allowance: &NonFungibleLocalId,
+                           nfdata: &AllowanceNfData,
+                           quantity: &TokenQuantity) -> IndexSet<NonFungibleLocalId>
+        {
+            let mut free = IndexSet::new();
+            if nfdata.stored_ids.is_none() { return free }
+            let (nflids, _) = quantity.extract_max_values();
+            if let Some(nflids) = nflids {
+                let owner = self.pool_owner(&nfdata.escrow_pool.1);
+                let pool = self.pools.get(&owner)
+                    .expect(EscrowError::PoolNotFound.as_str());
+                if let Some(ids) = pool.allowance_ids.get(allowance) {
+                    for nflid in nflids.iter() {
+                        if ids.get(&nflid).map_or(false, |taken| !*taken) {
+                            free.insert(nflid);
                         }
                     }
-                    let to_take = take_amount.unwrap_or_default()
-                        + length_of_option_set(&take_nflids) - already_taken;
-                    assert!(to_take <= max_amount.unwrap_or_default().into(),
-                            "2012 insufficient allowance");
-                },
-                Some(TokenQuantity::Fungible(max_amount)) => {
-                    assert!(take_amount.unwrap_or_default() + length_of_option_set(&take_nflids)
-                            <= max_amount,
-                            "2010 insufficient allowance");
-                },
-                None => {
-                    // This means there is no limit
-                },
+                }
+            }
+            free
+        }
+
+        /// Works out how many tokens the Allowance `allowance` can
+        /// draw on right now, given the funds in its pool (or its
+        /// reservation), its priority floor and its percentage
+        /// limit. Also returns the
+        /// reason why taking `quantity` from those funds would fail,
+        /// if it would.
+        fn funds_for_allowance(&self,
+                               allowance: &NonFungibleGlobalId,
+                               nfdata: &AllowanceNfData,
+                               quantity: &TokenQuantity) -> (Decimal, Option<EscrowError>)
+        {
+            self.with_allowance_funds(allowance, nfdata, |vault, floor| {
+                let available = std::cmp::max(vault.amount() - floor, Decimal::ZERO);
+                let cap = nfdata.percentage_of(vault);
+                let available = std::cmp::min(available, cap.unwrap_or(Decimal::MAX));
+                let (take_nflids, _) = quantity.extract_max_values();
+                let failure =
+                    if quantity.to_amount() > vault.amount()
+                        || take_nflids.map_or(false, |nflids| nflids.iter()
+                            .any(|nflid| !vault.as_non_fungible().contains_non_fungible(&nflid)))
+                    {
+                        Some(EscrowError::FundsNotAvailable)
+                    } else if vault.amount() - quantity.to_amount() < floor {
+                        Some(EscrowError::BelowPriorityFloor)
+                    } else if cap.map_or(false, |cap| quantity.to_amount() > cap) {
+                        Some(EscrowError::AbovePercentageOfBalance)
+                    } else { None };
+                (available, failure)
+            }).unwrap_or_else(|error| (Decimal::ZERO, Some(error)))
+        }
+
+        /// Works out exactly which tokens `quantity` asks for out of
+        /// the funds that the Allowance `allowance` draws on, never
+        /// picking NFTs that the Allowance excludes. Also returns how
+        /// many NFTs couldn't be found. See [TokenQuantity::resolve].
+        fn resolve_for_allowance(&self,
+                                 allowance: &NonFungibleGlobalId,
+                                 nfdata: &AllowanceNfData,
+                                 quantity: &TokenQuantity)
+                                 -> Result<(TokenQuantity, u64), EscrowError>
+        {
+            let avoid = nfdata.max_amount.as_ref().and_then(|max| max.excluded_ids());
+            if !quantity.needs_resolving(avoid) {
+                return Ok((quantity.clone(), 0))
             }
+            self.with_allowance_funds(allowance, nfdata,
+                                      |vault, floor| quantity.resolve(vault, floor, avoid))
+        }
 
-            // Update the allowance to reflect the withdrawal
-            // indicated.
-            match nfdata.life_cycle {
-                AllowanceLifeCycle::OneOff => {
-                    burn = true;
+        /// Cuts `quantity` down to what the Allowance `allowance` can
+        /// actually take right now, considering both its own limits
+        /// and the funds available to it. Returns the reduced
+        /// quantity, along with those of the requested non-fungible
+        /// local ids that had to be dropped from it.
+        ///
+        /// This does not consider whether the Allowance is currently
+        /// valid, only how much it could take if it is.
+        fn fit_to_allowance(&self,
+                            allowance: &NonFungibleGlobalId,
+                            nfdata: &AllowanceNfData,
+                            quantity: &TokenQuantity)
+                            -> (TokenQuantity, IndexSet<NonFungibleLocalId>)
+        {
+            // Only try for NFTs that the Allowance doesn't exclude
+            let (quantity, _) = self.resolve_for_allowance(allowance, nfdata, quantity)
+                .unwrap_or_else(|error| panic!("{}", error));
+            let quantity = &quantity;
+
+            // Named nflids in allowed_nflids or among the stored ids
+            // are free to take, excluded nflids can never be taken,
+            // and all other tokens count against the budget. A budget
+            // of None means there is no limit.
+            let stored = self.free_stored_ids(allowance.local_id(), nfdata, quantity);
+            let (allowed_nflids, excluded, budget) = match &nfdata.max_amount {
+                None | Some(TokenQuantity::All) => (None, None, None),
+                Some(TokenQuantity::Fungible(max_amount)) => (None, None, Some(*max_amount)),
+                Some(TokenQuantity::NonFungibleExcept(excluded, max_amount)) =>
+                    (None, Some(excluded), max_amount.map(Decimal::from)),
+                Some(max_quantity) => {
+                    let (max_nflids, max_amount) = max_quantity.extract_max_values();
+                    (max_nflids, None, Some(max_amount.unwrap_or_default()))
                 },
-                AllowanceLifeCycle::Accumulating => {
-                    match nfdata.max_amount {
-                        Some(TokenQuantity::Fungible(max_amount)) => {
-                            let new_max_amount = max_amount
-                                - take_amount.unwrap_or_default()
-                                - length_of_option_set(&take_nflids);
-                            if new_max_amount.is_zero() { burn = true } else {
-                                ResourceManager::from(allowance.resource_address())
-                                    .update_non_fungible_data(
-                                        &allowance.as_non_fungible().non_fungible_local_id(),
-                                        "max_amount",
-                                        Some(TokenQuantity::Fungible(new_max_amount)));
-                            }
-                        },
-                        Some(TokenQuantity::NonFungible(max_nflids, max_amount)) => {
-                            let new_max_nflids: Option<IndexSet<NonFungibleLocalId>>;
-                            if let Some(take_nflids) = take_nflids {
-                                new_max_nflids = Some(
-                                    max_nflids.unwrap().difference(&take_nflids).cloned().collect());
-                            } else {
-                                new_max_nflids = max_nflids;
-                            }
-                            let new_max_amount: Option<u64>;
-                            if let Some(take_amount) = take_amount {
-                                new_max_amount = Some(max_amount.unwrap() - u64::try_from(take_amount)
-                                                  .expect("amount to take must be whole number"));
-                            } else {
-                                new_max_amount = max_amount;
-                            }
-                            if new_max_amount.unwrap_or_default() == 0 && length_of_option_set(&new_max_nflids) == 0 {
-                                // No tokens left in allowance
-                                burn = true;
+            };
+
+            self.with_allowance_funds(allowance, nfdata, |vault, floor| {
+                let mut budget = budget;
+                // This is how much we can take before hitting the
+                // floor, or the most a single use may take
+                let mut room = std::cmp::min(std::cmp::max(vault.amount() - floor, Decimal::ZERO),
+                                             nfdata.max_per_use.unwrap_or(Decimal::MAX));
+                room = std::cmp::min(room, nfdata.percentage_of(vault).unwrap_or(Decimal::MAX));
+                match quantity {
+                    TokenQuantity::Fungible(amount) => {
+                        let divisibility = ResourceManager::from(vault.resource_address())
+                            .resource_type().divisibility().unwrap_or(0);
+                        let take = std::cmp::min(std::cmp::min(*amount, room),
+                                                 budget.unwrap_or(*amount))
+                            .checked_round(divisibility, RoundingMode::ToZero).unwrap();
+                        (TokenQuantity::Fungible(take), IndexSet::new())
+                    },
+                    _ => {
+                        let (nflids, amount) = quantity.extract_max_values();
+                        let mut taken = IndexSet::new();
+                        let mut missing = IndexSet::new();
+                        for nflid in nflids.iter().flat_map(|nflids| nflids.iter()) {
+                            let free = stored.contains(&nflid)
+                                || allowed_nflids.as_ref()
+                                .map_or(false, |allowed| allowed.contains(&nflid));
+                            if room >= Decimal::ONE
+                                && !excluded.map_or(false, |excluded| excluded.contains(&nflid))
+                                && (free || budget.map_or(true, |budget| budget >= Decimal::ONE))
+                                && vault.as_non_fungible().contains_non_fungible(&nflid)
+                            {
+                                room -= Decimal::ONE;
+                                if !free {
+                                    budget = budget.map(|budget| budget - Decimal::ONE);
+                                }
+                                taken.insert(nflid);
                             } else {
-                                ResourceManager::from(allowance.resource_address())
-                                    .update_non_fungible_data(
-                                        &allowance.as_non_fungible().non_fungible_local_id(),
-                                        "max_amount",
-                                        Some(TokenQuantity::NonFungible(new_max_nflids, new_max_amount)));
+                                missing.insert(nflid);
                             }
-                            
-                        },
-                        None => {
-                            burn = true;
-                        },
-                    }
-                },
-                AllowanceLifeCycle::Repeating{min_delay} => {
-                    if let Some(min_delay) = min_delay {
-                        ResourceManager::from(allowance.resource_address())
-                            .update_non_fungible_data(
-                                &allowance.as_non_fungible().non_fungible_local_id(),
-                                "valid_from",
-                                now + min_delay);
-                    }
+                        }
+                        let amount = amount.map(|amount| {
+                            let take = std::cmp::min(std::cmp::min(amount, room),
+                                                     budget.unwrap_or(amount));
+                            dec_to_u64(take.checked_round(0, RoundingMode::ToZero).unwrap())
+                        });
+                        let taken = if taken.is_empty() { None } else { Some(taken) };
+                        (TokenQuantity::NonFungible(taken, amount), missing)
+                    },
                 }
+            }).unwrap_or_else(|error| panic!("{}", error))
+        }
+
+        /// Runs `operation` on the vault holding the funds that the
+        /// Allowance `allowance` draws on, also passing it the
+        /// priority floor that applies to that vault. This is the
+        /// Allowance's reservation if it has one, otherwise it is the
+        /// pool vault for its resource.
+        fn with_allowance_funds<F, R>(&self,
+                                      allowance: &NonFungibleGlobalId,
+                                      nfdata: &AllowanceNfData,
+                                      operation: F) -> Result<R, EscrowError>
+        where F: FnOnce(&Vault, Decimal) -> R
+        {
+            if nfdata.escrow_pool.0 != Runtime::global_address() {
+                return Err(EscrowError::AllowanceNotForEscrow)
+            }
+            let owner = self.pool_owner(&nfdata.escrow_pool.1);
+            let pool = self.pools.get(&owner).ok_or(EscrowError::PoolNotFound)?;
+            if pool.allowance_badge_res != allowance.resource_address() {
+                return Err(EscrowError::AllowanceNotForPool)
             }
 
-            // Burn the allowance NFT if it's now spent
-            let mut allowance = Some(allowance);
-            if burn {
-                allowance.unwrap().burn();
-                allowance = None;
+            if let Some(reserved) = pool.reservations.get(allowance.local_id()) {
+                Ok(operation(&reserved, Decimal::ZERO))
+            } else if let Some(vault) = pool.vaults.get(&nfdata.for_resource) {
+                let floor = pool.priority_floors
+                    .get(&(nfdata.for_resource, nfdata.priority))
+                    .map(|floor| *floor)
+                    .unwrap_or_default();
+                Ok(operation(&vault, floor))
+            } else {
+                Err(EscrowError::ResourceNotFound)
             }
-            
-            (owner_nfgid, withdraw_resource, allowance)
         }
 
         /// Determines if this nfgid is trusted to receive automatic
@@ -1051,34 +4157,252 @@ mod escrow {
         /// to vaults. It *must* have come out of a proof, or out of a
         /// bucket, or otherwise from a trusted source.
         ///
-        /// If 'allowance_resaddr' is present then authority to
-        /// operate on the vault came from an allowance NFT with that
-        /// resource address. This function asserts that that is
-        /// indeed the resource address of the pool's allowance NFTs.
+        /// If 'allowance' is present then authority to operate on
+        /// the vault came from that allowance NFT, whose data is
+        /// also given. This function asserts that it is indeed one of
+        /// the pool's allowance NFTs. If the allowance has funds
+        /// reserved for it then `operation` is given the vault
+        /// holding those instead of the pool vault. Otherwise we
+        /// assert that the operation didn't take the pool vault below
+        /// the floor for the allowance's priority tier.
+        ///
+        /// If `allowance` is not present then it is the pool owner
+        /// taking the funds, and we assert that the pool's savings
+        /// locks and withdrawal delay allow it.
         fn operate_on_vault<F>(&mut self,
                                owner: &NonFungibleGlobalId,
                                resource: &ResourceAddress,
-                               allowance_resaddr: Option<ResourceAddress>,
+                               allowance: Option<(&NonFungibleGlobalId, &AllowanceNfData)>,
                                operation: F) 
                                -> Option<Bucket>
         where F: Fn(KeyValueEntryRefMut<Vault>) -> Option<Bucket>
         {
-            if let Some(mut pool) = self.pools.get_mut(&owner) {
-                if let Some(allowance_resaddr) = allowance_resaddr {
-                    assert_eq!(pool.allowance_badge_res,
-                               allowance_resaddr,
-                               "allowance is not for this pool");
+            if let Some(mut pool_entry) = self.pools.get_mut(&owner) {
+                let pool: &mut Pool = &mut pool_entry;
+                if let Some((allowance, _)) = allowance {
+                    assert!(pool.allowance_badge_res == allowance.resource_address(),
+                            "{}", EscrowError::AllowanceNotForPool);
+                    if let Some(reserved) = pool.reservations.get_mut(allowance.local_id()) {
+                        let before = reserved.amount();
+                        let result = operation(reserved);
+                        let used = before
+                            - pool.reservations.get(allowance.local_id()).unwrap().amount();
+                        *pool.reserved_amounts.get_mut(resource).unwrap() -= used;
+                        return result
+                    }
                 }
                 if let Some(vault) = pool.vaults.get_mut(&resource) {
-                    operation(vault)
+                    let before = vault.amount();
+                    let result = operation(vault);
+                    let after = pool.vaults.get(&resource).unwrap().amount();
+                    if let Some((_, nfdata)) = allowance {
+                        if let Some(floor) = pool.priority_floors.get(&(*resource, nfdata.priority)) {
+                            assert!(after >= *floor,
+                                    "{}", EscrowError::BelowPriorityFloor);
+                        }
+                    } else {
+                        pool.take_as_owner(resource, before - after);
+                    }
+                    result
                 } else {
-                    panic!("resource not found")
+                    panic!("{}", EscrowError::ResourceNotFound)
                 }
             } else {
-                panic!("pool not found")
+                panic!("{}", EscrowError::PoolNotFound)
+            }
+        }
+
+        /// Puts `funds` into the pool owned by `owner`, creating the
+        /// pool and its vault for that resource if necessary.
+        fn put_in_pool(&mut self,
+                       owner: &NonFungibleGlobalId,
+                       funds: Bucket)
+        {
+            let owner = self.pool_owner(owner);
+            let mut pool = self.get_or_add_pool(&owner);
+            let resource = funds.resource_address();
+            // Create this resource vault if we don't have it already.
+            if pool.vaults.get(&resource).is_none() {
+                pool.vaults.insert(resource, Vault::with_bucket(funds));
+            } else {
+                pool.vaults.get_mut(&resource).unwrap().put(funds);
+            }
+        }
+
+        /// Puts `funds` that the owner of a pool took out of it, but
+        /// that were never paid out, back into that pool. They then
+        /// no longer count against its savings locks or withdrawal
+        /// threshold.
+        fn return_to_pool(&mut self,
+                          owner: &NonFungibleGlobalId,
+                          funds: Bucket)
+        {
+            let owner = self.pool_owner(owner);
+            if let Some(mut pool) = self.pools.get_mut(&owner) {
+                pool.return_as_owner(&funds.resource_address(), funds.amount());
+            }
+            self.put_in_pool(&owner, funds);
+        }
+
+        /// Finds the pool owner badge in `owner` and records the call
+        /// as activity on their pool, if they have one. Use this
+        /// wherever a pool owner authenticates, so that their
+        /// beneficiaries can't claim the pool while they're still
+        /// around.
+        fn authenticate_owner(&mut self, owner: Proof) -> NonFungibleGlobalId {
+            let owner = unchecked_proof_to_nfgid(owner);
+            if let Some(mut pool) = self.pools.get_mut(&owner) {
+                pool.last_active = unix_time_now();
+            }
+            owner
+        }
+
+        /// Moves the pool owned by `owner`, with everything in it, to
+        /// `new_owner`, and makes `new_owner` the one who can recall
+        /// the pool's Allowances. Panics if `new_owner` has or has had
+        /// a pool of their own.
+        fn move_pool(&mut self,
+                     owner: &NonFungibleGlobalId,
+                     new_owner: &NonFungibleGlobalId)
+        {
+            assert!(self.pools.get(new_owner).is_none()
+                    && self.moved_pools.get(new_owner).is_none(),
+                    "{}", EscrowError::NewOwnerHasPool);
+
+            let mut pool = self.pools.remove(owner).unwrap();
+            pool.recovery = None;
+            ResourceManager::from(pool.allowance_badge_res)
+                .set_recallable(rule!(require(new_owner.clone())));
+            self.pools.insert(new_owner.clone(), pool);
+            self.moved_pools.insert(owner.clone(), new_owner.clone());
+        }
+
+        /// Finds who owns the pool that was once owned by `owner`,
+        /// following any recoveries that have moved it since. Only
+        /// use this for references to a pool that were made earlier,
+        /// never to authenticate an owner. When checking a caller
+        /// against such a reference, compare the caller to what this
+        /// returns: that way the new owner is let through and the old
+        /// one, which is never returned, is not.
+        fn pool_owner(&self, owner: &NonFungibleGlobalId) -> NonFungibleGlobalId {
+            let mut owner = owner.clone();
+            loop {
+                let moved_to = self.moved_pools.get(&owner).map(|new_owner| new_owner.clone());
+                match moved_to {
+                    Some(new_owner) => owner = new_owner,
+                    None => return owner,
+                }
+            }
+        }
+
+        /// Moves any funds reserved for the Allowance `allowance_id`
+        /// back into the pool owned by `owner`.
+        fn release_reserved_funds(&mut self,
+                                  owner: &NonFungibleGlobalId,
+                                  allowance_id: &NonFungibleLocalId)
+        {
+            let mut pool_entry =
+                self.pools.get_mut(owner).expect(EscrowError::PoolNotFound.as_str());
+            let pool: &mut Pool = &mut pool_entry;
+            if let Some(mut reserved) = pool.reservations.get_mut(allowance_id) {
+                let resource = reserved.resource_address();
+                *pool.reserved_amounts.get_mut(&resource).unwrap() -= reserved.amount();
+                pool.vaults.get_mut(&resource).unwrap().put(reserved.take_all());
+            }
+        }
+
+        /// Sends `funds` to `recipient`. If the recipient is an
+        /// account that won't accept them, the funds are returned.
+        fn pay_recipient(&mut self,
+                         recipient: PaymentRecipient,
+                         funds: Bucket) -> Option<Bucket>
+        {
+            match recipient {
+                PaymentRecipient::EscrowPool(escrow, owner) => {
+                    if escrow == Runtime::global_address() {
+                        // Calling ourselves through the global
+                        // address would be a reentrancy error.
+                        self.put_in_pool(&owner, funds);
+                    } else {
+                        let escrow: Global<Escrow> = Global::from(escrow);
+                        escrow.deposit_funds(owner, funds, None);
+                    }
+                    None
+                },
+                PaymentRecipient::Account(account) => {
+                    let account: Global<Account> = Global::from(account);
+                    account.try_deposit(funds, None)
+                },
             }
         }
 
+        /// Takes `funds` that the owner of a pool has taken out of it
+        /// and holds on to them as a withdrawal request, to be paid
+        /// to `recipient` once the pool's withdrawal delay has passed.
+        ///
+        /// Returns the id of the withdrawal request.
+        fn queue_withdrawal(&mut self,
+                            owner: &NonFungibleGlobalId,
+                            funds: Bucket,
+                            recipient: PaymentRecipient) -> u64
+        {
+            let mut pool = self.pools.get_mut(owner).unwrap();
+            let delay = pool.withdrawal_delay.as_ref().unwrap().delay;
+            let withdrawal_id = pool.next_withdrawal_id;
+            pool.next_withdrawal_id += 1;
+            let finalizes_at = unix_time_now() + delay;
+
+            Runtime::emit_event(WithdrawalRequestedEvent {
+                owner: owner.clone(),
+                withdrawal_id,
+                resource: funds.resource_address(),
+                amount: funds.amount(),
+                finalizes_at,
+            });
+
+            pool.pending_withdrawals.insert(
+                withdrawal_id,
+                PendingWithdrawal {
+                    recipient,
+                    finalizes_at,
+                    open: true,
+                    vault: Vault::with_bucket(funds),
+                });
+
+            withdrawal_id
+        }
+
+        /// Retrieves the stream `stream_id` of the pool owned by
+        /// `owner`, panicking if that stream has been cancelled.
+        fn get_open_stream(&mut self,
+                           owner: &NonFungibleGlobalId,
+                           stream_id: u64) -> KeyValueEntryRefMut<Stream>
+        {
+            let pool = self.pools.get_mut(owner).expect(EscrowError::PoolNotFound.as_str());
+            let stream = pool.streams.get_mut(&stream_id)
+                .expect(EscrowError::StreamNotFound.as_str());
+            assert!(stream.open, "{}", EscrowError::StreamClosed);
+            stream
+        }
+
+        /// Retrieves the data of the Deal with receipt `deal_id`,
+        /// panicking if that Deal is no longer open.
+        fn get_open_deal(&self, deal_id: &NonFungibleLocalId) -> DealNfData
+        {
+            let deal: DealNfData = ResourceManager::from(self.deal_receipt_res)
+                .get_non_fungible_data(deal_id);
+            assert!(deal.state == DealState::Open,
+                    "{}", EscrowError::DealNotOpen);
+            deal
+        }
+
+        /// Records the new `state` of the Deal with receipt `deal_id`.
+        fn set_deal_state(&self, deal_id: &NonFungibleLocalId, state: DealState)
+        {
+            ResourceManager::from(self.deal_receipt_res)
+                .update_non_fungible_data(deal_id, "state", state);
+        }
+
         /// Retrieves the pool corresponding to the input
         /// `owner_nfgid`, creating one if necessary.
         fn get_or_add_pool(&mut self,
@@ -1102,10 +4426,12 @@ mod escrow {
                         minter_updater => rule!(deny_all);))
 
                 // The escrow pool owner can recall any allowances
-                // that have been issued for the pool.
+                // that have been issued for the pool. We hand this
+                // over to the new owner if the pool is recovered.
                     .recall_roles(recall_roles!(
                         recaller => rule!(require(owner_nfgid.clone()));
-                        recaller_updater => rule!(deny_all);
+                        recaller_updater =>
+                            rule!(require(global_caller(Runtime::global_address())));
                     ))
 
                 // Anyone can burn the allowances they have been
@@ -1132,12 +4458,176 @@ mod escrow {
                         trusted_nfgids: KeyValueStore::new(),
                         trusted_res: KeyValueStore::new(),
                         vaults: KeyValueStore::new(),
+                        standing_orders: KeyValueStore::new(),
+                        active_standing_orders: Vec::new(),
+                        next_standing_order_id: 0,
+                        streams: KeyValueStore::new(),
+                        next_stream_id: 0,
+                        reservations: KeyValueStore::new(),
+                        reserved_amounts: KeyValueStore::new(),
+                        priority_floors: KeyValueStore::new(),
+                        allowance_ids: KeyValueStore::new(),
+                        guardians: None,
+                        recovery: None,
+                        last_active: unix_time_now(),
+                        inheritance: None,
+                        inheritance_claimed: IndexSet::new(),
+                        savings_locks: KeyValueStore::new(),
+                        savings_lock_keys: IndexSet::new(),
+                        savings_taken: KeyValueStore::new(),
+                        withdrawal_delay: None,
+                        delay_taken: KeyValueStore::new(),
+                        pending_withdrawals: KeyValueStore::new(),
+                        next_withdrawal_id: 0,
                     });
             }
 
             self.pools.get_mut(owner_nfgid).unwrap()
         }
 
+        /// Validates the Allowance parameters given and mints an
+        /// Allowance for the pool owned by `owner`, creating that
+        /// pool if necessary.
+        ///
+        /// Note that `owner` must have been authenticated before
+        /// calling this function.
+        fn mint_allowance_for_pool(&mut self,
+                                   owner: &NonFungibleGlobalId,
+                                   valid_until: Option<TimeBound>,
+                                   valid_from: TimeBound,
+                                   life_cycle: AllowanceLifeCycle,
+                                   for_resource: ResourceAddress,
+                                   max_quantity: Option<TokenQuantity>,
+                                   options: AllowanceOptions) -> Bucket
+        {
+            if options.reserved {
+                assert!(max_quantity.is_some(),
+                        "{}", EscrowError::ReservationWithoutMax);
+                assert!(!matches!(life_cycle,
+                                  AllowanceLifeCycle::Repeating{..}
+                                  | AllowanceLifeCycle::Scheduled{..}),
+                        "{}", EscrowError::RepeatingReservation);
+            }
+            if let AllowanceLifeCycle::Scheduled{schedule} = &life_cycle {
+                assert!(schedule.is_valid(), "{}", EscrowError::InvalidSchedule);
+            }
+            assert!(options.max_uses != Some(0), "{}", EscrowError::ZeroMaxUses);
+            for limit in options.min_per_use.iter().chain(options.max_per_use.iter()) {
+                assert!(!limit.is_negative()
+                        && (for_resource.is_fungible()
+                            || limit.checked_round(0, RoundingMode::ToZero) == Some(*limit)),
+                        "{}", EscrowError::InvalidPerUseLimits);
+            }
+            if let (Some(min), Some(max)) = (options.min_per_use, options.max_per_use) {
+                assert!(min <= max, "{}", EscrowError::InvalidPerUseLimits);
+            }
+            if let (Some(min), Some(max_quantity)) = (options.min_per_use, &max_quantity) {
+                assert!(min <= max_quantity.to_amount(), "{}", EscrowError::InvalidPerUseLimits);
+            }
+            if let Some(percentage) = options.max_percentage {
+                assert!(percentage.is_positive() && percentage <= dec!("100"),
+                        "{}", EscrowError::InvalidPercentage);
+            }
+            if let Some(co_signer) = &options.co_signer {
+                assert!(!co_signer.threshold.is_negative(),
+                        "{}", EscrowError::NegativeCoSignerThreshold);
+            }
+            if let Some(max_quantity) = &max_quantity {
+                let amount;
+                match max_quantity {
+                    TokenQuantity::NonFungible(_, max_amount) => {
+                        amount = max_amount.map(|v|Decimal::from(v))
+                    },
+                    TokenQuantity::NonFungibleRanges(ranges, max_amount) => {
+                        assert!(ranges.is_well_formed(), "{}", EscrowError::MalformedIdRanges);
+                        amount = max_amount.map(|v|Decimal::from(v))
+                    },
+                    TokenQuantity::Fungible(max_amount) => {
+                        amount = Some(*max_amount)
+                    },
+                    TokenQuantity::All => {
+                        amount = None
+                    },
+                    TokenQuantity::NonFungibleExcept(excluded, max_amount) => {
+                        assert!(!for_resource.is_fungible(),
+                                "{}", EscrowError::NonFungibleQuantityForFungible);
+                        assert!(excluded.is_well_formed(), "{}", EscrowError::MalformedIdRanges);
+                        amount = max_amount.map(|v|Decimal::from(v))
+                    },
+                }
+                assert!(!amount.unwrap_or_default().is_negative(),
+                        "{}", EscrowError::NegativeMaxAmount);
+            }
+
+            // Take the reserved funds out of the pool before minting,
+            // since max_quantity goes into the Allowance.
+            let reserved_funds =
+                if options.reserved {
+                    let max_quantity = max_quantity.clone().unwrap();
+                    Some(self.operate_on_vault(owner,
+                                               &for_resource,
+                                               None,
+                                               |mut v| Some(max_quantity.take_from_vault(&mut v)))
+                         .unwrap())
+                } else { None };
+
+            // Stored ids are moved out of max_quantity and into the
+            // component.
+            let mut max_quantity = max_quantity;
+            let mut stored_nflids = None;
+            if options.stored_ids {
+                match max_quantity {
+                    Some(TokenQuantity::NonFungible(nflids, amount)) => {
+                        stored_nflids = Some(nflids.unwrap_or_default());
+                        max_quantity = Some(TokenQuantity::NonFungible(None, amount));
+                    },
+                    _ => panic!("{}", EscrowError::StoredIdsNotNonFungible),
+                }
+            }
+            let stored_ids = stored_nflids.as_ref().map(|nflids| StoredIds {
+                permitted: nflids.len() as u64,
+                consumed: 0,
+            });
+
+            let pool_mgr = ResourceManager::from(
+                self.get_or_add_pool(owner).allowance_badge_res);
+
+            let allowance = self.create_allowance(
+                (Runtime::global_address(), owner.clone()),
+                pool_mgr,
+                valid_until,
+                valid_from,
+                life_cycle,
+                for_resource,
+                max_quantity,
+                stored_ids,
+                options);
+
+            if let Some(stored_nflids) = stored_nflids {
+                let ids = KeyValueStore::new();
+                for nflid in stored_nflids {
+                    ids.insert(nflid, false);
+                }
+                self.get_or_add_pool(owner).allowance_ids.insert(
+                    allowance.as_non_fungible().non_fungible_local_id(), ids);
+            }
+
+            if let Some(reserved_funds) = reserved_funds {
+                let mut pool_entry = self.get_or_add_pool(owner);
+                let pool: &mut Pool = &mut pool_entry;
+                let amount = reserved_funds.amount();
+                if pool.reserved_amounts.get(&for_resource).is_none() {
+                    pool.reserved_amounts.insert(for_resource, amount);
+                } else {
+                    *pool.reserved_amounts.get_mut(&for_resource).unwrap() += amount;
+                }
+                pool.reservations.insert(allowance.as_non_fungible().non_fungible_local_id(),
+                                         Vault::with_bucket(reserved_funds));
+            }
+
+            allowance
+        }
+
         /// Creates an Allowance NFT for `escrow_pool` using
         /// `pool_mgr` to do so, and initizalizing it with the given
         /// values.
@@ -1147,11 +4637,13 @@ mod escrow {
         fn create_allowance(&self,
                             escrow_pool: (ComponentAddress, NonFungibleGlobalId),
                             pool_mgr: ResourceManager,
-                            valid_until: Option<i64>,
-                            valid_from: i64,
+                            valid_until: Option<TimeBound>,
+                            valid_from: TimeBound,
                             life_cycle: AllowanceLifeCycle,
                             for_resource: ResourceAddress,
-                            max_amount: Option<TokenQuantity>) -> Bucket
+                            max_amount: Option<TokenQuantity>,
+                            stored_ids: Option<StoredIds>,
+                            options: AllowanceOptions) -> Bucket
         {
             pool_mgr
                 .mint_ruid_non_fungible(
@@ -1161,7 +4653,23 @@ mod escrow {
                         valid_from,
                         life_cycle,
                         for_resource,
-                        max_amount
+                        max_amount,
+                        reserved: options.reserved,
+                        priority: options.priority,
+                        revoked: false,
+                        stored_ids,
+                        max_uses: options.max_uses,
+                        min_per_use: options.min_per_use,
+                        max_per_use: options.max_per_use,
+                        max_percentage: options.max_percentage,
+                        co_signer: options.co_signer,
+                        usage: AllowanceUsage {
+                            total_withdrawn: Decimal::ZERO,
+                            total_subsidy: Decimal::ZERO,
+                            use_count: 0,
+                            last_used: None,
+                            since_co_signed: Decimal::ZERO,
+                        },
                     }
                 )
         }
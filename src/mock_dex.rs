@@ -6,9 +6,11 @@ use scrypto::prelude::*;
 
 use radix_engine_common::ManifestSbor;
 use crate::escrow::Escrow;
-use crate::AllowanceNfData;
+use crate::{AllowanceNfData, AllowanceStatus};
 use crate::TokenQuantity;
 use crate::util::proof_to_nfgid;
+use crate::time_bound::Now;
+use crate::errors::EscrowError;
 
 /// Describes a trader and, optionally, that trader's Escrow
 /// component.
@@ -115,7 +117,7 @@ mod mock_dex {
                                 price_in_xrd: Decimal,
                                 escrow_payout_component: Option<ComponentAddress>,
                                 payment: FungibleBucket) {
-            assert!(payment.resource_address() == XRD, "Only XRD payment allowed for buy");
+            assert!(payment.resource_address() == XRD, "{}", EscrowError::MockDexOnlyXrd);
             let trader = trader.skip_checking();
             self.buy_book.insert(price_in_xrd,
                                  Offering {
@@ -141,7 +143,7 @@ mod mock_dex {
                                      price_in_xrd: Decimal,
                                      escrow_payout_component: Option<ComponentAddress>,
                                      allowance: NonFungibleBucket) {
-            assert_eq!(dec!(1), allowance.amount(), "Only one Allowance per trade");
+            assert!(dec!(1) == allowance.amount(), "{}", EscrowError::MockDexOneAllowance);
             let trader = trader.skip_checking();
             self.buy_book.insert(price_in_xrd,
                                  Offering {
@@ -167,7 +169,7 @@ mod mock_dex {
                                  escrow_payout_component: Option<ComponentAddress>,
                                  for_sale: FungibleBucket) {
             assert!(for_sale.resource_address() == self.meme_token,
-                    "Only MEME can be sold");
+                    "{}", EscrowError::MockDexOnlyMeme);
             let trader = trader.skip_checking();
             self.sell_book.insert(price_in_xrd,
                                   Offering {
@@ -192,7 +194,7 @@ mod mock_dex {
                                       price_in_xrd: Decimal,
                                       escrow_payout_component: Option<ComponentAddress>,
                                       allowance: NonFungibleBucket) {
-            assert_eq!(dec!(1), allowance.amount(), "Only one Allowance per trade");
+            assert!(dec!(1) == allowance.amount(), "{}", EscrowError::MockDexOneAllowance);
             let trader = trader.skip_checking();
             self.sell_book.insert(price_in_xrd,
                                   Offering {
@@ -218,8 +220,8 @@ mod mock_dex {
                                  mut payment: FungibleBucket)
                                  -> (Option<FungibleBucket>, FungibleBucket)
         {
-            assert_eq!(XRD, payment.resource_address(),
-                       "Can only pay with XRD");
+            assert!(XRD == payment.resource_address(),
+                    "{}", EscrowError::MockDexOnlyXrd);
             let mut purchased = FungibleBucket::new(self.meme_token);
 
             let mut maker_payouts = Vec::new();
@@ -254,22 +256,21 @@ mod mock_dex {
                             .get_non_fungible_data(
                                 &allowance_nfgid.local_id());
 
-                        let max_avail = Self::find_smallest(
-                            Self::find_allowance_limit(&allowance_nfgid),
-                            Self::call_escrow_read_funds(nfdata.escrow_pool.clone(),
-                                                         self.meme_token));
+                        // Don't trip over Allowances that have
+                        // expired etc., just skip them
+                        if !matches!(nfdata.status(&Now::from_ledger()),
+                                     AllowanceStatus::Usable{..}) { continue; }
 
                         let funds_wanted = payment.amount() / *price_in_xrd;
                         if funds_wanted.is_zero() { break; }
-                        let funds_to_take = std::cmp::min(funds_wanted, max_avail);
                         let allowance_bucket = vault.as_non_fungible().take_all();
 
-                        // pull funds from escrow
+                        // pull what funds we can get from escrow
                         let (meme_bucket, allowance_bucket) =
                             Self::call_escrow_withdraw_with_allowance(
                                 nfdata.escrow_pool,
                                 allowance_bucket,
-                                funds_to_take);
+                                funds_wanted);
                         
                         // xfer payment to maker
                         maker_payouts.push((actor.clone(),
@@ -315,8 +316,8 @@ mod mock_dex {
                                   mut selling: FungibleBucket)
                                   -> (Option<FungibleBucket>, FungibleBucket)
         {
-            assert_eq!(self.meme_token, selling.resource_address(),
-                       "Can only sell MEME");
+            assert!(self.meme_token == selling.resource_address(),
+                    "{}", EscrowError::MockDexOnlyMeme);
             let mut purchased = FungibleBucket::new(XRD);
 
             let mut maker_payouts = Vec::new();
@@ -352,22 +353,21 @@ mod mock_dex {
                             .get_non_fungible_data(
                                 &allowance_nfgid.local_id());
 
-                        let max_avail = Self::find_smallest(
-                            Self::find_allowance_limit(&allowance_nfgid),
-                            Self::call_escrow_read_funds(nfdata.escrow_pool.clone(),
-                                                         XRD));
+                        // Don't trip over Allowances that have
+                        // expired etc., just skip them
+                        if !matches!(nfdata.status(&Now::from_ledger()),
+                                     AllowanceStatus::Usable{..}) { continue; }
 
                         let funds_wanted = selling.amount() * *price_in_xrd;
                         if funds_wanted.is_zero() { break; }
-                        let funds_to_take = std::cmp::min(funds_wanted, max_avail);
                         let allowance_bucket = vault.as_non_fungible().take_all();
 
-                        // pull funds from escrow
+                        // pull what funds we can get from escrow
                         let (xrd_bucket, allowance_bucket) =
                             Self::call_escrow_withdraw_with_allowance(
                                 nfdata.escrow_pool,
                                 allowance_bucket,
-                                funds_to_take);
+                                funds_wanted);
                         
                         // xfer payment to maker
                         maker_payouts.push((actor.clone(),
@@ -428,39 +428,8 @@ mod mock_dex {
             }
         }
 
-        
-        
-        /// Finds how much can be taken when an allowance is `limit`
-        /// restricted, and there is `available` funds actually
-        /// available to take.
-        ///
-        /// `limit` is `None` for no limit (i.e. infinite limit).
-        fn find_smallest(limit: Option<Decimal>, available: Decimal) -> Decimal
-        {
-            if let Some(limit) = limit {
-                std::cmp::min(limit, available)
-            } else {
-                available
-            }
-        }
-        
-        fn find_allowance_limit(allowance: &NonFungibleGlobalId) -> Option<Decimal>
-        {
-            let nfdata: AllowanceNfData =
-                ResourceManager::from(allowance.resource_address())
-                .get_non_fungible_data(
-                    &allowance.local_id());
-            if nfdata.is_valid() {
-                nfdata.max_amount.map(|v| v.to_amount())
-            } else { Some(dec!(0)) }
-        }
-        
-        fn call_escrow_read_funds(escrow: (ComponentAddress, NonFungibleGlobalId),
-                                  resource: ResourceAddress) -> Decimal {
-            let escrow_component: Global<Escrow> = Global::from(escrow.0);
-            escrow_component.read_funds(escrow.1, resource)
-        }
-
+        /// Takes up to `amount` from the Escrow using `allowance`,
+        /// settling for less if that's all there is.
         fn call_escrow_withdraw_with_allowance(escrow: (ComponentAddress, NonFungibleGlobalId),
                                                allowance: NonFungibleBucket,
                                                amount: Decimal)
@@ -468,8 +437,10 @@ mod mock_dex {
         {
             let escrow_component: Global<Escrow> = Global::from(escrow.0);
 
-            escrow_component.withdraw_with_allowance(allowance.into(),
-                                                     TokenQuantity::Fungible(amount))
+            let (funds, _, _, allowance) =
+                escrow_component.withdraw_with_allowance_up_to(allowance.into(),
+                                                               TokenQuantity::Fungible(amount));
+            (funds, allowance)
         }
 
         fn call_escrow_deposit_funds(escrow: (ComponentAddress, NonFungibleGlobalId),
@@ -0,0 +1,65 @@
+//! Provides a type for points in time that can be given either as
+//! Unix time or as a consensus epoch.
+
+use scrypto::prelude::*;
+use std::cmp::Ordering;
+
+use crate::util::unix_time_now;
+
+/// A point in time, such as when an Allowance becomes valid or when a
+/// deal's deadline passes.
+///
+/// Unix time is what people think in, but the ledger clock we read it
+/// from is only accurate to the minute and comes from the timestamps
+/// of block proposers. Epochs are set by consensus itself, but an
+/// epoch lasts for several minutes and how long exactly varies. Use
+/// whichever suits your needs better.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeBound {
+    /// A Unix time in seconds.
+    UnixTime(i64),
+
+    /// A consensus epoch number.
+    Epoch(u64),
+}
+
+/// The current time, in both of the forms a [TimeBound] can take.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Now {
+    /// The current Unix time in seconds.
+    pub unix_time: i64,
+    /// The current consensus epoch.
+    pub epoch: u64,
+}
+
+impl Now {
+    /// Reads the current time off the ledger.
+    pub fn from_ledger() -> Now {
+        Now {
+            unix_time: unix_time_now(),
+            epoch: Runtime::current_epoch().number(),
+        }
+    }
+}
+
+impl TimeBound {
+    /// Compares this point in time to `now`, in whichever form this
+    /// time bound is given. All our time bounds get compared through
+    /// here.
+    pub fn cmp_to_now(&self, now: &Now) -> Ordering {
+        match self {
+            TimeBound::UnixTime(time) => time.cmp(&now.unix_time),
+            TimeBound::Epoch(epoch) => epoch.cmp(&now.epoch),
+        }
+    }
+
+    /// Returns true if this point in time is still to come.
+    pub fn is_after(&self, now: &Now) -> bool {
+        self.cmp_to_now(now) == Ordering::Greater
+    }
+
+    /// Returns true if this point in time has gone by.
+    pub fn is_before(&self, now: &Now) -> bool {
+        self.cmp_to_now(now) == Ordering::Less
+    }
+}
@@ -3,7 +3,8 @@
 
 use scrypto::prelude::*;
 
-use crate::util::length_of_option_set;
+use crate::util::{length_of_option_set, dec_to_u64};
+use crate::errors::EscrowError;
 
 /// This is a general way of describing how many tokens are wanted as
 /// payment for some trade. Its purpose is to enable us to specify
@@ -22,6 +23,48 @@ pub enum TokenQuantity {
     /// nonfungible local ids 1, 2 and 3 PLUS also 5 other random NFTs
     /// of the same NFT resource. (And by random we mean arbitrary.)
     NonFungible(Option<IndexSet<NonFungibleLocalId>>, Option<u64>),
+
+    /// Asks for the non-fungible tokens whose integer local ids are
+    /// in the given ranges, plus optionally a number of arbitrarily
+    /// chosen NFTs just like for `NonFungible`. This lets you name
+    /// very large numbers of integer local ids, e.g. all of 1 to
+    /// 10,000, without using a lot of space.
+    NonFungibleRanges(IntegerIdRanges, Option<u64>),
+
+    /// Asks for all the tokens there are. When withdrawing this means
+    /// everything the vault holds, and as the limit of an Allowance
+    /// it means there is no limit.
+    All,
+
+    /// Asks for any non-fungible tokens except those with the local
+    /// ids in the set. The number is the most NFTs asked for, and if
+    /// it is `None` there is no such limit. So for example
+    /// ```NonFungibleExcept(NflidSet::Ranges(IntegerIdRanges::new(1, 10)), None)```
+    /// asks for every NFT other than ids 1 to 10.
+    ///
+    /// When an Allowance has this as its limit, the excluded ids can
+    /// never be taken with it.
+    NonFungibleExcept(NflidSet, Option<u64>),
+}
+
+/// A set of integer non-fungible local ids, described as a list of
+/// inclusive ranges.
+///
+/// The ranges are kept sorted and with no two of them overlapping or
+/// touching, so that each set has only one representation.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub struct IntegerIdRanges {
+    ranges: Vec<(u64, u64)>,
+}
+
+/// The non-fungible local ids asked for by a [TokenQuantity], in
+/// whichever form the quantity holds them.
+#[derive(ScryptoSbor, ManifestSbor, Clone, PartialEq, Eq, Debug)]
+pub enum NflidSet {
+    /// An explicit set of local ids.
+    Ids(IndexSet<NonFungibleLocalId>),
+    /// Ranges of integer local ids.
+    Ranges(IntegerIdRanges),
 }
 
 impl TokenQuantity {
@@ -32,17 +75,26 @@ impl TokenQuantity {
             TokenQuantity::NonFungible(set, amount) =>
                 amount.unwrap_or_default() == 0 &&
                 length_of_option_set(set) == 0,
+            TokenQuantity::NonFungibleRanges(ranges, amount) =>
+                amount.unwrap_or_default() == 0 && ranges.is_empty(),
+            TokenQuantity::All => false,
+            TokenQuantity::NonFungibleExcept(_, amount) => *amount == Some(0),
         }
     }
     
     /// Determines how many tokens in total are being asked for in
-    /// an instance of the `TokenQuantity` enum.
+    /// an instance of the `TokenQuantity` enum. Quantities that have
+    /// no upper bound give `Decimal::MAX`.
     pub fn to_amount(&self) -> Decimal {
         match self {
             TokenQuantity::Fungible(price) => price.clone(),
             TokenQuantity::NonFungible(set, amount)
                 => Decimal::from(amount.unwrap_or_default()
                                  + length_of_option_set(set) as u64),
+            TokenQuantity::NonFungibleRanges(ranges, amount)
+                => Decimal::from(amount.unwrap_or_default()) + Decimal::from(ranges.len()),
+            TokenQuantity::All | TokenQuantity::NonFungibleExcept(_, None) => Decimal::MAX,
+            TokenQuantity::NonFungibleExcept(_, Some(amount)) => Decimal::from(*amount),
         }
     }
 
@@ -50,12 +102,377 @@ impl TokenQuantity {
     /// quantity. Note that this involves cloning of the data and also
     /// potentially involves a conversion from u64 to Decimal for
     /// NonFungible quantities.
-    pub fn extract_max_values(&self) -> (Option<IndexSet<NonFungibleLocalId>>, Option<Decimal>)
+    ///
+    /// Ranges of local ids are returned as ranges, we never expand
+    /// them here. `All` and `NonFungibleExcept` don't name any local
+    /// ids to take, you need to [resolve] them against a vault to
+    /// find those.
+    ///
+    /// [resolve]: TokenQuantity::resolve
+    pub fn extract_max_values(&self) -> (Option<NflidSet>, Option<Decimal>)
     {
         match self {
             TokenQuantity::Fungible(price) => (None, Some(price.clone())),
-            TokenQuantity::NonFungible(set, amount) => (set.clone(), amount.map(|v|Decimal::from(v))),
+            TokenQuantity::NonFungible(set, amount) =>
+                (set.clone().map(NflidSet::Ids), amount.map(|v|Decimal::from(v))),
+            TokenQuantity::NonFungibleRanges(ranges, amount) =>
+                (Some(NflidSet::Ranges(ranges.clone())), amount.map(|v|Decimal::from(v))),
+            TokenQuantity::All => (None, None),
+            TokenQuantity::NonFungibleExcept(_, amount) => (None, amount.map(|v|Decimal::from(v))),
+        }
+    }
+
+    /// Builds a non-fungible quantity out of a set of local ids and a
+    /// number of arbitrary NFTs, using whichever variant fits the set.
+    pub fn non_fungible(nflids: Option<NflidSet>, amount: Option<u64>) -> TokenQuantity {
+        match nflids {
+            Some(NflidSet::Ranges(ranges)) => TokenQuantity::NonFungibleRanges(ranges, amount),
+            Some(NflidSet::Ids(ids)) => TokenQuantity::NonFungible(Some(ids), amount),
+            None => TokenQuantity::NonFungible(None, amount),
+        }
+    }
+
+    /// Returns this quantity with the local ids in `nflids` no
+    /// longer named in it. The number of arbitrary NFTs asked for
+    /// stays the same.
+    pub fn without_nflids(&self, nflids: &IndexSet<NonFungibleLocalId>) -> TokenQuantity {
+        if nflids.is_empty() { return self.clone() }
+        match self {
+            TokenQuantity::Fungible(_)
+                | TokenQuantity::All
+                | TokenQuantity::NonFungibleExcept(..) => self.clone(),
+            TokenQuantity::NonFungible(set, amount) => TokenQuantity::NonFungible(
+                set.as_ref().map(|set| set.difference(nflids).cloned().collect()),
+                *amount),
+            TokenQuantity::NonFungibleRanges(ranges, amount) => TokenQuantity::NonFungibleRanges(
+                ranges.without_ids(nflids.iter()),
+                *amount),
+        }
+    }
+
+    /// Returns true if every token asked for by `other` is also
+    /// covered by this quantity. See [checked_sub] for how named
+    /// local ids and numbers of arbitrary NFTs cover each other.
+    ///
+    /// [checked_sub]: TokenQuantity::checked_sub
+    pub fn contains(&self, other: &TokenQuantity) -> bool {
+        self.checked_sub(other).is_some()
+    }
+
+    /// Returns true if this quantity stays within `limit`. This is
+    /// the same as `limit.contains(self)`.
+    pub fn satisfies(&self, limit: &TokenQuantity) -> bool {
+        limit.contains(self)
+    }
+
+    /// Returns what is left of this quantity after taking `other`
+    /// out of it, or `None` if this quantity doesn't cover `other`.
+    ///
+    /// `All` covers anything, and nothing but `All` covers an `All`
+    /// or a `NonFungibleExcept`.
+    ///
+    /// When this is a `Fungible` quantity everything in `other` is
+    /// counted as an amount, including any named local ids.
+    ///
+    /// Otherwise, local ids named in `other` that are also named
+    /// here are simply removed. Any other local ids named in `other`,
+    /// along with its number of arbitrary NFTs, are paid for out of
+    /// our own number of arbitrary NFTs. Note that our named local
+    /// ids can never pay for arbitrary NFTs in `other`. A `Fungible`
+    /// `other` counts as that many arbitrary NFTs, and must be a
+    /// whole number.
+    ///
+    /// When this is a `NonFungibleExcept` then `other` must not name
+    /// any of our excluded local ids, nor ask for arbitrary NFTs
+    /// since those might turn out to be excluded ones. Everything in
+    /// `other` is paid for out of our number of NFTs, if we have one.
+    pub fn checked_sub(&self, other: &TokenQuantity) -> Option<TokenQuantity> {
+        match (self, other) {
+            (TokenQuantity::All, _) => return Some(TokenQuantity::All),
+            (_, TokenQuantity::All) | (_, TokenQuantity::NonFungibleExcept(..)) => return None,
+            (TokenQuantity::NonFungibleExcept(excluded, count), _) => {
+                let (their_nflids, their_count) = other.nflids_and_count()?;
+                // Arbitrary NFTs could turn out to be excluded ones
+                if their_count.unwrap_or_default() > 0 && !excluded.is_empty() { return None }
+                let their_len = their_nflids.as_ref().map_or(0, |theirs| theirs.len());
+                if their_nflids.map_or(false, |theirs| theirs.intersection_len(excluded) > 0) {
+                    return None
+                }
+                let debit = their_len.checked_add(their_count.unwrap_or_default())?;
+                let left = match count {
+                    Some(count) => Some(count.checked_sub(debit)?),
+                    None => None,
+                };
+                return Some(TokenQuantity::NonFungibleExcept(excluded.clone(), left))
+            },
+            _ => {},
+        }
+
+        if let TokenQuantity::Fungible(amount) = self {
+            let take = other.to_amount();
+            if take.is_negative() { return None }
+            let left = amount.checked_sub(take)?;
+            return if left.is_negative() { None } else { Some(TokenQuantity::Fungible(left)) }
+        }
+
+        let (our_nflids, our_count) = self.nflids_and_count()?;
+        let (their_nflids, their_count) = other.nflids_and_count()?;
+
+        // Their named nflids that we don't name must be paid for
+        // out of our count, same as their arbitrary ones.
+        let outside = match (&their_nflids, &our_nflids) {
+            (Some(theirs), Some(ours)) => theirs.len() - theirs.intersection_len(ours),
+            (Some(theirs), None) => theirs.len(),
+            (None, _) => 0,
+        };
+        let debit = their_count.unwrap_or_default().checked_add(outside)?;
+        let left_count = if debit == 0 { our_count } else {
+            Some(our_count.unwrap_or_default().checked_sub(debit)?)
+        };
+        let left_nflids = match (our_nflids, &their_nflids) {
+            (Some(ours), Some(theirs)) => Some(ours.difference(theirs)),
+            (ours, _) => ours,
+        };
+        Some(TokenQuantity::non_fungible(left_nflids, left_count))
+    }
+
+    /// Returns the sum of this quantity and `other`, or `None` if the
+    /// sum overflows or if one of them is `Fungible` and the other
+    /// isn't.
+    ///
+    /// Local ids named in both quantities are only named once in the
+    /// sum. Integer local ids are kept as ranges if either quantity
+    /// has them as ranges.
+    ///
+    /// Adding anything to `All` gives `All`. Adding to a
+    /// `NonFungibleExcept` gives a `NonFungibleExcept` that no longer
+    /// excludes the local ids named in `other`, and whose number
+    /// grows by all the NFTs in `other`.
+    pub fn checked_add(&self, other: &TokenQuantity) -> Option<TokenQuantity> {
+        match (self, other) {
+            (TokenQuantity::All, _) | (_, TokenQuantity::All) => Some(TokenQuantity::All),
+            (TokenQuantity::Fungible(ours), TokenQuantity::Fungible(theirs)) =>
+                Some(TokenQuantity::Fungible(ours.checked_add(*theirs)?)),
+            (TokenQuantity::Fungible(_), _) | (_, TokenQuantity::Fungible(_)) => None,
+            (TokenQuantity::NonFungibleExcept(ours, our_count),
+             TokenQuantity::NonFungibleExcept(theirs, their_count)) => {
+                let count = match (our_count, their_count) {
+                    (Some(ours), Some(theirs)) => Some(ours.checked_add(*theirs)?),
+                    _ => None,
+                };
+                Some(TokenQuantity::NonFungibleExcept(ours.intersection(theirs), count))
+            },
+            (TokenQuantity::NonFungibleExcept(excluded, count), other)
+                | (other, TokenQuantity::NonFungibleExcept(excluded, count)) => {
+                let (nflids, other_count) = other.nflids_and_count()?;
+                let count = match count {
+                    Some(count) => Some(count
+                                        .checked_add(nflids.as_ref().map_or(0, |nflids| nflids.len()))?
+                                        .checked_add(other_count.unwrap_or_default())?),
+                    None => None,
+                };
+                let excluded = match nflids {
+                    Some(nflids) => excluded.difference(&nflids),
+                    None => excluded.clone(),
+                };
+                Some(TokenQuantity::NonFungibleExcept(excluded, count))
+            },
+            _ => {
+                let (our_nflids, our_count) = self.nflids_and_count()?;
+                let (their_nflids, their_count) = other.nflids_and_count()?;
+                let nflids = match (our_nflids, their_nflids) {
+                    (Some(ours), Some(theirs)) => Some(ours.union(&theirs)),
+                    (ours, theirs) => ours.or(theirs),
+                };
+                let count = match (our_count, their_count) {
+                    (None, None) => None,
+                    (ours, theirs) =>
+                        Some(ours.unwrap_or_default().checked_add(theirs.unwrap_or_default())?),
+                };
+                Some(TokenQuantity::non_fungible(nflids, count))
+            },
+        }
+    }
+
+    /// Returns what this quantity and `other` have in common, or
+    /// `None` if one of them is `Fungible` and the other isn't.
+    ///
+    /// For non-fungibles this is the local ids named in both along
+    /// with the smaller of the two numbers of arbitrary NFTs.
+    ///
+    /// `All` has everything in common with `other`. A
+    /// `NonFungibleExcept` has in common with `other` those of its
+    /// NFTs that it doesn't exclude.
+    pub fn intersect(&self, other: &TokenQuantity) -> Option<TokenQuantity> {
+        match (self, other) {
+            (TokenQuantity::All, other) | (other, TokenQuantity::All) => Some(other.clone()),
+            (TokenQuantity::Fungible(ours), TokenQuantity::Fungible(theirs)) =>
+                Some(TokenQuantity::Fungible(std::cmp::min(*ours, *theirs))),
+            (TokenQuantity::Fungible(_), _) | (_, TokenQuantity::Fungible(_)) => None,
+            (TokenQuantity::NonFungibleExcept(ours, our_count),
+             TokenQuantity::NonFungibleExcept(theirs, their_count)) => {
+                let count = match (our_count, their_count) {
+                    (Some(ours), Some(theirs)) => Some(std::cmp::min(*ours, *theirs)),
+                    (count, None) | (None, count) => *count,
+                };
+                Some(TokenQuantity::NonFungibleExcept(ours.union(theirs), count))
+            },
+            (TokenQuantity::NonFungibleExcept(excluded, count), other)
+                | (other, TokenQuantity::NonFungibleExcept(excluded, count)) => {
+                let (nflids, other_count) = other.nflids_and_count()?;
+                let other_count = match (count, other_count) {
+                    (Some(count), Some(other_count)) => Some(std::cmp::min(*count, other_count)),
+                    (_, other_count) => other_count,
+                };
+                Some(TokenQuantity::non_fungible(nflids.map(|nflids| nflids.difference(excluded)),
+                                                 other_count))
+            },
+            _ => {
+                let (our_nflids, our_count) = self.nflids_and_count()?;
+                let (their_nflids, their_count) = other.nflids_and_count()?;
+                let nflids = match (our_nflids, their_nflids) {
+                    (Some(ours), Some(theirs)) => Some(ours.intersection(&theirs)),
+                    _ => None,
+                };
+                let count = match (our_count, their_count) {
+                    (Some(ours), Some(theirs)) => Some(std::cmp::min(ours, theirs)),
+                    _ => None,
+                };
+                Some(TokenQuantity::non_fungible(nflids, count))
+            },
+        }
+    }
+
+    /// Splits this quantity into its named local ids and its number
+    /// of arbitrary NFTs. A `Fungible` quantity becomes that number
+    /// of arbitrary NFTs, and gives `None` if it isn't a whole
+    /// number. `All` and `NonFungibleExcept` also give `None` since
+    /// they can't be described this way.
+    fn nflids_and_count(&self) -> Option<(Option<NflidSet>, Option<u64>)> {
+        match self {
+            TokenQuantity::Fungible(amount) => Some((None, Some(u64::try_from(*amount).ok()?))),
+            TokenQuantity::NonFungible(set, count) =>
+                Some((set.clone().map(NflidSet::Ids), *count)),
+            TokenQuantity::NonFungibleRanges(ranges, count) =>
+                Some((Some(NflidSet::Ranges(ranges.clone())), *count)),
+            TokenQuantity::All | TokenQuantity::NonFungibleExcept(..) => None,
+        }
+    }
+
+    /// The local ids that this quantity excludes, if it is a
+    /// `NonFungibleExcept`.
+    pub fn excluded_ids(&self) -> Option<&NflidSet> {
+        match self {
+            TokenQuantity::NonFungibleExcept(excluded, _) => Some(excluded),
+            _ => None,
+        }
+    }
+
+    /// Returns true if we need to look in a vault to know exactly
+    /// which tokens this quantity asks for, given that we mustn't
+    /// pick any of the local ids in `avoid`. See [resolve].
+    ///
+    /// [resolve]: TokenQuantity::resolve
+    pub fn needs_resolving(&self, avoid: Option<&NflidSet>) -> bool {
+        match self {
+            TokenQuantity::All | TokenQuantity::NonFungibleExcept(..) => true,
+            _ => avoid.map_or(false, |avoid| !avoid.is_empty())
+                && self.nflids_and_count().map_or(false, |(_, count)| count.unwrap_or_default() > 0),
+        }
+    }
+
+    /// Works out which tokens in `vault` this quantity asks for,
+    /// without taking the vault below `floor` and without picking
+    /// any of the local ids in `avoid`. For a fungible vault this
+    /// only changes `All`, into the amount above the floor.
+    ///
+    /// For a non-fungible vault, any arbitrary NFTs asked for are
+    /// picked out of the vault and named in the resolved quantity,
+    /// as are all the NFTs asked for by `All` or a
+    /// `NonFungibleExcept` without a number. If there are no
+    /// arbitrary NFTs and nothing to avoid the quantity is returned
+    /// as is. Named local ids are kept even if they are in `avoid`,
+    /// it is up to the caller to reject them.
+    ///
+    /// Also returns how many NFTs we were asked to pick but couldn't
+    /// find in the vault.
+    pub fn resolve(&self,
+                   vault: &Vault,
+                   floor: Decimal,
+                   avoid: Option<&NflidSet>) -> (TokenQuantity, u64)
+    {
+        let room = std::cmp::max(vault.amount() - floor, Decimal::ZERO);
+        if vault.resource_address().is_fungible() {
+            return match self {
+                TokenQuantity::All => (TokenQuantity::Fungible(room), 0),
+                _ => (self.clone(), 0),
+            }
+        }
+        if !self.needs_resolving(avoid) {
+            return (self.clone(), 0)
+        }
+
+        let room = dec_to_u64(room.checked_round(0, RoundingMode::ToZero).unwrap());
+        let (named, excluded, wanted) = match self {
+            TokenQuantity::All => (None, None, room),
+            TokenQuantity::NonFungibleExcept(excluded, count) =>
+                (None, Some(excluded), count.unwrap_or(room)),
+            _ => {
+                // needs_resolving has made sure this is Some
+                let (named, count) = self.nflids_and_count().unwrap();
+                (named, None, count.unwrap_or_default())
+            },
+        };
+
+        // We may need to skip past every id we must not pick before
+        // we find enough that we can.
+        let skip_len = [named.as_ref(), excluded, avoid].iter()
+            .map(|set| set.map_or(0, |set| set.len()))
+            .fold(0u64, |acc, len| acc.saturating_add(len));
+        let limit = std::cmp::min(dec_to_u64(vault.amount()), wanted.saturating_add(skip_len));
+        let picked: IndexSet<NonFungibleLocalId> = vault.as_non_fungible()
+            .non_fungible_local_ids(u32::try_from(limit).unwrap_or(u32::MAX))
+            .into_iter()
+            .filter(|nflid| ![named.as_ref(), excluded, avoid].iter()
+                    .any(|set| set.map_or(false, |set| set.contains(nflid))))
+            .take(usize::try_from(wanted).unwrap_or(usize::MAX))
+            .collect();
+        let short = wanted - picked.len() as u64;
+
+        let picked = NflidSet::Ids(picked);
+        let nflids = match named {
+            Some(named) => named.union(&picked),
+            None => picked,
+        };
+        (TokenQuantity::non_fungible(Some(nflids), None), short)
+    }
+
+    /// Takes the tokens described by this quantity out of `vault`
+    /// and returns them in a bucket. Panics if the vault doesn't
+    /// hold them.
+    pub fn take_from_vault(&self, vault: &mut Vault) -> Bucket {
+        match self {
+            TokenQuantity::All => return vault.take_all(),
+            TokenQuantity::NonFungibleExcept(..) => {
+                let (resolved, short) = self.resolve(vault, Decimal::ZERO, None);
+                assert!(short == 0, "{}", EscrowError::FundsNotAvailable);
+                return resolved.take_from_vault(vault)
+            },
+            _ => {},
+        }
+        let (take_nflids, take_amount) = self.extract_max_values();
+        let mut bucket = Bucket::new(vault.resource_address());
+
+        // First take the named nflids: if we do this the other way
+        // around they may no longer be available when we try.
+        if let Some(nflids) = &take_nflids {
+            bucket.put(vault.as_non_fungible().take_non_fungibles(&nflids.to_index_set()).into());
+        }
+        // Then take the necessary amount of arbitrary tokens.
+        if let Some(amount) = take_amount {
+            bucket.put(vault.take(amount));
         }
+        bucket
     }
 
     /// Checks if the values in the provided map are consistent
@@ -65,13 +482,271 @@ impl TokenQuantity {
             let fung_res = resaddr.is_fungible();
             match ask {
                 TokenQuantity::Fungible(amount) => {
-                    assert!(fung_res, "fungible TokenQuantity used for non-fungible resource");
-                    assert!(!amount.is_negative(), "cannot ask for negative amounts");
+                    assert!(fung_res, "{}", EscrowError::FungibleQuantityForNonFungible);
+                    assert!(!amount.is_negative(), "{}", EscrowError::NegativeQuantity);
                 },
                 TokenQuantity::NonFungible(_, _) => {
-                    assert!(!fung_res, "non-fungible TokenQuantity used for fungible resource");
-                }
+                    assert!(!fung_res, "{}", EscrowError::NonFungibleQuantityForFungible);
+                },
+                TokenQuantity::NonFungibleRanges(ranges, _) => {
+                    assert!(!fung_res, "{}", EscrowError::NonFungibleQuantityForFungible);
+                    assert!(ranges.is_well_formed(), "{}", EscrowError::MalformedIdRanges);
+                },
+                TokenQuantity::All => {},
+                TokenQuantity::NonFungibleExcept(excluded, _) => {
+                    assert!(!fung_res, "{}", EscrowError::NonFungibleQuantityForFungible);
+                    assert!(excluded.is_well_formed(), "{}", EscrowError::MalformedIdRanges);
+                },
             }
         }
     }
 }
+
+impl IntegerIdRanges {
+    /// Creates the set of all integer local ids from `first` to
+    /// `last`, both inclusive.
+    pub fn new(first: u64, last: u64) -> IntegerIdRanges {
+        IntegerIdRanges::from_ranges(vec![(first, last)])
+    }
+
+    /// Creates a set from any number of inclusive `(first, last)`
+    /// ranges. They may overlap and be in any order. Ranges where
+    /// `last` is less than `first` are taken to be empty.
+    pub fn from_ranges(mut ranges: Vec<(u64, u64)>) -> IntegerIdRanges {
+        ranges.retain(|(first, last)| first <= last);
+        ranges.sort();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (first, last) in ranges {
+            match merged.last_mut() {
+                Some(prev) if first <= prev.1.saturating_add(1) =>
+                    prev.1 = std::cmp::max(prev.1, last),
+                _ => merged.push((first, last)),
+            }
+        }
+        IntegerIdRanges { ranges: merged }
+    }
+
+    /// Removes the ids `first` to `last`, both inclusive, from the
+    /// set. Use this to punch holes in a range.
+    pub fn excluding(self, first: u64, last: u64) -> IntegerIdRanges {
+        self.difference(&IntegerIdRanges::new(first, last))
+    }
+
+    /// The inclusive `(first, last)` ranges that make up this set.
+    pub fn ranges(&self) -> &[(u64, u64)] {
+        &self.ranges
+    }
+
+    /// Returns true if the set has no ids in it.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The number of ids in the set.
+    pub fn len(&self) -> u64 {
+        self.ranges.iter().map(|(first, last)| (last - first).saturating_add(1)).sum()
+    }
+
+    /// Returns true if `nflid` is an integer local id in the set.
+    pub fn contains(&self, nflid: &NonFungibleLocalId) -> bool {
+        match nflid {
+            NonFungibleLocalId::Integer(id) => self.contains_integer(id.value()),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the integer local id `id` is in the set.
+    pub fn contains_integer(&self, id: u64) -> bool {
+        self.ranges.iter().any(|(first, last)| *first <= id && id <= *last)
+    }
+
+    /// Goes through the ids in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = NonFungibleLocalId> + '_ {
+        self.ranges.iter()
+            .flat_map(|(first, last)| *first..=*last)
+            .map(NonFungibleLocalId::integer)
+    }
+
+    /// Counts the ids that are in both this set and `other`.
+    pub fn intersection_len(&self, other: &IntegerIdRanges) -> u64 {
+        let (mut i, mut j, mut count) = (0, 0, 0u64);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a_first, a_last) = self.ranges[i];
+            let (b_first, b_last) = other.ranges[j];
+            let first = std::cmp::max(a_first, b_first);
+            let last = std::cmp::min(a_last, b_last);
+            if first <= last {
+                count += (last - first).saturating_add(1);
+            }
+            if a_last < b_last { i += 1 } else { j += 1 }
+        }
+        count
+    }
+
+    /// Returns the ids in this set that aren't in `other`.
+    pub fn difference(&self, other: &IntegerIdRanges) -> IntegerIdRanges {
+        let mut result = Vec::new();
+        let mut j = 0;
+        for &(first, last) in &self.ranges {
+            // Skip the ranges in `other` that end before this one
+            // starts, they can't affect any later ranges either.
+            while j < other.ranges.len() && other.ranges[j].1 < first { j += 1 }
+
+            let mut start = first;
+            let mut covered = false;
+            for &(o_first, o_last) in &other.ranges[j..] {
+                if o_first > last { break }
+                if o_first > start { result.push((start, o_first - 1)) }
+                if o_last >= last { covered = true; break }
+                start = o_last + 1;
+            }
+            if !covered { result.push((start, last)) }
+        }
+        IntegerIdRanges { ranges: result }
+    }
+
+    /// Creates a set from the local ids in `nflids`, or returns
+    /// `None` if any of them aren't integer ids.
+    pub fn from_ids<'a>(nflids: impl Iterator<Item = &'a NonFungibleLocalId>)
+                        -> Option<IntegerIdRanges>
+    {
+        let ranges = nflids.map(|nflid| match nflid {
+            NonFungibleLocalId::Integer(id) => Some((id.value(), id.value())),
+            _ => None,
+        }).collect::<Option<Vec<_>>>()?;
+        Some(IntegerIdRanges::from_ranges(ranges))
+    }
+
+    /// Returns the ids that are in this set, in `other` or in both.
+    pub fn union(&self, other: &IntegerIdRanges) -> IntegerIdRanges {
+        IntegerIdRanges::from_ranges(
+            self.ranges.iter().chain(other.ranges.iter()).cloned().collect())
+    }
+
+    /// Returns the ids that are in both this set and `other`.
+    pub fn intersection(&self, other: &IntegerIdRanges) -> IntegerIdRanges {
+        self.difference(&self.difference(other))
+    }
+
+    /// Returns the ids in this set with the integer ids in `nflids`
+    /// removed. Ids of other types in `nflids` are ignored.
+    pub fn without_ids<'a>(&self, nflids: impl Iterator<Item = &'a NonFungibleLocalId>)
+                           -> IntegerIdRanges
+    {
+        let removed = nflids.filter_map(|nflid| match nflid {
+            NonFungibleLocalId::Integer(id) => Some((id.value(), id.value())),
+            _ => None,
+        }).collect();
+        self.difference(&IntegerIdRanges::from_ranges(removed))
+    }
+
+    /// Returns true if the ranges are sorted, non-empty and neither
+    /// overlap nor touch. Sets we build ourselves always are, but one
+    /// that came in through a transaction manifest may not be.
+    pub fn is_well_formed(&self) -> bool {
+        self.ranges.iter().all(|(first, last)| first <= last)
+            && self.ranges.windows(2).all(|pair| pair[0].1.saturating_add(1) < pair[1].0)
+    }
+}
+
+impl NflidSet {
+    /// Returns true unless this is ranges that aren't well formed.
+    /// See [IntegerIdRanges::is_well_formed].
+    pub fn is_well_formed(&self) -> bool {
+        match self {
+            NflidSet::Ids(_) => true,
+            NflidSet::Ranges(ranges) => ranges.is_well_formed(),
+        }
+    }
+
+    /// The number of ids in the set.
+    pub fn len(&self) -> u64 {
+        match self {
+            NflidSet::Ids(ids) => ids.len() as u64,
+            NflidSet::Ranges(ranges) => ranges.len(),
+        }
+    }
+
+    /// Returns true if the set has no ids in it.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if `nflid` is in the set.
+    pub fn contains(&self, nflid: &NonFungibleLocalId) -> bool {
+        match self {
+            NflidSet::Ids(ids) => ids.contains(nflid),
+            NflidSet::Ranges(ranges) => ranges.contains(nflid),
+        }
+    }
+
+    /// Goes through the ids in the set without collecting them.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = NonFungibleLocalId> + '_> {
+        match self {
+            NflidSet::Ids(ids) => Box::new(ids.iter().cloned()),
+            NflidSet::Ranges(ranges) => Box::new(ranges.iter()),
+        }
+    }
+
+    /// Collects all the ids of the set. Note that for ranges this
+    /// may be a very large set, only do this when you need the
+    /// actual ids, e.g. to take them out of a vault.
+    pub fn to_index_set(&self) -> IndexSet<NonFungibleLocalId> {
+        self.iter().collect()
+    }
+
+    /// Counts the ids that are in both this set and `other`.
+    pub fn intersection_len(&self, other: &NflidSet) -> u64 {
+        match (self, other) {
+            (NflidSet::Ranges(ours), NflidSet::Ranges(theirs)) => ours.intersection_len(theirs),
+            (NflidSet::Ids(ours), _) =>
+                ours.iter().filter(|nflid| other.contains(nflid)).count() as u64,
+            (_, NflidSet::Ids(theirs)) =>
+                theirs.iter().filter(|nflid| self.contains(nflid)).count() as u64,
+        }
+    }
+
+    /// Returns the ids in this set that aren't in `other`. The
+    /// result is in the same form as this set.
+    pub fn difference(&self, other: &NflidSet) -> NflidSet {
+        match (self, other) {
+            (NflidSet::Ranges(ours), NflidSet::Ranges(theirs)) =>
+                NflidSet::Ranges(ours.difference(theirs)),
+            (NflidSet::Ranges(ours), NflidSet::Ids(theirs)) =>
+                NflidSet::Ranges(ours.without_ids(theirs.iter())),
+            (NflidSet::Ids(ours), _) =>
+                NflidSet::Ids(ours.iter().filter(|nflid| !other.contains(nflid)).cloned().collect()),
+        }
+    }
+    /// Returns the ids that are in this set, in `other` or in
+    /// both. If either set is ranges the result is ranges too,
+    /// unless the other set has ids that aren't integers in which
+    /// case the ranges have to be expanded.
+    pub fn union(&self, other: &NflidSet) -> NflidSet {
+        match (self, other) {
+            (NflidSet::Ranges(ours), NflidSet::Ranges(theirs)) =>
+                NflidSet::Ranges(ours.union(theirs)),
+            (NflidSet::Ranges(ranges), NflidSet::Ids(ids))
+                | (NflidSet::Ids(ids), NflidSet::Ranges(ranges)) =>
+                match IntegerIdRanges::from_ids(ids.iter()) {
+                    Some(id_ranges) => NflidSet::Ranges(ranges.union(&id_ranges)),
+                    None => NflidSet::Ids(ranges.iter().chain(ids.iter().cloned()).collect()),
+                },
+            (NflidSet::Ids(ours), NflidSet::Ids(theirs)) =>
+                NflidSet::Ids(ours.union(theirs).cloned().collect()),
+        }
+    }
+
+    /// Returns the ids that are in both this set and `other`. This is
+    /// only ranges if both sets are.
+    pub fn intersection(&self, other: &NflidSet) -> NflidSet {
+        match (self, other) {
+            (NflidSet::Ranges(ours), NflidSet::Ranges(theirs)) =>
+                NflidSet::Ranges(ours.intersection(theirs)),
+            (NflidSet::Ids(ours), _) =>
+                NflidSet::Ids(ours.iter().filter(|nflid| other.contains(nflid)).cloned().collect()),
+            (_, NflidSet::Ids(theirs)) =>
+                NflidSet::Ids(theirs.iter().filter(|nflid| self.contains(nflid)).cloned().collect()),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use escrow::calendar::*;
+
+const DAY: i64 = 86_400;
+
+#[test]
+fn test_civil_dates() {
+    assert_eq!((1970, 1, 1), civil_from_days(0), "Day zero is the epoch");
+    assert_eq!((1969, 12, 31), civil_from_days(-1), "Should handle dates before the epoch");
+    assert_eq!((2024, 2, 29), civil_from_days(1709164800 / DAY), "Should find leap days");
+    assert_eq!((2000, 2, 29), civil_from_days(951782400 / DAY), "2000 was a leap year");
+    assert_eq!(47541, days_from_civil(2100, 3, 1), "2100 is not a leap year");
+    assert_eq!(days_from_civil(2100, 2, 28) + 1, days_from_civil(2100, 3, 1),
+               "2100 should have no 29th of February");
+
+    for days in (-1000..30000).step_by(7) {
+        let (year, month, day) = civil_from_days(days);
+        assert_eq!(days, days_from_civil(year, month, day),
+                   "{}-{}-{} should convert back", year, month, day);
+    }
+
+    assert_eq!(29, days_in_month(2024, 2), "2024 is a leap year");
+    assert_eq!(28, days_in_month(2023, 2), "2023 is not a leap year");
+    assert_eq!(28, days_in_month(1900, 2), "1900 was not a leap year");
+    assert_eq!(30, days_in_month(2024, 4), "April has 30 days");
+    assert_eq!(3, weekday_of(0), "The epoch was a Thursday");
+    assert_eq!(0, weekday_of(1705320000 / DAY), "15 January 2024 was a Monday");
+}
+
+#[test]
+fn test_daily_and_weekly() {
+    assert_eq!(1735689600, CalendarSchedule::Daily.next_period_start(1735689540),
+               "A minute to midnight should give the next day");
+    assert_eq!(1735689600 + DAY, CalendarSchedule::Daily.next_period_start(1735689600),
+               "Midnight is in the period it starts");
+
+    // 6 March 2024 was a Wednesday
+    assert_eq!(1709769600, CalendarSchedule::Weekly{weekday: 3}.next_period_start(1709737200),
+               "Should find the coming Thursday");
+    assert_eq!(1710115200, CalendarSchedule::Weekly{weekday: 0}.next_period_start(1709737200),
+               "Should find the coming Monday");
+    assert_eq!(1709769600 + 7 * DAY,
+               CalendarSchedule::Weekly{weekday: 3}.next_period_start(1709769600),
+               "On the day itself should give a week later");
+}
+
+#[test]
+fn test_monthly() {
+    let first = CalendarSchedule::Monthly{day: 1};
+    assert_eq!(1709251200, first.next_period_start(1707955200),
+               "Mid February should give 1 March");
+    assert_eq!(1735689600, first.next_period_start(1735689540),
+               "Should cross into the next year");
+
+    let last = CalendarSchedule::Monthly{day: 31};
+    assert_eq!(1706659200, last.next_period_start(1705320000),
+               "Mid January should give 31 January");
+    assert_eq!(1709164800, last.next_period_start(1706659200),
+               "31 January should give 29 February in a leap year");
+    assert_eq!(1711843200, last.next_period_start(1709200800),
+               "29 February should give 31 March");
+    assert_eq!(1677542400, last.next_period_start(1675987200),
+               "Should give 28 February outside leap years");
+
+    let tenth = CalendarSchedule::Monthly{day: 10};
+    assert_eq!(19792 * DAY, tenth.next_period_start(1707523200),
+               "On the 10th should give the 10th of the next month");
+    assert_eq!(1707523200, tenth.next_period_start(19754 * DAY),
+               "On the 1st should give the 10th of the same month");
+}
+
+#[test]
+fn test_quarterly() {
+    assert_eq!(1711929600, CalendarSchedule::Quarterly.next_period_start(1707955200),
+               "February should give 1 April");
+    assert_eq!(1719792000, CalendarSchedule::Quarterly.next_period_start(1711929600),
+               "1 April should give 1 July");
+    assert_eq!(1719792000, CalendarSchedule::Quarterly.next_period_start(1719791940),
+               "End of June should give 1 July");
+    assert_eq!(1735689600, CalendarSchedule::Quarterly.next_period_start(1732089600),
+               "November should give 1 January");
+}
+
+#[test]
+fn test_schedule_validity() {
+    assert!(CalendarSchedule::Weekly{weekday: 6}.is_valid(), "Sunday exists");
+    assert!(!CalendarSchedule::Weekly{weekday: 7}.is_valid(), "There is no eighth day");
+    assert!(CalendarSchedule::Monthly{day: 31}.is_valid(), "31 means end of month");
+    assert!(!CalendarSchedule::Monthly{day: 0}.is_valid(), "Days count from 1");
+    assert!(!CalendarSchedule::Monthly{day: 32}.is_valid(), "No month has 32 days");
+}
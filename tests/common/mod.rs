@@ -65,6 +65,13 @@ pub fn set_test_runner_clock(
     }
 }
 
+/// Changes the test runner's consensus epoch.
+pub fn set_test_runner_epoch(
+    test_runner: &mut DefaultTestRunner,
+    epoch: u64) {
+    test_runner.set_current_epoch(Epoch::of(epoch));
+}
+
 pub fn balance_change_amount(commit_result: &CommitResult,
                              vaults: Vec<NodeId>,
                              resource: ResourceAddress)
@@ -165,6 +172,23 @@ pub fn give_tokens(test_runner: &mut DefaultTestRunner,
     receipt.expect_commit_success();
 }
 
+/// Sets whether an account accepts deposits from others.
+pub fn set_default_deposit_rule(test_runner: &mut DefaultTestRunner,
+                                user: &User,
+                                rule: DefaultDepositRule) {
+    let manifest = ManifestBuilder::new()
+        .call_method(user.account,
+                     "set_default_deposit_rule",
+                     manifest_args!(rule))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    receipt.expect_commit_success();
+}
+
 /// Retrieves all non-fungible local ids of a given resource held by
 /// an account. For non-fungibles it's like
 /// TestRunner::get_component_balance except better.
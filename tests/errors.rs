@@ -0,0 +1,53 @@
+use escrow::errors::EscrowError;
+use std::collections::HashSet;
+
+#[test]
+fn test_error_codes_are_unique() {
+    let mut seen = HashSet::new();
+    for error in EscrowError::ALL {
+        assert!(seen.insert(error.code()),
+                "code {} is used more than once", error.code());
+    }
+}
+
+#[test]
+fn test_error_codes_are_in_order() {
+    for pair in EscrowError::ALL.windows(2) {
+        assert!(pair[0].code() < pair[1].code(),
+                "{:?} should come after {:?}", pair[0], pair[1]);
+    }
+    assert_eq!(2000, EscrowError::ALL[0].code(),
+               "Codes should start at 2000");
+}
+
+#[test]
+fn test_error_messages() {
+    for error in EscrowError::ALL {
+        let message = error.to_string();
+        assert_eq!(error.as_str(), message,
+                   "Display should give the panic message");
+        assert!(message.starts_with(&format!("{} ", error.code())),
+                "{:?} message should start with its code", error);
+        assert!(message.len() > 5,
+                "{:?} should have a description", error);
+        assert_eq!(Some(*error), EscrowError::from_message(&message),
+                   "{:?} should be found from its message", error);
+        assert_eq!(Some(*error), EscrowError::from_code(error.code()),
+                   "{:?} should be found from its code", error);
+    }
+}
+
+#[test]
+fn test_error_lookup_misses() {
+    assert_eq!(None, EscrowError::from_code(2008),
+               "2008 is not in use");
+    assert_eq!(None, EscrowError::from_code(1999),
+               "Codes below 2000 are not ours");
+    assert_eq!(None, EscrowError::from_message("Vault error"),
+               "Engine messages have no code");
+    assert_eq!(None, EscrowError::from_message(""),
+               "Empty message has no code");
+    assert_eq!(Some(EscrowError::NotTrusted),
+               EscrowError::from_message("2013 only trusted can request allowance"),
+               "Known message should be found");
+}
@@ -1,9 +1,15 @@
 use scrypto::prelude::*;
 use radix_engine::errors::*;
 use transaction::builder::ManifestBuilder;
-use escrow::token_quantity::TokenQuantity;
+use escrow::token_quantity::{IntegerIdRanges, NflidSet, TokenQuantity};
 use radix_engine::blueprints::resource::NonFungibleVaultError;
-use escrow::{AllowanceLifeCycle, AllowanceNfData};
+use escrow::calendar::CalendarSchedule;
+use escrow::time_bound::TimeBound;
+use escrow::{AllowanceLifeCycle, AllowanceNfData, AllowanceOptions, AllowanceQuote,
+             AllowanceStatus, AllowanceUsage, CoSigner, DealNfData, DealState, Guardians,
+             HashLockClaimedEvent, Inheritance,
+             PaymentRecipient, SavingsLimit, SavingsLock, StoredIds, WithdrawalCancelledEvent,
+             WithdrawalDelay, WithdrawalFinalizedEvent, WithdrawalRequestedEvent};
 
 mod common;
 mod manifests;
@@ -431,9 +437,9 @@ fn test_mint_allowance() {
                "allowance should reference correct escrow instance");
     assert_eq!(owner_badge, nfdata.escrow_pool.1,
                "owner should own the allowance's pool");
-    assert_eq!(Some(50), nfdata.valid_until,
+    assert_eq!(Some(TimeBound::UnixTime(50)), nfdata.valid_until,
                "valid_until should be as we set it");
-    assert_eq!(2, nfdata.valid_from,
+    assert_eq!(TimeBound::UnixTime(2), nfdata.valid_from,
                "valid_from should be as we set it");
     assert!(matches!(nfdata.life_cycle, AllowanceLifeCycle::Accumulating),
             "life_cycle should be as we set it");
@@ -3044,3 +3050,4468 @@ fn test_automatic_allowance() {
     drop(receipt);
     drop(allowance);
 }
+
+
+#[test]
+fn test_deal_approve_and_refund() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_pool_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_pool_badge =
+        NonFungibleGlobalId::new(bob_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 100);
+
+    // Verify that a deal cannot be opened with a deadline that has
+    // already passed
+    let receipt = call_open_deal_impl(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_pool_badge,
+                                      None,
+                                      XRD,
+                                      TokenQuantity::Fungible(dec!("300")),
+                                      TimeBound::UnixTime(60),
+                                      false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2095 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a deal cannot be opened with a quantity that
+    // doesn't fit the resource
+    let receipt = call_open_deal_impl(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_pool_badge,
+                                      None,
+                                      XRD,
+                                      TokenQuantity::NonFungible(None, Some(3)),
+                                      TimeBound::UnixTime(1000),
+                                      false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2057 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let deal1 = call_open_deal(&mut test_runner,
+                               &alice,
+                               escrow,
+                               &alice_pool_badge,
+                               &bob_pool_badge,
+                               None,
+                               XRD,
+                               TokenQuantity::Fungible(dec!("300")),
+                               1000);
+
+    assert_eq!(dec!("700"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Deal funds should have left Alice's pool");
+
+    let nfdata = test_runner.get_non_fungible_data::<DealNfData>(
+        deal1.resource_address(),
+        deal1.local_id().clone());
+    assert_eq!(alice_pool_badge, nfdata.payer,
+               "Alice should be the payer");
+    assert_eq!(bob_pool_badge, nfdata.payee,
+               "Bob should be the payee");
+    assert_eq!(DealState::Open, nfdata.state,
+               "Deal should be open");
+
+    // Verify that the payee cannot approve the deal
+    let receipt = call_approve_deal(&mut test_runner,
+                                    &bob,
+                                    escrow,
+                                    &bob_pool_badge,
+                                    &deal1,
+                                    false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2015 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that the deal cannot be refunded before its deadline
+    let receipt = call_refund_deal(&mut test_runner,
+                                   &bob,
+                                   escrow,
+                                   &deal1,
+                                   false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2018 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that the payer can approve the deal
+    call_approve_deal(&mut test_runner,
+                      &alice,
+                      escrow,
+                      &alice_pool_badge,
+                      &deal1,
+                      true);
+
+    assert_eq!(dec!("300"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Deal funds should have arrived in Bob's pool");
+    let nfdata = test_runner.get_non_fungible_data::<DealNfData>(
+        deal1.resource_address(),
+        deal1.local_id().clone());
+    assert_eq!(DealState::Released, nfdata.state,
+               "Deal should be released");
+
+    // Verify that a closed deal cannot be approved again
+    let receipt = call_approve_deal(&mut test_runner,
+                                    &alice,
+                                    escrow,
+                                    &alice_pool_badge,
+                                    &deal1,
+                                    false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2019 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that an expired deal is refunded to the payer
+    let deal2 = call_open_deal(&mut test_runner,
+                               &alice,
+                               escrow,
+                               &alice_pool_badge,
+                               &bob_pool_badge,
+                               None,
+                               XRD,
+                               TokenQuantity::Fungible(dec!("200")),
+                               1000);
+
+    assert_eq!(dec!("500"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Deal funds should have left Alice's pool");
+
+    set_test_runner_clock(&mut test_runner, 1100);
+
+    call_refund_deal(&mut test_runner,
+                     &bob,
+                     escrow,
+                     &deal2,
+                     true);
+
+    assert_eq!(dec!("700"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Deal funds should be back in Alice's pool");
+    assert_eq!(dec!("300"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob's pool should be unchanged");
+    let nfdata = test_runner.get_non_fungible_data::<DealNfData>(
+        deal2.resource_address(),
+        deal2.local_id().clone());
+    assert_eq!(DealState::Refunded, nfdata.state,
+               "Deal should be refunded");
+}
+
+
+#[test]
+fn test_deal_settle() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_pool_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_pool_badge =
+        NonFungibleGlobalId::new(bob_pool_res, 1.into());
+
+    let carol = make_user(&mut test_runner, Some(&"Carol".to_owned()));
+    let carol_arbiter_res =
+        test_runner.create_non_fungible_resource(carol.account);
+    let carol_arbiter_badge =
+        NonFungibleGlobalId::new(carol_arbiter_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    let deal = call_open_deal(&mut test_runner,
+                              &alice,
+                              escrow,
+                              &alice_pool_badge,
+                              &bob_pool_badge,
+                              Some(carol_arbiter_badge.clone()),
+                              XRD,
+                              TokenQuantity::Fungible(dec!("300")),
+                              1000);
+
+    // Verify that only the arbiter can settle
+    let receipt = call_settle_deal(&mut test_runner,
+                                   &bob,
+                                   escrow,
+                                   &bob_pool_badge,
+                                   &deal,
+                                   TokenQuantity::Fungible(dec!("300")),
+                                   false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2017 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that the arbiter's split is honoured
+    call_settle_deal(&mut test_runner,
+                     &carol,
+                     escrow,
+                     &carol_arbiter_badge,
+                     &deal,
+                     TokenQuantity::Fungible(dec!("100")),
+                     true);
+
+    assert_eq!(dec!("100"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob should have received his part of the deal");
+    assert_eq!(dec!("900"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Alice should have received the rest of the deal");
+    let nfdata = test_runner.get_non_fungible_data::<DealNfData>(
+        deal.resource_address(),
+        deal.local_id().clone());
+    assert_eq!(DealState::Settled{to_payee: TokenQuantity::Fungible(dec!("100"))},
+               nfdata.state,
+               "Deal should be settled");
+
+    // Verify that a deal without arbiter cannot be settled
+    let deal = call_open_deal(&mut test_runner,
+                              &alice,
+                              escrow,
+                              &alice_pool_badge,
+                              &bob_pool_badge,
+                              None,
+                              XRD,
+                              TokenQuantity::Fungible(dec!("300")),
+                              1000);
+    let receipt = call_settle_deal(&mut test_runner,
+                                   &carol,
+                                   escrow,
+                                   &carol_arbiter_badge,
+                                   &deal,
+                                   TokenQuantity::Fungible(dec!("300")),
+                                   false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2016 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+}
+
+
+#[test]
+fn test_hash_lock() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_id_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_id_badge =
+        NonFungibleGlobalId::new(bob_id_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 100);
+
+    let secret1 = "the first secret".as_bytes().to_vec();
+    let secret2 = "the second secret".as_bytes().to_vec();
+
+    // Verify that someone else locking under our hash first doesn't
+    // get in our way
+    call_deposit_funds(&mut test_runner,
+                       &bob,
+                       escrow,
+                       &bob_id_badge,
+                       None,
+                       XRD,
+                       dec!("10"),
+                       true);
+    let receipt = call_lock_with_hash(&mut test_runner,
+                                      &bob,
+                                      escrow,
+                                      &bob_id_badge,
+                                      &alice_pool_badge,
+                                      XRD,
+                                      TokenQuantity::Fungible(dec!("1")),
+                                      hash(&secret1),
+                                      1000,
+                                      true);
+    let bob_lock: u64 = receipt.expect_commit_success().output(3);
+    drop(receipt);
+
+    let receipt = call_lock_with_hash(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_id_badge,
+                                      XRD,
+                                      TokenQuantity::Fungible(dec!("100")),
+                                      hash(&secret1),
+                                      1000,
+                                      true);
+    let lock1: u64 = receipt.expect_commit_success().output(3);
+    drop(receipt);
+    assert_ne!(bob_lock, lock1,
+               "Locks under the same hash should get different ids");
+
+    // Verify that a lock cannot be for zero tokens
+    let receipt = call_lock_with_hash(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_id_badge,
+                                      XRD,
+                                      TokenQuantity::Fungible(dec!("0")),
+                                      hash(&secret2),
+                                      1000,
+                                      false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2096 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a lock cannot have a deadline that has already
+    // passed
+    let receipt = call_lock_with_hash(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_id_badge,
+                                      XRD,
+                                      TokenQuantity::Fungible(dec!("100")),
+                                      hash(&secret2),
+                                      60,
+                                      false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2024 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    assert_eq!(dec!("900"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Locked funds should have left Alice's pool");
+
+    // Verify that the wrong preimage doesn't work
+    let receipt = call_claim_hash_lock(&mut test_runner,
+                                       &bob,
+                                       escrow,
+                                       &bob_id_badge,
+                                       lock1,
+                                       secret2.clone(),
+                                       false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2097 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a lock that doesn't exist can't be claimed
+    let receipt = call_claim_hash_lock(&mut test_runner,
+                                       &bob,
+                                       escrow,
+                                       &bob_id_badge,
+                                       lock1 + 100,
+                                       secret1.clone(),
+                                       false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2021 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that only the recipient can claim
+    let receipt = call_claim_hash_lock(&mut test_runner,
+                                       &alice,
+                                       escrow,
+                                       &alice_pool_badge,
+                                       lock1,
+                                       secret1.clone(),
+                                       false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2023 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that the owner cannot reclaim before the deadline
+    let receipt = call_reclaim_hash_lock(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         lock1,
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2026 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that the recipient can claim with the right preimage
+    let receipt = call_claim_hash_lock(&mut test_runner,
+                                       &bob,
+                                       escrow,
+                                       &bob_id_badge,
+                                       lock1,
+                                       secret1.clone(),
+                                       true);
+    let result = receipt.expect_commit_success();
+    assert_eq!(dec!("100"),
+               balance_change_amount(result, test_runner.get_component_vaults(bob.account, XRD), XRD),
+               "Bob should be up 100 XRD");
+    let events: Vec<HashLockClaimedEvent> = result.application_events
+        .iter()
+        .filter_map(|(_, data)| scrypto_decode::<HashLockClaimedEvent>(data).ok())
+        .collect();
+    assert_eq!(1, events.len(),
+               "There should be one claim event");
+    assert_eq!(secret1, events[0].preimage,
+               "The claim event should reveal the preimage");
+    assert_eq!(hash(&secret1), events[0].hash_lock,
+               "The claim event should name the hash");
+    assert_eq!(lock1, events[0].lock_id,
+               "The claim event should name the lock");
+    drop(receipt);
+
+    // Verify that a claimed lock cannot be claimed again
+    let receipt = call_claim_hash_lock(&mut test_runner,
+                                       &bob,
+                                       escrow,
+                                       &bob_id_badge,
+                                       lock1,
+                                       secret1.clone(),
+                                       false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2022 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that an expired lock can be reclaimed but not claimed
+    let receipt = call_lock_with_hash(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_id_badge,
+                                      XRD,
+                                      TokenQuantity::Fungible(dec!("200")),
+                                      hash(&secret2),
+                                      1000,
+                                      true);
+    let lock2: u64 = receipt.expect_commit_success().output(3);
+    drop(receipt);
+
+    set_test_runner_clock(&mut test_runner, 1100);
+
+    let receipt = call_claim_hash_lock(&mut test_runner,
+                                       &bob,
+                                       escrow,
+                                       &bob_id_badge,
+                                       lock2,
+                                       secret2.clone(),
+                                       false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2024 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_reclaim_hash_lock(&mut test_runner,
+                           &alice,
+                           escrow,
+                           &alice_pool_badge,
+                           lock2,
+                           true);
+
+    assert_eq!(dec!("900"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Reclaimed funds should be back in Alice's pool");
+}
+
+
+#[test]
+fn test_claim_codes() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+
+    let receipt =
+        call_deposit_funds(&mut test_runner,
+                           &alice,
+                           escrow,
+                           &alice_pool_badge,
+                           None,
+                           XRD,
+                           dec!("1000"),
+                           true);
+    // This also creates the pool so let's remember its Allowance
+    // resource address.
+    let alice_pool_allowance_resource =
+        receipt.expect_commit_success().new_resource_addresses()[0];
+    drop(receipt);
+
+    set_test_runner_clock(&mut test_runner, 100);
+
+    let allowance_secret = "allowance secret".as_bytes().to_vec();
+    let funds_secret = "funds secret".as_bytes().to_vec();
+    let cancelled_secret = "cancelled secret".as_bytes().to_vec();
+
+    call_mint_allowance_claim_code(&mut test_runner,
+                                   &alice,
+                                   escrow,
+                                   &alice_pool_badge,
+                                   hash(&allowance_secret),
+                                   1000,
+                                   false,
+                                   AllowanceLifeCycle::Accumulating,
+                                   XRD,
+                                   Some(TokenQuantity::Fungible(dec!("100"))),
+                                   true);
+
+    // Verify that the Allowance is held by the Escrow until redeemed
+    assert!(get_component_nflids(&mut test_runner, alice.account, alice_pool_allowance_resource)
+            .is_empty(),
+            "Alice should not have received the Allowance");
+
+    // Verify that the wrong secret doesn't work
+    let receipt = call_redeem_claim_code(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         funds_secret.clone(),
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2028 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that Bob gets the Allowance and can use it
+    call_redeem_claim_code(&mut test_runner,
+                           &bob,
+                           escrow,
+                           allowance_secret.clone(),
+                           true);
+    let allowance_nflids =
+        get_component_nflids(&mut test_runner, bob.account, alice_pool_allowance_resource);
+    assert_eq!(1, allowance_nflids.len(),
+               "Bob should have received the Allowance");
+
+    let receipt =
+        call_withdraw_with_allowance(&mut test_runner,
+                                     &bob,
+                                     escrow,
+                                     &NonFungibleGlobalId::new(
+                                         alice_pool_allowance_resource,
+                                         allowance_nflids.first().unwrap().clone()),
+                                     TokenQuantity::Fungible(dec!("40")),
+                                     true);
+    let result = receipt.expect_commit_success();
+    assert_eq!(dec!("40"),
+               balance_change_amount(result, test_runner.get_component_vaults(bob.account, XRD), XRD),
+               "Bob should be up 40 XRD");
+    drop(receipt);
+
+    // Verify that a claim code can only be used once
+    let receipt = call_redeem_claim_code(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         allowance_secret.clone(),
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2029 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a funds claim code needs a max quantity
+    let receipt = call_mint_allowance_claim_code(&mut test_runner,
+                                                 &alice,
+                                                 escrow,
+                                                 &alice_pool_badge,
+                                                 hash(&funds_secret),
+                                                 1000,
+                                                 true,
+                                                 AllowanceLifeCycle::OneOff,
+                                                 XRD,
+                                                 None,
+                                                 false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2098 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a claim code cannot expire in the past
+    let receipt = call_mint_allowance_claim_code(&mut test_runner,
+                                                 &alice,
+                                                 escrow,
+                                                 &alice_pool_badge,
+                                                 hash(&funds_secret),
+                                                 60,
+                                                 true,
+                                                 AllowanceLifeCycle::OneOff,
+                                                 XRD,
+                                                 Some(TokenQuantity::Fungible(dec!("50"))),
+                                                 false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2030 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a funds claim code pays out directly
+    call_mint_allowance_claim_code(&mut test_runner,
+                                   &alice,
+                                   escrow,
+                                   &alice_pool_badge,
+                                   hash(&funds_secret),
+                                   1000,
+                                   true,
+                                   AllowanceLifeCycle::OneOff,
+                                   XRD,
+                                   Some(TokenQuantity::Fungible(dec!("50"))),
+                                   true);
+    let receipt = call_redeem_claim_code(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         funds_secret.clone(),
+                                         true);
+    let result = receipt.expect_commit_success();
+    assert_eq!(dec!("50"),
+               balance_change_amount(result, test_runner.get_component_vaults(bob.account, XRD), XRD),
+               "Bob should be up 50 XRD");
+    drop(receipt);
+    assert_eq!(dec!("910"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Alice's pool should have paid out 90 XRD");
+    assert_eq!(1,
+               get_component_nflids(&mut test_runner, bob.account, alice_pool_allowance_resource)
+               .len(),
+               "Bob should not have received a second Allowance");
+
+    // Verify that a cancelled claim code cannot be redeemed
+    call_mint_allowance_claim_code(&mut test_runner,
+                                   &alice,
+                                   escrow,
+                                   &alice_pool_badge,
+                                   hash(&cancelled_secret),
+                                   1000,
+                                   true,
+                                   AllowanceLifeCycle::OneOff,
+                                   XRD,
+                                   Some(TokenQuantity::Fungible(dec!("50"))),
+                                   true);
+    call_cancel_claim_code(&mut test_runner,
+                           &alice,
+                           escrow,
+                           &alice_pool_badge,
+                           hash(&cancelled_secret),
+                           true);
+    let receipt = call_redeem_claim_code(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         cancelled_secret.clone(),
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2029 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that an expired claim code cannot be redeemed
+    let expiring_secret = "expiring secret".as_bytes().to_vec();
+    call_mint_allowance_claim_code(&mut test_runner,
+                                   &alice,
+                                   escrow,
+                                   &alice_pool_badge,
+                                   hash(&expiring_secret),
+                                   1000,
+                                   true,
+                                   AllowanceLifeCycle::OneOff,
+                                   XRD,
+                                   Some(TokenQuantity::Fungible(dec!("50"))),
+                                   true);
+    set_test_runner_clock(&mut test_runner, 1100);
+    let receipt = call_redeem_claim_code(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         expiring_secret.clone(),
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2030 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+}
+
+
+#[test]
+fn test_standing_orders() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_pool_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_pool_badge =
+        NonFungibleGlobalId::new(bob_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 60);
+
+    // Pays into Bob's pool every 120 seconds from 120 to 1200,
+    // catching up on at most 2 missed payments
+    call_add_standing_order(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            XRD,
+                            dec!("10"),
+                            PaymentRecipient::EscrowPool(escrow, bob_pool_badge.clone()),
+                            120,
+                            120,
+                            Some(1200),
+                            2);
+
+    // Pays into Bob's account every 120 seconds, forever
+    let account_order =
+        call_add_standing_order(&mut test_runner,
+                                &alice,
+                                escrow,
+                                &alice_pool_badge,
+                                XRD,
+                                dec!("1"),
+                                PaymentRecipient::Account(bob.account),
+                                120,
+                                120,
+                                None,
+                                100);
+
+    // Verify that nothing is paid before the first due date
+    call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("1000"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Nothing should have been paid yet");
+
+    // Verify the first payments
+    set_test_runner_clock(&mut test_runner, 120);
+    let result = call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("10"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob's pool should have received one payment");
+    assert_eq!(dec!("1"),
+               balance_change_amount(&result, test_runner.get_component_vaults(bob.account, XRD), XRD),
+               "Bob's account should have received one payment");
+
+    // Verify that a repeat call doesn't pay twice
+    call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("10"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob's pool should not have been paid twice");
+
+    // Five payments have fallen due for each order by now. The pool
+    // order catches up on only two of them.
+    set_test_runner_clock(&mut test_runner, 720);
+    let result = call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("30"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob's pool should have caught up on two payments");
+    assert_eq!(dec!("5"),
+               balance_change_amount(&result, test_runner.get_component_vaults(bob.account, XRD), XRD),
+               "Bob's account should have caught up on five payments");
+
+    call_cancel_standing_order(&mut test_runner,
+                               &alice,
+                               escrow,
+                               &alice_pool_badge,
+                               account_order,
+                               true);
+
+    // The pool order ends at 1200 so only the payments due at 840,
+    // 960, 1080 and 1200 remain, and two of those are skipped.
+    set_test_runner_clock(&mut test_runner, 2400);
+    let result = call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("50"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob's pool should have received its final payments");
+    assert_eq!(dec!("0"),
+               balance_change_amount(&result, test_runner.get_component_vaults(bob.account, XRD), XRD),
+               "The cancelled order should not pay out");
+
+    // Verify that an ended order doesn't pay again
+    set_test_runner_clock(&mut test_runner, 3600);
+    call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("50"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "The ended order should not pay out");
+    assert_eq!(dec!("944"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Alice's pool should have paid out 56 XRD in total");
+
+    // Verify that a cancelled order can't be cancelled again
+    let receipt = call_cancel_standing_order(&mut test_runner,
+                                             &alice,
+                                             escrow,
+                                             &alice_pool_badge,
+                                             account_order,
+                                             false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2034 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that an order must be able to catch up on at least one
+    // payment
+    let receipt = call_add_standing_order_impl(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &alice_pool_badge,
+                                               XRD,
+                                               dec!("1"),
+                                               PaymentRecipient::Account(bob.account),
+                                               3600,
+                                               120,
+                                               None,
+                                               0,
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2099 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that an order can't pay out fractions of NFTs
+    let receipt = call_add_standing_order_impl(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &alice_pool_badge,
+                                               alice_pool_res,
+                                               dec!("1.5"),
+                                               PaymentRecipient::Account(bob.account),
+                                               3600,
+                                               120,
+                                               None,
+                                               1,
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2100 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a payment the recipient's account refuses is left
+    // outstanding, and doesn't hold up other orders
+    let carol = make_user(&mut test_runner, Some(&"Carol".to_owned()));
+    set_default_deposit_rule(&mut test_runner, &carol, DefaultDepositRule::Reject);
+    call_add_standing_order(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            XRD,
+                            dec!("2"),
+                            PaymentRecipient::Account(carol.account),
+                            3600,
+                            120,
+                            None,
+                            10);
+    call_add_standing_order(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            XRD,
+                            dec!("1"),
+                            PaymentRecipient::EscrowPool(escrow, bob_pool_badge.clone()),
+                            3600,
+                            120,
+                            None,
+                            10);
+    let result = call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("0"),
+               balance_change_amount(&result, test_runner.get_component_vaults(carol.account, XRD), XRD),
+               "Carol's account should have refused the payment");
+    assert_eq!(dec!("51"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob's pool should still have been paid");
+    assert_eq!(dec!("943"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "The refused payment should be back in Alice's pool");
+
+    set_default_deposit_rule(&mut test_runner, &carol, DefaultDepositRule::Accept);
+    let result = call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("2"),
+               balance_change_amount(&result, test_runner.get_component_vaults(carol.account, XRD), XRD),
+               "Carol's account should have received the outstanding payment");
+    assert_eq!(dec!("51"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob's pool should not have been paid twice");
+    assert_eq!(dec!("941"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Alice's pool should have made the outstanding payment");
+}
+
+#[test]
+fn test_streams() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_pool_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_pool_badge =
+        NonFungibleGlobalId::new(bob_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    // Streams 0.5 XRD per second to Bob, funded with 120 XRD
+    set_test_runner_clock(&mut test_runner, 60);
+    let stream_id = call_start_stream(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_pool_badge,
+                                      XRD,
+                                      dec!("0.5"),
+                                      dec!("120"));
+    assert_eq!(dec!("880"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Stream funding should have left Alice's pool");
+
+    // Verify that funds accrue over time
+    set_test_runner_clock(&mut test_runner, 120);
+    assert_eq!((dec!("30"), dec!("90")),
+               call_read_stream(&mut test_runner, &bob, escrow, &alice_pool_badge, stream_id),
+               "30 XRD should have accrued");
+
+    // Verify that only the recipient can claim
+    let receipt = call_claim_stream(&mut test_runner,
+                                    &alice,
+                                    escrow,
+                                    &alice_pool_badge,
+                                    &alice_pool_badge,
+                                    stream_id,
+                                    false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2038 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let receipt = call_claim_stream(&mut test_runner,
+                                    &bob,
+                                    escrow,
+                                    &bob_pool_badge,
+                                    &alice_pool_badge,
+                                    stream_id,
+                                    true);
+    assert_eq!(dec!("30"),
+               balance_change_amount(receipt.expect_commit_success(),
+                                     test_runner.get_component_vaults(bob.account, XRD), XRD),
+               "Bob should have claimed 30 XRD");
+    drop(receipt);
+
+    // Verify that nothing accrues while paused
+    set_test_runner_clock(&mut test_runner, 180);
+    call_pause_stream(&mut test_runner, &alice, escrow, &alice_pool_badge, stream_id, true);
+    set_test_runner_clock(&mut test_runner, 300);
+    assert_eq!((dec!("30"), dec!("60")),
+               call_read_stream(&mut test_runner, &bob, escrow, &alice_pool_badge, stream_id),
+               "Nothing should accrue while paused");
+    call_resume_stream(&mut test_runner, &alice, escrow, &alice_pool_badge, stream_id, true);
+
+    // Verify that accrual stops when the funding runs out
+    set_test_runner_clock(&mut test_runner, 480);
+    assert_eq!((dec!("90"), dec!("0")),
+               call_read_stream(&mut test_runner, &bob, escrow, &alice_pool_badge, stream_id),
+               "All funding should have accrued");
+
+    // Verify that a top-up doesn't accrue retroactively
+    call_top_up_stream(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       stream_id,
+                       dec!("50"),
+                       true);
+    assert_eq!((dec!("90"), dec!("50")),
+               call_read_stream(&mut test_runner, &bob, escrow, &alice_pool_badge, stream_id),
+               "The top-up should not have accrued yet");
+
+    // Cancelling pays the accrued funds to Bob and the rest to Alice
+    set_test_runner_clock(&mut test_runner, 540);
+    call_cancel_stream(&mut test_runner, &alice, escrow, &alice_pool_badge, stream_id, true);
+    assert_eq!(dec!("120"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_pool_badge, XRD),
+               "Bob's pool should have received the accrued funds");
+    assert_eq!(dec!("850"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Alice's pool should have received the remaining funding");
+
+    // Verify that a cancelled stream can't be claimed from
+    let receipt = call_claim_stream(&mut test_runner,
+                                    &bob,
+                                    escrow,
+                                    &bob_pool_badge,
+                                    &alice_pool_badge,
+                                    stream_id,
+                                    false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2039 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+}
+
+#[test]
+fn test_reserved_allowances() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("100"),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 10);
+
+    let reserved_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         Some(1000),
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         XRD,
+                                         Some(TokenQuantity::Fungible(dec!("60"))),
+                                         AllowanceOptions { reserved: true, ..Default::default() });
+    assert_eq!(dec!("40"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Reserved funds should no longer be free");
+    assert_eq!(dec!("60"),
+               call_read_reserved_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Funds should have been reserved");
+
+    // Verify that other Allowances can't touch the reserved funds
+    let plain_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::OneOff,
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("100"))));
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &plain_allowance,
+                                 TokenQuantity::Fungible(dec!("50")),
+                                 false);
+
+    // Using the reserved Allowance draws on its reservation
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &reserved_allowance,
+                                 TokenQuantity::Fungible(dec!("20")),
+                                 true);
+    assert_eq!(dec!("40"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Free funds should be unchanged");
+    assert_eq!(dec!("40"),
+               call_read_reserved_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Reservation should have been drawn on");
+
+    // Verify that a valid Allowance keeps its reservation
+    let receipt = call_release_reservation(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &alice_pool_badge,
+                                           &reserved_allowance,
+                                           false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2042 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // A OneOff Allowance releases what it didn't use when it burns
+    let one_off_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::OneOff,
+                                         XRD,
+                                         Some(TokenQuantity::Fungible(dec!("30"))),
+                                         AllowanceOptions { reserved: true, ..Default::default() });
+    assert_eq!(dec!("10"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Reserved funds should no longer be free");
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &one_off_allowance,
+                                 TokenQuantity::Fungible(dec!("10")),
+                                 true);
+    assert_eq!(dec!("30"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Unused reservation should have been released");
+    assert_eq!(dec!("40"),
+               call_read_reserved_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Only the first reservation should remain");
+
+    // Anyone can release the reservation of an expired Allowance
+    set_test_runner_clock(&mut test_runner, 2000);
+    call_release_reservation(&mut test_runner,
+                             &alice,
+                             escrow,
+                             &alice_pool_badge,
+                             &reserved_allowance,
+                             true);
+    assert_eq!(dec!("70"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Expired reservation should have been released");
+    assert_eq!(dec!("0"),
+               call_read_reserved_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "No funds should remain reserved");
+
+    // Verify that the owner can release the reservation of a
+    // recalled Allowance without burning it
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let recalled_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         XRD,
+                                         Some(TokenQuantity::Fungible(dec!("25"))),
+                                         AllowanceOptions { reserved: true, ..Default::default() });
+    give_tokens(&mut test_runner,
+                &alice.account,
+                &alice.nfgid,
+                &bob.account,
+                &recalled_allowance.resource_address(),
+                TokenQuantity::NonFungible(Some([recalled_allowance.local_id().clone()].into()), None));
+    call_recall_nft(&mut test_runner, &alice, &alice_pool_badge, &bob, &recalled_allowance, true);
+
+    let receipt = call_release_reservation(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &alice_pool_badge,
+                                           &recalled_allowance,
+                                           false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2042 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_release_recalled_reservation(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &recalled_allowance,
+                                      true);
+    assert_eq!(dec!("70"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Recalled reservation should have been released");
+    assert_eq!(dec!("0"),
+               call_read_reserved_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "No funds should remain reserved");
+}
+
+#[test]
+fn test_priority_floors() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("100"),
+                       true);
+
+    // Tier 1 Allowances must leave 70 XRD for the other tiers
+    call_set_priority_floor(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            XRD,
+                            1,
+                            dec!("70"));
+
+    let trading_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Repeating{min_delay: None},
+                                         XRD,
+                                         None,
+                                         AllowanceOptions { priority: 1, ..Default::default() });
+    let payroll_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Repeating{min_delay: None},
+                            XRD,
+                            None);
+
+    // Verify that the tier 1 Allowance can't go below its floor
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &trading_allowance,
+                                               TokenQuantity::Fungible(dec!("40")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2043 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &trading_allowance,
+                                 TokenQuantity::Fungible(dec!("30")),
+                                 true);
+
+    // Verify that the tier 0 Allowance can use the funds below it
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &payroll_allowance,
+                                 TokenQuantity::Fungible(dec!("60")),
+                                 true);
+    assert_eq!(dec!("10"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Both Allowances should have withdrawn");
+}
+
+#[test]
+fn test_quote_allowance() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("100"),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 120);
+
+    let repeating_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Repeating{min_delay: Some(10)},
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("30"))));
+
+    assert_eq!(AllowanceQuote {
+                   max_available: dec!("30"),
+                   status: AllowanceStatus::Usable{
+                       remaining: Some(TokenQuantity::Fungible(dec!("30")))},
+                   would_burn: false,
+                   next_use: Some(TimeBound::UnixTime(120)),
+                   failure: None,
+               },
+               call_quote_allowance(&mut test_runner, &alice, escrow, &repeating_allowance,
+                                    TokenQuantity::Fungible(dec!("20"))),
+               "Fresh Allowance should be usable");
+
+    let quote = call_quote_allowance(&mut test_runner, &alice, escrow, &repeating_allowance,
+                                     TokenQuantity::Fungible(dec!("40")));
+    assert!(quote.failure.unwrap().starts_with("2010 "),
+            "Quote above the Allowance should fail");
+
+    // After use the Allowance needs to cool down
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &repeating_allowance,
+                                 TokenQuantity::Fungible(dec!("20")),
+                                 true);
+    let quote = call_quote_allowance(&mut test_runner, &alice, escrow, &repeating_allowance,
+                                     TokenQuantity::Fungible(dec!("20")));
+    assert_eq!(dec!("0"), quote.max_available, "Cooling Allowance can't take anything");
+    assert_eq!(Some(TimeBound::UnixTime(130)), quote.next_use, "Allowance should be usable after its delay");
+    assert!(quote.failure.unwrap().starts_with("2009 "),
+            "Cooling Allowance should fail");
+
+    // The pool limits what an Allowance can take
+    let accumulating_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Accumulating,
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("500"))));
+    let quote = call_quote_allowance(&mut test_runner, &alice, escrow, &accumulating_allowance,
+                                     TokenQuantity::Fungible(dec!("90")));
+    assert_eq!(dec!("80"), quote.max_available, "Pool should limit the Allowance");
+    assert!(quote.failure.unwrap().starts_with("2045 "),
+            "Quote above the pool funds should fail");
+
+    // Verify burn detection
+    let one_off_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::OneOff,
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("10"))));
+    let quote = call_quote_allowance(&mut test_runner, &alice, escrow, &one_off_allowance,
+                                     TokenQuantity::Fungible(dec!("10")));
+    assert!(quote.would_burn, "OneOff Allowance should burn");
+    assert_eq!(None, quote.failure, "OneOff Allowance should be usable");
+
+    // Verify expiry
+    let expired_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            Some(50),
+                            0,
+                            AllowanceLifeCycle::OneOff,
+                            XRD,
+                            None);
+    let quote = call_quote_allowance(&mut test_runner, &alice, escrow, &expired_allowance,
+                                     TokenQuantity::Fungible(dec!("10")));
+    assert_eq!(None, quote.next_use, "Expired Allowance can never be used");
+    assert!(quote.failure.unwrap().starts_with("2011 "),
+            "Expired Allowance should fail");
+}
+
+#[test]
+fn test_allowance_status() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("100"),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 120);
+
+    let allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            Some(1000),
+                            240,
+                            AllowanceLifeCycle::Repeating{min_delay: Some(60)},
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("10"))));
+    assert_eq!(AllowanceStatus::NotYetValid,
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should not be valid yet");
+
+    // Verify that being within min_delay of valid_from doesn't make
+    // an unused Allowance cooling down
+    let unused =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            Some(1000),
+                            180,
+                            AllowanceLifeCycle::Repeating{min_delay: Some(60)},
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("10"))));
+    assert_eq!(AllowanceStatus::NotYetValid,
+               call_allowance_status(&mut test_runner, &alice, escrow, &unused),
+               "Unused Allowance should not yet be valid");
+
+    set_test_runner_clock(&mut test_runner, 240);
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::Fungible(dec!("10")))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should be usable");
+
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::Fungible(dec!("10")),
+                                 true);
+    assert_eq!(AllowanceStatus::CoolingDown{until: 300},
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should be cooling down");
+
+    set_test_runner_clock(&mut test_runner, 2000);
+    assert_eq!(AllowanceStatus::Expired,
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should have expired");
+
+    // Verify exhaustion
+    let allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Repeating{min_delay: None},
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("10"))));
+    call_reduce_allowance_to_amount(&mut test_runner,
+                                    &alice,
+                                    escrow,
+                                    allowance.clone(),
+                                    dec!("0"),
+                                    true);
+    assert_eq!(AllowanceStatus::Exhausted,
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should be exhausted");
+
+    // Verify revocation
+    let allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Repeating{min_delay: None},
+                            XRD,
+                            None);
+    call_revoke_allowance(&mut test_runner, &alice, escrow, &alice_pool_badge, &allowance, true);
+    assert_eq!(AllowanceStatus::Revoked,
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should be revoked");
+
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &allowance,
+                                               TokenQuantity::Fungible(dec!("10")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2046 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+}
+
+#[test]
+fn test_withdraw_with_allowance_up_to() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("100"),
+                       true);
+
+    // Verify that we settle for what the pool holds
+    let accumulating_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Accumulating,
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("500"))));
+    let (achieved, missing, receipt) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &accumulating_allowance,
+                                           TokenQuantity::Fungible(dec!("150")));
+    assert_eq!(TokenQuantity::Fungible(dec!("100")), achieved,
+               "Should get what the pool holds");
+    assert!(missing.is_empty(), "Fungibles are never missing");
+    assert_eq!(dec!("-100"),
+               balance_change_amount(receipt.expect_commit_success(),
+                                     test_runner.get_component_vaults(escrow, XRD),
+                                     XRD),
+               "Escrow should be down 100 XRD");
+    drop(receipt);
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::Fungible(dec!("400")))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &accumulating_allowance),
+               "Allowance should only be debited what was taken");
+
+    // Verify that an empty pool doesn't use up the Allowance
+    let one_off_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::OneOff,
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("50"))));
+    let (achieved, _, _) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &one_off_allowance,
+                                           TokenQuantity::Fungible(dec!("10")));
+    assert!(achieved.is_zero(), "Empty pool should give nothing");
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::Fungible(dec!("50")))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &one_off_allowance),
+               "Allowance should not have been used");
+
+    // Verify that we settle for what the Allowance permits
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("100"),
+                       true);
+    let (achieved, _, _) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &one_off_allowance,
+                                           TokenQuantity::Fungible(dec!("80")));
+    assert_eq!(TokenQuantity::Fungible(dec!("50")), achieved,
+               "Should get what the Allowance permits");
+    assert_eq!(dec!("50"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Pool should have 50 XRD left");
+
+    // Verify that missing nflids are reported
+    let nfts_res =
+        create_nft_resource(&mut test_runner,
+                            &alice,
+                            0,
+                            50,
+                            None);
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       nfts_res,
+                       dec!("50"),
+                       true);
+    let nft_allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Accumulating,
+                            nfts_res,
+                            Some(TokenQuantity::NonFungible(
+                                Some([1.into(), 2.into(), 3.into()].into()),
+                                Some(1))));
+    let (achieved, missing, receipt) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &nft_allowance,
+                                           TokenQuantity::NonFungible(
+                                               Some([1.into(), 2.into(), 500.into()].into()),
+                                               Some(5)));
+    assert_eq!(TokenQuantity::NonFungible(Some([1.into(), 2.into()].into()), Some(1)),
+               achieved,
+               "Should get the nflids that exist, and what the Allowance permits");
+    let expected_missing: IndexSet<NonFungibleLocalId> = [500.into()].into();
+    assert_eq!(expected_missing, missing,
+               "Absent nflid should be reported");
+    assert_eq!(dec!("-3"),
+               balance_change_amount(receipt.expect_commit_success(),
+                                     test_runner.get_component_vaults(escrow, nfts_res),
+                                     nfts_res),
+               "Escrow should be down 3 NFTs");
+    drop(receipt);
+}
+
+#[test]
+fn test_allowance_with_id_ranges() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let nfts_res =
+        create_nft_resource(&mut test_runner,
+                            &alice,
+                            0,
+                            100,
+                            None);
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       nfts_res,
+                       dec!("100"),
+                       true);
+
+    // An Allowance for ids 0 to 9999, except id 5
+    let allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Accumulating,
+                            nfts_res,
+                            Some(TokenQuantity::NonFungibleRanges(
+                                IntegerIdRanges::new(0, 9999).excluding(5, 5),
+                                Some(0))));
+
+    // Verify taking named ids out of the ranges
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &allowance,
+                                               TokenQuantity::NonFungible(
+                                                   Some([1.into(), 2.into()].into()),
+                                                   None),
+                                               true);
+    assert_eq!(dec!("-2"),
+               balance_change_amount(receipt.expect_commit_success(),
+                                     test_runner.get_component_vaults(escrow, nfts_res),
+                                     nfts_res),
+               "Escrow should be down 2 NFTs");
+    drop(receipt);
+
+    // Verify that the excluded id can't be taken
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &allowance,
+                                               TokenQuantity::NonFungible(
+                                                   Some([5.into()].into()),
+                                                   None),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2012 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify taking a range of ids
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &allowance,
+                                               TokenQuantity::NonFungibleRanges(
+                                                   IntegerIdRanges::new(10, 19),
+                                                   None),
+                                               true);
+    assert_eq!(dec!("-10"),
+               balance_change_amount(receipt.expect_commit_success(),
+                                     test_runner.get_component_vaults(escrow, nfts_res),
+                                     nfts_res),
+               "Escrow should be down 10 NFTs");
+    drop(receipt);
+
+    // Verify reducing the ranges by nflids
+    call_reduce_allowance_by_nflids(&mut test_runner,
+                                    &alice,
+                                    escrow,
+                                    allowance.clone(),
+                                    [3.into()].into(),
+                                    true);
+
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::NonFungibleRanges(
+                   IntegerIdRanges::from_ranges(vec![(0, 0), (4, 4), (6, 9), (20, 9999)]),
+                   Some(0)))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance ranges should have been debited");
+}
+
+#[test]
+fn test_stored_allowance_ids() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let nfts_res =
+        create_nft_resource(&mut test_runner,
+                            &alice,
+                            0,
+                            1000,
+                            None);
+    for _ in 0..20 {
+        call_deposit_funds(&mut test_runner,
+                           &alice,
+                           escrow,
+                           &alice_pool_badge,
+                           None,
+                           nfts_res,
+                           dec!("50"),
+                           true);
+    }
+
+    let stored = AllowanceOptions { stored_ids: true, ..Default::default() };
+
+    // A small Allowance for ids 0 to 9
+    let small_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         nfts_res,
+                                         Some(TokenQuantity::NonFungible(
+                                             Some((0..10u64).map(|n| n.into()).collect()),
+                                             Some(0))),
+                                         stored.clone());
+
+    // A large Allowance for ids 10 to 999, built up in batches
+    let large_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         nfts_res,
+                                         Some(TokenQuantity::NonFungible(
+                                             Some((10..100u64).map(|n| n.into()).collect()),
+                                             Some(0))),
+                                         stored.clone());
+    for batch in 1..10u64 {
+        call_add_allowance_ids(&mut test_runner,
+                               &alice,
+                               escrow,
+                               &alice_pool_badge,
+                               &large_allowance,
+                               (batch*100..batch*100+100).map(|n| n.into()).collect(),
+                               true);
+    }
+
+    let nfdata: AllowanceNfData = test_runner.get_non_fungible_data(
+        large_allowance.resource_address(), large_allowance.local_id().clone());
+    assert_eq!(Some(StoredIds { permitted: 990, consumed: 0 }), nfdata.stored_ids,
+               "Large Allowance should count all its ids");
+    assert_eq!(Some(TokenQuantity::NonFungible(None, Some(0))), nfdata.max_amount,
+               "Stored ids should not be in the NFT");
+
+    // Benchmark taking one id with each
+    let small_receipt =
+        call_withdraw_with_allowance(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &small_allowance,
+                                     TokenQuantity::NonFungible(Some([5.into()].into()), None),
+                                     true);
+    let large_receipt =
+        call_withdraw_with_allowance(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &large_allowance,
+                                     TokenQuantity::NonFungible(Some([500.into()].into()), None),
+                                     true);
+    let small_cost = small_receipt.fee_summary.total_execution_cost_units_consumed;
+    let large_cost = large_receipt.fee_summary.total_execution_cost_units_consumed;
+    println!("Cost of using stored ids: {} for 10 ids, {} for 990 ids", small_cost, large_cost);
+    assert!(large_cost <= small_cost + small_cost / 20,
+            "Using a large stored id set should cost about the same as a small one");
+    drop(small_receipt);
+    drop(large_receipt);
+
+    // Verify that stored ids can be taken on a best-effort basis
+    let (achieved, missing, receipt) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &large_allowance,
+                                           TokenQuantity::NonFungible(
+                                               Some([600.into(), 601.into(), 2000.into()].into()),
+                                               None));
+    drop(receipt);
+    assert_eq!(TokenQuantity::NonFungible(Some([600.into(), 601.into()].into()), None),
+               achieved,
+               "Both stored ids in the pool should have been taken");
+    let expected_missing: IndexSet<NonFungibleLocalId> = [2000.into()].into();
+    assert_eq!(expected_missing, missing,
+               "The id not in the pool should be missing");
+    let nfdata: AllowanceNfData = test_runner.get_non_fungible_data(
+        large_allowance.resource_address(), large_allowance.local_id().clone());
+    assert_eq!(Some(StoredIds { permitted: 990, consumed: 3 }), nfdata.stored_ids,
+               "Best-effort withdrawal should consume the stored ids");
+
+    // Verify that a stored id can only be taken once
+    call_deposit_funds_with_non_fungibles(&mut test_runner,
+                                          &alice,
+                                          escrow,
+                                          &alice_pool_badge,
+                                          None,
+                                          nfts_res,
+                                          BTreeSet::from([500.into()]),
+                                          true);
+    let receipt =
+        call_withdraw_with_allowance(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &large_allowance,
+                                     TokenQuantity::NonFungible(Some([500.into()].into()), None),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2012 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify reducing the stored ids
+    call_reduce_allowance_by_nflids(&mut test_runner,
+                                    &alice,
+                                    escrow,
+                                    small_allowance.clone(),
+                                    [0.into(), 1.into(), 5.into(), 50.into()].into(),
+                                    true);
+    let nfdata: AllowanceNfData = test_runner.get_non_fungible_data(
+        small_allowance.resource_address(), small_allowance.local_id().clone());
+    assert_eq!(Some(StoredIds { permitted: 8, consumed: 1 }), nfdata.stored_ids,
+               "Only untaken stored ids should have been removed");
+    let receipt =
+        call_withdraw_with_allowance(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &small_allowance,
+                                     TokenQuantity::NonFungible(Some([0.into()].into()), None),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2012 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+}
+
+#[test]
+fn test_all_and_except_quantities() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    // Verify that the owner can withdraw All
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("100"),
+                       true);
+    let result = call_withdraw(&mut test_runner,
+                               &alice,
+                               escrow,
+                               &alice_pool_badge,
+                               XRD,
+                               TokenQuantity::All);
+    assert_eq!(dec!("-100"),
+               balance_change_amount(&result,
+                                     test_runner.get_component_vaults(escrow, XRD),
+                                     XRD),
+               "All should take the whole vault");
+
+    let nfts_res =
+        create_nft_resource(&mut test_runner,
+                            &alice,
+                            0,
+                            20,
+                            None);
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       nfts_res,
+                       dec!("20"),
+                       true);
+
+    // Our rare ones are ids 0 to 9
+    let rare = NflidSet::Ranges(IntegerIdRanges::new(0, 9));
+    let allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Accumulating,
+                            nfts_res,
+                            Some(TokenQuantity::NonFungibleExcept(rare.clone(), Some(8))));
+
+    // Verify that arbitrary NFTs are never rare ones
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::NonFungible(None, Some(5)),
+                                 true);
+    let held = get_component_nflids(&mut test_runner, escrow, nfts_res);
+    assert_eq!(15, held.len(), "Should have taken five NFTs");
+    assert!(to_nflids(0..10).iter().all(|nflid| held.contains(nflid)),
+            "No rare ones should have left");
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(
+        TokenQuantity::NonFungibleExcept(rare.clone(), Some(3)))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should be down five NFTs");
+
+    // Verify that a rare one cannot be named
+    let receipt =
+        call_withdraw_with_allowance(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &allowance,
+                                     TokenQuantity::NonFungible(Some([3.into()].into()), None),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2012 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that asking for more than the non-rare ones fails
+    let receipt =
+        call_withdraw_with_allowance(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &allowance,
+                                     TokenQuantity::All,
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2012 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that up_to takes what the Allowance has left
+    let (achieved, _, receipt) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &allowance,
+                                           TokenQuantity::All);
+    assert_eq!(dec!("3"), achieved.to_amount(), "Should take the last three");
+    drop(receipt);
+    let held = get_component_nflids(&mut test_runner, escrow, nfts_res);
+    assert_eq!(12, held.len(),
+               "Should hold the ten rare ones and two more");
+    assert!(to_nflids(0..10).iter().all(|nflid| held.contains(nflid)),
+            "Still no rare ones should have left");
+
+    // Verify that the owner can take everything but the rare ones
+    let result = call_withdraw(&mut test_runner,
+                               &alice,
+                               escrow,
+                               &alice_pool_badge,
+                               nfts_res,
+                               TokenQuantity::NonFungibleExcept(rare, None));
+    assert_eq!(dec!("-2"),
+               balance_change_amount(&result,
+                                     test_runner.get_component_vaults(escrow, nfts_res),
+                                     nfts_res),
+               "Should take the two non-rare ones");
+    assert_eq!(to_nflids(0..10).into_iter().collect::<BTreeSet<_>>(),
+               get_component_nflids(&mut test_runner, escrow, nfts_res),
+               "Only rare ones should be left");
+}
+
+#[test]
+fn test_scheduled_allowance() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    // 15 January 2024, 12:00 UTC
+    set_test_runner_clock(&mut test_runner, 1705320000);
+
+    let monthly =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Scheduled{
+                                schedule: CalendarSchedule::Monthly{day: 31}},
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("10"))));
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &monthly,
+                                 TokenQuantity::Fungible(dec!("10")),
+                                 true);
+    assert_eq!(AllowanceStatus::CoolingDown{until: 1706659200},
+               call_allowance_status(&mut test_runner, &alice, escrow, &monthly),
+               "Should next be usable on 31 January");
+
+    // Verify that one that hasn't been used yet isn't cooling down
+    let unused =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            1706659200,
+                            AllowanceLifeCycle::Scheduled{
+                                schedule: CalendarSchedule::Monthly{day: 31}},
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("10"))));
+    assert_eq!(AllowanceStatus::NotYetValid,
+               call_allowance_status(&mut test_runner, &alice, escrow, &unused),
+               "Unused Allowance should not yet be valid");
+
+    // Verify that it can't be used again this period
+    let receipt =
+        call_withdraw_with_allowance(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &monthly,
+                                     TokenQuantity::Fungible(dec!("1")),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2009 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // 31 January 2024, 00:00 UTC
+    set_test_runner_clock(&mut test_runner, 1706659200);
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &monthly,
+                                 TokenQuantity::Fungible(dec!("10")),
+                                 true);
+    assert_eq!(AllowanceStatus::CoolingDown{until: 1709164800},
+               call_allowance_status(&mut test_runner, &alice, escrow, &monthly),
+               "Should next be usable on the leap day");
+
+    // 29 February 2024, 10:00 UTC
+    set_test_runner_clock(&mut test_runner, 1709200800);
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &monthly,
+                                 TokenQuantity::Fungible(dec!("10")),
+                                 true);
+    assert_eq!(AllowanceStatus::CoolingDown{until: 1711843200},
+               call_allowance_status(&mut test_runner, &alice, escrow, &monthly),
+               "Should next be usable on 31 March, not a month after last use");
+
+    // Verify a quarterly Allowance crossing into a new year
+    let quarterly =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Scheduled{
+                                schedule: CalendarSchedule::Quarterly},
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("10"))));
+
+    // 20 November 2024, 08:00 UTC
+    set_test_runner_clock(&mut test_runner, 1732089600);
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &quarterly,
+                                 TokenQuantity::Fungible(dec!("10")),
+                                 true);
+    assert_eq!(AllowanceStatus::CoolingDown{until: 1735689600},
+               call_allowance_status(&mut test_runner, &alice, escrow, &quarterly),
+               "Should next be usable on 1 January");
+
+    // 1 January 2025, 00:00 UTC
+    set_test_runner_clock(&mut test_runner, 1735689600);
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::Fungible(dec!("10")))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &quarterly),
+               "Should be usable in the new quarter");
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::Fungible(dec!("10")))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &monthly),
+               "Monthly should have been usable since March");
+}
+
+#[test]
+fn test_epoch_time_bounds() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_pool_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_pool_badge =
+        NonFungibleGlobalId::new(bob_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 100);
+    set_test_runner_epoch(&mut test_runner, 10);
+
+    let allowance =
+        call_mint_allowance_with_time_bounds(&mut test_runner,
+                                             &alice,
+                                             escrow,
+                                             &alice_pool_badge,
+                                             Some(TimeBound::Epoch(30)),
+                                             TimeBound::Epoch(20),
+                                             AllowanceLifeCycle::Repeating{min_delay: None},
+                                             XRD,
+                                             Some(TokenQuantity::Fungible(dec!("10"))),
+                                             AllowanceOptions::default());
+    assert_eq!(AllowanceStatus::NotYetValid,
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should not be valid before epoch 20");
+    let quote = call_quote_allowance(&mut test_runner, &alice, escrow, &allowance,
+                                     TokenQuantity::Fungible(dec!("10")));
+    assert_eq!(Some(TimeBound::Epoch(20)), quote.next_use,
+               "Quote should give the epoch the Allowance becomes valid");
+
+    // Unix time has no say in an epoch bound
+    set_test_runner_clock(&mut test_runner, 1_000_000);
+    assert_eq!(AllowanceStatus::NotYetValid,
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should still not be valid");
+
+    set_test_runner_epoch(&mut test_runner, 20);
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::Fungible(dec!("10")),
+                                 true);
+
+    set_test_runner_epoch(&mut test_runner, 30);
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::Fungible(dec!("10")))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should be usable through its last epoch");
+
+    set_test_runner_epoch(&mut test_runner, 31);
+    assert_eq!(AllowanceStatus::Expired,
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should have expired after epoch 30");
+
+    // Verify a deal with an epoch deadline
+    let deal = call_open_deal_with_deadline(&mut test_runner,
+                                            &alice,
+                                            escrow,
+                                            &alice_pool_badge,
+                                            &bob_pool_badge,
+                                            None,
+                                            XRD,
+                                            TokenQuantity::Fungible(dec!("300")),
+                                            TimeBound::Epoch(40));
+
+    set_test_runner_epoch(&mut test_runner, 40);
+    let receipt = call_refund_deal(&mut test_runner,
+                                   &bob,
+                                   escrow,
+                                   &deal,
+                                   false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2018 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    set_test_runner_epoch(&mut test_runner, 41);
+    call_refund_deal(&mut test_runner,
+                     &bob,
+                     escrow,
+                     &deal,
+                     true);
+    assert_eq!(dec!("990"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Deal funds should be back in Alice's pool");
+}
+
+#[test]
+fn test_allowance_usage() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+    let play_resource =
+        test_runner.create_fungible_resource(dec!("10000"), 18, alice.account);
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 120);
+
+    let allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Repeating{min_delay: None},
+                                         XRD,
+                                         Some(TokenQuantity::Fungible(dec!("50"))),
+                                         AllowanceOptions { max_uses: Some(3),
+                                                            ..Default::default() });
+
+    let nfdata = test_runner.get_non_fungible_data::<AllowanceNfData>(
+        allowance.resource_address(),
+        allowance.local_id().clone());
+    assert_eq!(AllowanceUsage {
+                   total_withdrawn: dec!("0"),
+                   total_subsidy: dec!("0"),
+                   use_count: 0,
+                   last_used: None,
+                   since_co_signed: dec!("0"),
+               },
+               nfdata.usage,
+               "New Allowance should be unused");
+
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::Fungible(dec!("10")),
+                                 true);
+
+    set_test_runner_clock(&mut test_runner, 240);
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::Fungible(dec!("15")),
+                                 true);
+
+    let nfdata = test_runner.get_non_fungible_data::<AllowanceNfData>(
+        allowance.resource_address(),
+        allowance.local_id().clone());
+    assert_eq!(AllowanceUsage {
+                   total_withdrawn: dec!("25"),
+                   total_subsidy: dec!("0"),
+                   use_count: 2,
+                   last_used: Some(240),
+                   since_co_signed: dec!("0"),
+               },
+               nfdata.usage,
+               "Usage should count both withdrawals");
+
+    set_test_runner_clock(&mut test_runner, 360);
+    call_subsidize_with_allowance_and_play(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           alice_pool_badge.clone(),
+                                           allowance.clone(),
+                                           dec!("5"),
+                                           play_resource,
+                                           false,
+                                           true);
+
+    let nfdata = test_runner.get_non_fungible_data::<AllowanceNfData>(
+        allowance.resource_address(),
+        allowance.local_id().clone());
+    assert_eq!(AllowanceUsage {
+                   total_withdrawn: dec!("25"),
+                   total_subsidy: dec!("5"),
+                   use_count: 3,
+                   last_used: Some(360),
+                   since_co_signed: dec!("0"),
+               },
+               nfdata.usage,
+               "Subsidy should be counted separately");
+
+    // Verify that a Repeating Allowance runs out of uses
+    assert_eq!(AllowanceStatus::Exhausted,
+               call_allowance_status(&mut test_runner, &alice, escrow, &allowance),
+               "Allowance should have no uses left");
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &allowance,
+                                               TokenQuantity::Fungible(dec!("10")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2047 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+}
+
+#[test]
+fn test_per_use_limits() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    let merchant_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         XRD,
+                                         Some(TokenQuantity::Fungible(dec!("1000"))),
+                                         AllowanceOptions { min_per_use: Some(dec!("1")),
+                                                            max_per_use: Some(dec!("50")),
+                                                            ..Default::default() });
+
+    // Verify that dust can't be taken
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &merchant_allowance,
+                                               TokenQuantity::Fungible(dec!("0.5")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2066 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a single charge can't be too large
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &merchant_allowance,
+                                               TokenQuantity::Fungible(dec!("60")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2067 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &merchant_allowance,
+                                 TokenQuantity::Fungible(dec!("50")),
+                                 true);
+
+    let quote = call_quote_allowance(&mut test_runner, &alice, escrow, &merchant_allowance,
+                                     TokenQuantity::Fungible(dec!("100")));
+    assert_eq!(dec!("50"), quote.max_available,
+               "Quote should not exceed the per-use maximum");
+    assert!(quote.failure.unwrap().starts_with("2067 "),
+            "Quote should report the per-use maximum");
+
+    let (achieved, _, receipt) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &merchant_allowance,
+                                           TokenQuantity::Fungible(dec!("100")));
+    assert_eq!(TokenQuantity::Fungible(dec!("50")), achieved,
+               "Should get no more than the per-use maximum");
+    drop(receipt);
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::Fungible(dec!("900")))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &merchant_allowance),
+               "Allowance should be down by both charges");
+
+    // Verify that a best-effort withdrawal takes nothing rather than
+    // dust
+    call_withdraw(&mut test_runner,
+                  &alice,
+                  escrow,
+                  &alice_pool_badge,
+                  XRD,
+                  TokenQuantity::Fungible(dec!("899.5")));
+    let (achieved, _, receipt) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &merchant_allowance,
+                                           TokenQuantity::Fungible(dec!("10")));
+    assert_eq!(TokenQuantity::Fungible(dec!("0")), achieved,
+               "Should not take less than the per-use minimum");
+    drop(receipt);
+    assert_eq!(dec!("0.5"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Pool should be untouched");
+    assert_eq!(AllowanceStatus::Usable{remaining: Some(TokenQuantity::Fungible(dec!("900")))},
+               call_allowance_status(&mut test_runner, &alice, escrow, &merchant_allowance),
+               "Allowance should not have been used");
+
+    // Verify that NFT counts are limited the same way
+    let nfts_res =
+        create_nft_resource(&mut test_runner,
+                            &alice,
+                            0,
+                            20,
+                            None);
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       nfts_res,
+                       dec!("20"),
+                       true);
+    let nft_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         nfts_res,
+                                         Some(TokenQuantity::NonFungible(None, Some(10))),
+                                         AllowanceOptions { min_per_use: Some(dec!("2")),
+                                                            max_per_use: Some(dec!("3")),
+                                                            ..Default::default() });
+
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &nft_allowance,
+                                               TokenQuantity::NonFungible(
+                                                   Some([1.into()].into()),
+                                                   None),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2066 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &nft_allowance,
+                                               TokenQuantity::NonFungible(None, Some(4)),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2067 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &nft_allowance,
+                                 TokenQuantity::NonFungible(
+                                     Some([1.into(), 2.into(), 3.into()].into()),
+                                     None),
+                                 true);
+
+    // Verify that the last use can take a remainder below the
+    // per-use minimum
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &nft_allowance,
+                                 TokenQuantity::NonFungible(None, Some(3)),
+                                 true);
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &nft_allowance,
+                                 TokenQuantity::NonFungible(None, Some(3)),
+                                 true);
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &nft_allowance,
+                                               TokenQuantity::NonFungible(None, Some(1)),
+                                               true);
+    assert_eq!(dec!("1"),
+               balance_change_amount(receipt.expect_commit_success(),
+                                     test_runner.get_component_vaults(alice.account, nfts_res),
+                                     nfts_res),
+               "Should take the single NFT left");
+    drop(receipt);
+}
+
+#[test]
+fn test_percentage_of_balance() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    let market_maker_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Repeating{min_delay: None},
+                                         XRD,
+                                         None,
+                                         AllowanceOptions { max_percentage: Some(dec!("10")),
+                                                            ..Default::default() });
+
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &market_maker_allowance,
+                                               TokenQuantity::Fungible(dec!("101")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2070 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &market_maker_allowance,
+                                 TokenQuantity::Fungible(dec!("100")),
+                                 true);
+
+    // With 900 left in the pool the limit is now 90
+    let quote = call_quote_allowance(&mut test_runner, &alice, escrow, &market_maker_allowance,
+                                     TokenQuantity::Fungible(dec!("91")));
+    assert_eq!(dec!("90"), quote.max_available,
+               "Quote should follow the shrinking pool");
+    assert!(quote.failure.unwrap().starts_with("2070 "),
+            "Quote should report the percentage limit");
+
+    let (achieved, _, receipt) =
+        call_withdraw_with_allowance_up_to(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &market_maker_allowance,
+                                           TokenQuantity::Fungible(dec!("1000")));
+    assert_eq!(TokenQuantity::Fungible(dec!("90")), achieved,
+               "Should get 10% of what the pool holds");
+    drop(receipt);
+
+    // Verify that an Accumulating Allowance is held to both limits
+    let accumulating_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         XRD,
+                                         Some(TokenQuantity::Fungible(dec!("100"))),
+                                         AllowanceOptions { max_percentage: Some(dec!("10")),
+                                                            ..Default::default() });
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &accumulating_allowance,
+                                 TokenQuantity::Fungible(dec!("81")),
+                                 true);
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &accumulating_allowance,
+                                               TokenQuantity::Fungible(dec!("20")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2010 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that NFT counts are rounded down
+    let nfts_res =
+        create_nft_resource(&mut test_runner,
+                            &alice,
+                            0,
+                            25,
+                            None);
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       nfts_res,
+                       dec!("25"),
+                       true);
+    let nft_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Repeating{min_delay: None},
+                                         nfts_res,
+                                         None,
+                                         AllowanceOptions { max_percentage: Some(dec!("10")),
+                                                            ..Default::default() });
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &nft_allowance,
+                                               TokenQuantity::NonFungible(None, Some(3)),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2070 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &nft_allowance,
+                                 TokenQuantity::NonFungible(None, Some(2)),
+                                 true);
+}
+
+#[test]
+fn test_co_signed_allowance() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_pool_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_pool_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_badge_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_badge =
+        NonFungibleGlobalId::new(bob_badge_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    let allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         None,
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         XRD,
+                                         Some(TokenQuantity::Fungible(dec!("500"))),
+                                         AllowanceOptions {
+                                             co_signer: Some(CoSigner {
+                                                 approver: bob_badge.clone(),
+                                                 threshold: dec!("100"),
+                                             }),
+                                             ..Default::default() });
+
+    // Verify that small withdrawals don't need the co-signer
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::Fungible(dec!("100")),
+                                 true);
+
+    // Verify that large withdrawals do
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &allowance,
+                                               TokenQuantity::Fungible(dec!("150")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        matches!(error, RuntimeError::SystemModuleError(SystemModuleError::AuthError(..)))
+    });
+    drop(receipt);
+
+    let receipt =
+        call_withdraw_with_co_signed_allowance(&mut test_runner,
+                                               &alice,
+                                               &bob,
+                                               &bob_badge,
+                                               escrow,
+                                               &allowance,
+                                               TokenQuantity::Fungible(dec!("150")),
+                                               true);
+    assert_eq!(dec!("-150"),
+               balance_change_amount(receipt.expect_commit_success(),
+                                     test_runner.get_component_vaults(escrow, XRD),
+                                     XRD),
+               "Co-signed withdrawal should go through");
+    drop(receipt);
+
+    // Verify that small withdrawals add up to the threshold
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &alice,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::Fungible(dec!("60")),
+                                 true);
+    let receipt = call_withdraw_with_allowance(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &allowance,
+                                               TokenQuantity::Fungible(dec!("50")),
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        matches!(error, RuntimeError::SystemModuleError(SystemModuleError::AuthError(..)))
+    });
+    drop(receipt);
+
+    call_withdraw_with_co_signed_allowance(&mut test_runner,
+                                           &alice,
+                                           &bob,
+                                           &bob_badge,
+                                           escrow,
+                                           &allowance,
+                                           TokenQuantity::Fungible(dec!("50")),
+                                           true);
+    let nfdata = test_runner.get_non_fungible_data::<AllowanceNfData>(
+        allowance.resource_address(),
+        allowance.local_id().clone());
+    assert_eq!(dec!("0"), nfdata.usage.since_co_signed,
+               "Co-signing should start the count afresh");
+}
+
+#[test]
+fn test_pool_recovery() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_badge_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_badge_res, 1.into());
+    // Alice's replacement badge, for when she loses the first one
+    let alice_new_badge =
+        NonFungibleGlobalId::new(alice_badge_res, 2.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_badge_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_badge =
+        NonFungibleGlobalId::new(bob_badge_res, 1.into());
+
+    let carol = make_user(&mut test_runner, Some(&"Carol".to_owned()));
+    let carol_badge_res =
+        test_runner.create_non_fungible_resource(carol.account);
+    let carol_badge =
+        NonFungibleGlobalId::new(carol_badge_res, 1.into());
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1061"),
+                       true);
+
+    let allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Accumulating,
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("100"))));
+    give_tokens(&mut test_runner,
+                &alice.account,
+                &alice.nfgid,
+                &bob.account,
+                &allowance.resource_address(),
+                TokenQuantity::NonFungible(Some([allowance.local_id().clone()].into()), None));
+
+    // A Deal, a hash lock and a claim code made with the old badge,
+    // for checking who can manage them after the recovery
+    let deal = call_open_deal(&mut test_runner,
+                              &alice,
+                              escrow,
+                              &alice_pool_badge,
+                              &bob_badge,
+                              None,
+                              XRD,
+                              TokenQuantity::Fungible(dec!("10")),
+                              2000);
+    let receipt = call_lock_with_hash(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_badge,
+                                      XRD,
+                                      TokenQuantity::Fungible(dec!("1")),
+                                      hash("recovery secret".as_bytes()),
+                                      1200,
+                                      true);
+    let lock_id: u64 = receipt.expect_commit_success().output(3);
+    drop(receipt);
+    let code_hash = hash("recovery code".as_bytes());
+    call_mint_allowance_claim_code(&mut test_runner,
+                                   &alice,
+                                   escrow,
+                                   &alice_pool_badge,
+                                   code_hash,
+                                   2000,
+                                   false,
+                                   AllowanceLifeCycle::Accumulating,
+                                   XRD,
+                                   Some(TokenQuantity::Fungible(dec!("5"))),
+                                   true);
+
+    // A reservation, a stream and a standing order on the pool, for
+    // checking that calls naming the old badge still find it
+    let reserved_allowance =
+        call_mint_allowance_with_options(&mut test_runner,
+                                         &alice,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         Some(1200),
+                                         0,
+                                         AllowanceLifeCycle::Accumulating,
+                                         XRD,
+                                         Some(TokenQuantity::Fungible(dec!("20"))),
+                                         AllowanceOptions { reserved: true, ..Default::default() });
+    let stream_id = call_start_stream(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_badge,
+                                      XRD,
+                                      dec!("0.01"),
+                                      dec!("30"));
+    call_add_standing_order(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            XRD,
+                            dec!("1"),
+                            PaymentRecipient::EscrowPool(escrow, bob_badge.clone()),
+                            1200,
+                            600,
+                            None,
+                            1);
+
+    // Verify guardian validation
+    let receipt = call_set_guardians(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &alice_pool_badge,
+                                     Some(Guardians {
+                                         badges: [bob_badge.clone()].into(),
+                                         threshold: 2,
+                                         challenge_period: 960,
+                                     }),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2073 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let receipt = call_set_guardians(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &alice_pool_badge,
+                                     Some(Guardians {
+                                         badges: [bob_badge.clone(), carol_badge.clone()].into(),
+                                         threshold: 2,
+                                         challenge_period: 0,
+                                     }),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2102 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_set_guardians(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       Some(Guardians {
+                           badges: [bob_badge.clone(), carol_badge.clone()].into(),
+                           threshold: 2,
+                           challenge_period: 960,
+                       }),
+                       true);
+
+    set_test_runner_clock(&mut test_runner, 120);
+
+    // Verify that only guardians can approve
+    let receipt = call_approve_recovery(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_new_badge,
+                                        &alice_pool_badge,
+                                        &alice_new_badge,
+                                        false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2072 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_approve_recovery(&mut test_runner,
+                          &bob,
+                          escrow,
+                          &bob_badge,
+                          &alice_pool_badge,
+                          &alice_new_badge,
+                          true);
+    assert_eq!(None,
+               call_read_recovery(&mut test_runner, &alice, escrow, &alice_pool_badge)
+               .unwrap().completes_at,
+               "One approval should not be enough");
+
+    call_approve_recovery(&mut test_runner,
+                          &carol,
+                          escrow,
+                          &carol_badge,
+                          &alice_pool_badge,
+                          &alice_new_badge,
+                          true);
+    assert_eq!(Some(1080),
+               call_read_recovery(&mut test_runner, &alice, escrow, &alice_pool_badge)
+               .unwrap().completes_at,
+               "Challenge period should have started");
+
+    // Verify that the owner can cancel
+    call_cancel_recovery(&mut test_runner, &alice, escrow, &alice_pool_badge, true);
+    assert_eq!(None,
+               call_read_recovery(&mut test_runner, &alice, escrow, &alice_pool_badge),
+               "Recovery should be cancelled");
+
+    call_approve_recovery(&mut test_runner,
+                          &bob,
+                          escrow,
+                          &bob_badge,
+                          &alice_pool_badge,
+                          &alice_new_badge,
+                          true);
+
+    // Verify that a guardian backing someone else leaves the other
+    // approvals in place
+    call_approve_recovery(&mut test_runner,
+                          &carol,
+                          escrow,
+                          &carol_badge,
+                          &alice_pool_badge,
+                          &carol_badge,
+                          true);
+    let recovery =
+        call_read_recovery(&mut test_runner, &alice, escrow, &alice_pool_badge).unwrap();
+    assert_eq!(None, recovery.completes_at,
+               "Split approvals should not be enough");
+    assert_eq!(Some(&alice_new_badge), recovery.approvals.get(&bob_badge),
+               "Bob's approval should still stand");
+
+    call_approve_recovery(&mut test_runner,
+                          &carol,
+                          escrow,
+                          &carol_badge,
+                          &alice_pool_badge,
+                          &alice_new_badge,
+                          true);
+    let recovery =
+        call_read_recovery(&mut test_runner, &alice, escrow, &alice_pool_badge).unwrap();
+    assert_eq!(Some(alice_new_badge.clone()), recovery.new_owner,
+               "Guardians should have agreed on the new owner");
+    assert_eq!(2, recovery.approvals.len(),
+               "Carol's approval should have moved");
+
+    // Verify that the challenge period must run out
+    set_test_runner_clock(&mut test_runner, 1079);
+    let receipt = call_complete_recovery(&mut test_runner, &bob, escrow, &alice_pool_badge, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2076 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    set_test_runner_clock(&mut test_runner, 1080);
+    call_complete_recovery(&mut test_runner, &bob, escrow, &alice_pool_badge, true);
+
+    assert_eq!(dec!("1000"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_new_badge, XRD),
+               "Funds should have moved to the new owner");
+
+    // Verify that existing Allowances still work
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &bob,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::Fungible(dec!("50")),
+                                 true);
+    assert_eq!(dec!("950"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_new_badge, XRD),
+               "Allowance should draw on the moved pool");
+
+    // Verify that deposits to the old owner go to the new one
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("10"),
+                       true);
+    assert_eq!(dec!("960"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_new_badge, XRD),
+               "Deposit should follow the pool");
+
+    // Verify that the recall authority has moved
+    call_recall_nft(&mut test_runner, &alice, &alice_pool_badge, &bob, &allowance, false);
+    call_recall_nft(&mut test_runner, &alice, &alice_new_badge, &bob, &allowance, true);
+
+    // Verify that only the new owner can approve the old owner's Deal
+    let receipt = call_approve_deal(&mut test_runner, &alice, escrow, &alice_pool_badge, &deal, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2015 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+    call_approve_deal(&mut test_runner, &alice, escrow, &alice_new_badge, &deal, true);
+
+    // Verify that only the new owner can cancel the old owner's claim code
+    let receipt = call_cancel_claim_code(&mut test_runner, &alice, escrow, &alice_pool_badge, code_hash, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2031 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+    call_cancel_claim_code(&mut test_runner, &alice, escrow, &alice_new_badge, code_hash, true);
+
+    // Verify that only the new owner can reclaim the old owner's hash lock
+    set_test_runner_clock(&mut test_runner, 1260);
+    let receipt = call_reclaim_hash_lock(&mut test_runner, &alice, escrow, &alice_pool_badge, lock_id, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2025 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+    call_reclaim_hash_lock(&mut test_runner, &alice, escrow, &alice_new_badge, lock_id, true);
+    assert_eq!(dec!("961"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_new_badge, XRD),
+               "Reclaimed funds should go to the new owner");
+
+    // Verify that streams can still be read and claimed under the old badge
+    let stream = call_read_stream(&mut test_runner, &bob, escrow, &alice_pool_badge, stream_id);
+    assert_eq!(call_read_stream(&mut test_runner, &bob, escrow, &alice_new_badge, stream_id),
+               stream,
+               "Old badge should find the moved stream");
+    assert!(stream.0 > dec!("0"), "Stream should have accrued funds");
+    call_claim_stream(&mut test_runner, &bob, escrow, &bob_badge, &alice_pool_badge, stream_id, true);
+    assert_eq!(dec!("0"),
+               call_read_stream(&mut test_runner, &bob, escrow, &alice_new_badge, stream_id).0,
+               "Accrued funds should have been claimed");
+
+    // Verify that keepers can still run standing orders under the old badge
+    call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("11"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_badge, XRD),
+               "Standing order should have paid from the moved pool");
+
+    // Verify that reservations can still be released under the old badge
+    call_release_reservation(&mut test_runner,
+                             &bob,
+                             escrow,
+                             &alice_pool_badge,
+                             &reserved_allowance,
+                             true);
+    assert_eq!(dec!("980"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_new_badge, XRD),
+               "Expired reservation should return to the moved pool");
+}
+
+#[test]
+fn test_inheritance() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_badge_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_badge_res, 1.into());
+    let alice_other_badge =
+        NonFungibleGlobalId::new(alice_badge_res, 2.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_badge_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_badge =
+        NonFungibleGlobalId::new(bob_badge_res, 1.into());
+
+    let carol = make_user(&mut test_runner, Some(&"Carol".to_owned()));
+    let carol_badge_res =
+        test_runner.create_non_fungible_resource(carol.account);
+    let carol_badge =
+        NonFungibleGlobalId::new(carol_badge_res, 1.into());
+    let carol_other_badge =
+        NonFungibleGlobalId::new(carol_badge_res, 2.into());
+
+    set_test_runner_clock(&mut test_runner, 60);
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    // Verify inheritance validation
+    let receipt = call_set_inheritance(&mut test_runner,
+                                       &alice,
+                                       escrow,
+                                       &alice_pool_badge,
+                                       Some(Inheritance {
+                                           shares: [(bob_badge.clone(), dec!("0"))].into(),
+                                           inactivity_period: 600,
+                                       }),
+                                       false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2078 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    set_test_runner_clock(&mut test_runner, 120);
+
+    let inheritance = Inheritance {
+        shares: [(bob_badge.clone(), dec!("1")),
+                 (carol_badge.clone(), dec!("3"))].into(),
+        inactivity_period: 600,
+    };
+    call_set_inheritance(&mut test_runner,
+                         &alice,
+                         escrow,
+                         &alice_pool_badge,
+                         Some(inheritance.clone()),
+                         true);
+    assert_eq!(Some(inheritance),
+               call_read_inheritance(&mut test_runner, &alice, escrow, &alice_pool_badge),
+               "Inheritance should be set");
+    assert_eq!(120,
+               call_read_last_active(&mut test_runner, &alice, escrow, &alice_pool_badge),
+               "Setting the inheritance should count as activity");
+
+    // Verify that the owner's activity holds off the beneficiaries
+    set_test_runner_clock(&mut test_runner, 600);
+    call_withdraw(&mut test_runner,
+                  &alice,
+                  escrow,
+                  &alice_pool_badge,
+                  XRD,
+                  TokenQuantity::Fungible(dec!("200")));
+    assert_eq!(600,
+               call_read_last_active(&mut test_runner, &alice, escrow, &alice_pool_badge),
+               "Withdrawing should count as activity");
+
+    set_test_runner_clock(&mut test_runner, 1140);
+    let receipt = call_claim_inheritance(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         &bob_badge,
+                                         Some(XRD),
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2081 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    set_test_runner_clock(&mut test_runner, 1200);
+
+    // Verify that only beneficiaries can claim
+    let receipt = call_claim_inheritance(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         &alice_other_badge,
+                                         Some(XRD),
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2080 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Anyone can pay out a beneficiary's share
+    call_claim_inheritance(&mut test_runner,
+                           &alice,
+                           escrow,
+                           &alice_pool_badge,
+                           &bob_badge,
+                           Some(XRD),
+                           true);
+    assert_eq!(dec!("200"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_badge, XRD),
+               "Bob should have a quarter of the pool");
+
+    let receipt = call_claim_inheritance(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         &bob_badge,
+                                         Some(XRD),
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2082 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that a shared pool can't be taken over
+    let receipt = call_claim_inheritance(&mut test_runner,
+                                         &carol,
+                                         escrow,
+                                         &alice_pool_badge,
+                                         &carol_badge,
+                                         None,
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2083 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_claim_inheritance(&mut test_runner,
+                           &carol,
+                           escrow,
+                           &alice_pool_badge,
+                           &carol_badge,
+                           Some(XRD),
+                           true);
+    assert_eq!(dec!("600"),
+               call_read_funds(&mut test_runner, &carol, escrow, &carol_badge, XRD),
+               "Carol should have the rest of the pool");
+    assert_eq!(dec!("0"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Alice's pool should be empty");
+
+    // Verify that a sole beneficiary can take over the pool
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_other_badge,
+                       None,
+                       XRD,
+                       dec!("100"),
+                       true);
+    call_set_inheritance(&mut test_runner,
+                         &alice,
+                         escrow,
+                         &alice_other_badge,
+                         Some(Inheritance {
+                             shares: [(carol_other_badge.clone(), dec!("1"))].into(),
+                             inactivity_period: 0,
+                         }),
+                         true);
+
+    // Verify that nobody can block the takeover by paying out a share
+    let receipt = call_claim_inheritance(&mut test_runner,
+                                         &bob,
+                                         escrow,
+                                         &alice_other_badge,
+                                         &carol_other_badge,
+                                         Some(XRD),
+                                         false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2103 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_claim_inheritance(&mut test_runner,
+                           &bob,
+                           escrow,
+                           &alice_other_badge,
+                           &carol_other_badge,
+                           None,
+                           true);
+    assert_eq!(None,
+               call_read_inheritance(&mut test_runner, &carol, escrow, &carol_other_badge),
+               "The new owner should start without an inheritance");
+    call_withdraw(&mut test_runner,
+                  &carol,
+                  escrow,
+                  &carol_other_badge,
+                  XRD,
+                  TokenQuantity::Fungible(dec!("100")));
+}
+
+#[test]
+fn test_savings_lock() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_badge_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_badge_res, 1.into());
+
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_badge_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_badge =
+        NonFungibleGlobalId::new(bob_badge_res, 1.into());
+
+    set_test_runner_clock(&mut test_runner, 60);
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    let allowance =
+        call_mint_allowance(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            None,
+                            0,
+                            AllowanceLifeCycle::Accumulating,
+                            XRD,
+                            Some(TokenQuantity::Fungible(dec!("200"))));
+    give_tokens(&mut test_runner,
+                &alice.account,
+                &alice.nfgid,
+                &bob.account,
+                &allowance.resource_address(),
+                TokenQuantity::NonFungible(Some([allowance.local_id().clone()].into()), None));
+
+    let inheritance = Inheritance {
+        shares: [(bob_badge.clone(), dec!("1"))].into(),
+        inactivity_period: 6000,
+    };
+    call_set_inheritance(&mut test_runner,
+                         &alice,
+                         escrow,
+                         &alice_pool_badge,
+                         Some(inheritance.clone()),
+                         true);
+    call_add_standing_order(&mut test_runner,
+                            &alice,
+                            escrow,
+                            &alice_pool_badge,
+                            XRD,
+                            dec!("30"),
+                            PaymentRecipient::EscrowPool(escrow, bob_badge.clone()),
+                            960,
+                            60,
+                            Some(960),
+                            1);
+
+    // Verify lock validation
+    let receipt = call_set_savings_lock(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_pool_badge,
+                                        None,
+                                        SavingsLock {
+                                            until: TimeBound::UnixTime(600),
+                                            limit: Some(SavingsLimit {
+                                                amount: dec!("100"),
+                                                period: 0,
+                                            }),
+                                        },
+                                        false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2084 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Lock the whole pool
+    call_set_savings_lock(&mut test_runner,
+                          &alice,
+                          escrow,
+                          &alice_pool_badge,
+                          None,
+                          SavingsLock {
+                              until: TimeBound::UnixTime(600),
+                              limit: None,
+                          },
+                          true);
+
+    let receipt = call_withdraw_impl(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &alice_pool_badge,
+                                     XRD,
+                                     TokenQuantity::Fungible(dec!("1")),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2086 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that the lock can't be shortened
+    let receipt = call_set_savings_lock(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_pool_badge,
+                                        None,
+                                        SavingsLock {
+                                            until: TimeBound::UnixTime(300),
+                                            limit: None,
+                                        },
+                                        false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2085 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Limit XRD to 100 per 300 seconds, beyond the pool lock
+    let xrd_lock = SavingsLock {
+        until: TimeBound::UnixTime(1200),
+        limit: Some(SavingsLimit {
+            amount: dec!("100"),
+            period: 300,
+        }),
+    };
+    call_set_savings_lock(&mut test_runner,
+                          &alice,
+                          escrow,
+                          &alice_pool_badge,
+                          Some(XRD),
+                          xrd_lock.clone(),
+                          true);
+    assert_eq!(Some(xrd_lock),
+               call_read_savings_lock(&mut test_runner, &alice, escrow, &alice_pool_badge,
+                                      Some(XRD)),
+               "XRD lock should be set");
+
+    // Verify that new ways of taking funds can't be set up
+    let receipt = call_mint_allowance_impl(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &alice_pool_badge,
+                                           None,
+                                           0,
+                                           AllowanceLifeCycle::Accumulating,
+                                           XRD,
+                                           Some(TokenQuantity::Fungible(dec!("100"))),
+                                           AllowanceOptions::default(),
+                                           false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2104 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let receipt = call_add_standing_order_impl(&mut test_runner,
+                                               &alice,
+                                               escrow,
+                                               &alice_pool_badge,
+                                               XRD,
+                                               dec!("30"),
+                                               PaymentRecipient::EscrowPool(escrow, bob_badge.clone()),
+                                               960,
+                                               60,
+                                               None,
+                                               1,
+                                               false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2104 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let receipt = call_set_inheritance(&mut test_runner,
+                                       &alice,
+                                       escrow,
+                                       &alice_pool_badge,
+                                       Some(Inheritance {
+                                           shares: inheritance.shares.clone(),
+                                           inactivity_period: 600,
+                                       }),
+                                       false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2104 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Making the beneficiaries wait longer is fine
+    call_set_inheritance(&mut test_runner,
+                         &alice,
+                         escrow,
+                         &alice_pool_badge,
+                         Some(Inheritance {
+                             shares: inheritance.shares.clone(),
+                             inactivity_period: 12000,
+                         }),
+                         true);
+
+    // Once the pool lock runs out the XRD limit applies
+    set_test_runner_clock(&mut test_runner, 660);
+
+    // Verify that funds coming back from a Deal no longer count
+    // against the limit
+    let deal = call_open_deal(&mut test_runner,
+                              &alice,
+                              escrow,
+                              &alice_pool_badge,
+                              &bob_badge,
+                              Some(bob_badge.clone()),
+                              XRD,
+                              TokenQuantity::Fungible(dec!("60")),
+                              2000);
+    call_settle_deal(&mut test_runner,
+                     &bob,
+                     escrow,
+                     &bob_badge,
+                     &deal,
+                     TokenQuantity::Fungible(dec!("0")),
+                     true);
+
+    call_withdraw(&mut test_runner,
+                  &alice,
+                  escrow,
+                  &alice_pool_badge,
+                  XRD,
+                  TokenQuantity::Fungible(dec!("60")));
+    let receipt = call_withdraw_impl(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &alice_pool_badge,
+                                     XRD,
+                                     TokenQuantity::Fungible(dec!("50")),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2087 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+    call_withdraw(&mut test_runner,
+                  &alice,
+                  escrow,
+                  &alice_pool_badge,
+                  XRD,
+                  TokenQuantity::Fungible(dec!("40")));
+
+    // Verify that the limit can't be raised
+    let receipt = call_set_savings_lock(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_pool_badge,
+                                        Some(XRD),
+                                        SavingsLock {
+                                            until: TimeBound::UnixTime(1200),
+                                            limit: Some(SavingsLimit {
+                                                amount: dec!("200"),
+                                                period: 300,
+                                            }),
+                                        },
+                                        false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2085 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that existing Allowances are unaffected
+    call_withdraw_with_allowance(&mut test_runner,
+                                 &bob,
+                                 escrow,
+                                 &allowance,
+                                 TokenQuantity::Fungible(dec!("200")),
+                                 true);
+
+    // A new period starts
+    set_test_runner_clock(&mut test_runner, 960);
+    call_withdraw(&mut test_runner,
+                  &alice,
+                  escrow,
+                  &alice_pool_badge,
+                  XRD,
+                  TokenQuantity::Fungible(dec!("100")));
+
+    // Verify that standing orders count against the limit
+    call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+
+    // Verify that deposits are unaffected
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("10"),
+                       true);
+    assert_eq!(dec!("610"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Pool should hold what's left");
+
+    // Once the lock runs out the standing order goes through
+    set_test_runner_clock(&mut test_runner, 1200);
+    call_execute_due_orders(&mut test_runner, &bob, escrow, &alice_pool_badge);
+    assert_eq!(dec!("30"),
+               call_read_funds(&mut test_runner, &bob, escrow, &bob_badge, XRD),
+               "Bob should have been paid");
+
+    // Once the lock runs out everything can be withdrawn
+    call_withdraw_all_of(&mut test_runner,
+                         &alice,
+                         escrow,
+                         &alice_pool_badge,
+                         XRD);
+    assert_eq!(dec!("0"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Pool should be empty");
+}
+
+#[test]
+fn test_delayed_withdrawals() {
+    let (mut test_runner, alice, package) = setup_for_test();
+
+    let escrow = call_instantiate(&mut test_runner, &alice, package);
+
+    let alice_badge_res =
+        test_runner.create_non_fungible_resource(alice.account);
+    let alice_pool_badge =
+        NonFungibleGlobalId::new(alice_badge_res, 1.into());
+
+    // Bob keeps the canceller badge for Alice
+    let bob = make_user(&mut test_runner, Some(&"Bob".to_owned()));
+    let bob_badge_res =
+        test_runner.create_non_fungible_resource(bob.account);
+    let bob_badge =
+        NonFungibleGlobalId::new(bob_badge_res, 1.into());
+
+    set_test_runner_clock(&mut test_runner, 60);
+
+    call_deposit_funds(&mut test_runner,
+                       &alice,
+                       escrow,
+                       &alice_pool_badge,
+                       None,
+                       XRD,
+                       dec!("1000"),
+                       true);
+
+    // Verify that requests need a withdrawal delay
+    let receipt = call_request_withdraw(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_pool_badge,
+                                        XRD,
+                                        TokenQuantity::Fungible(dec!("300")),
+                                        PaymentRecipient::Account(alice.account),
+                                        false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2090 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify withdrawal delay validation
+    let receipt = call_set_withdrawal_delay(&mut test_runner,
+                                            &alice,
+                                            escrow,
+                                            &alice_pool_badge,
+                                            Some(WithdrawalDelay {
+                                                thresholds: [(XRD, dec!("100"))].into(),
+                                                period: 600,
+                                                delay: -1,
+                                                canceller: bob_badge.clone(),
+                                            }),
+                                            None,
+                                            false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2089 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // A delay of zero would let requests be finalized at once
+    let receipt = call_set_withdrawal_delay(&mut test_runner,
+                                            &alice,
+                                            escrow,
+                                            &alice_pool_badge,
+                                            Some(WithdrawalDelay {
+                                                thresholds: [(XRD, dec!("100"))].into(),
+                                                period: 600,
+                                                delay: 0,
+                                                canceller: bob_badge.clone(),
+                                            }),
+                                            None,
+                                            false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2089 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let withdrawal_delay = WithdrawalDelay {
+        thresholds: [(XRD, dec!("100"))].into(),
+        period: 600,
+        delay: 600,
+        canceller: bob_badge.clone(),
+    };
+    call_set_withdrawal_delay(&mut test_runner,
+                              &alice,
+                              escrow,
+                              &alice_pool_badge,
+                              Some(withdrawal_delay.clone()),
+                              None,
+                              true);
+
+    // Verify that large withdrawals must be requested
+    let receipt = call_withdraw_impl(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &alice_pool_badge,
+                                     XRD,
+                                     TokenQuantity::Fungible(dec!("150")),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2088 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    call_withdraw(&mut test_runner,
+                  &alice,
+                  escrow,
+                  &alice_pool_badge,
+                  XRD,
+                  TokenQuantity::Fungible(dec!("100")));
+
+    // Verify that the owner alone can't remove the delay
+    let receipt = call_set_withdrawal_delay(&mut test_runner,
+                                            &alice,
+                                            escrow,
+                                            &alice_pool_badge,
+                                            None,
+                                            None,
+                                            false);
+    receipt.expect_specific_failure(|error| {
+        matches!(error, RuntimeError::SystemModuleError(SystemModuleError::AuthError(..)))
+    });
+    drop(receipt);
+
+    call_set_withdrawal_delay(&mut test_runner,
+                              &alice,
+                              escrow,
+                              &alice_pool_badge,
+                              Some(withdrawal_delay),
+                              Some((&bob, &bob_badge)),
+                              true);
+
+    // Request a withdrawal
+    let receipt = call_request_withdraw(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_pool_badge,
+                                        XRD,
+                                        TokenQuantity::Fungible(dec!("300")),
+                                        PaymentRecipient::Account(alice.account),
+                                        true);
+    let result = receipt.expect_commit_success();
+    let first_id: u64 = result.output(3);
+    let events: Vec<WithdrawalRequestedEvent> = result.application_events
+        .iter()
+        .filter_map(|(_, data)| scrypto_decode::<WithdrawalRequestedEvent>(data).ok())
+        .collect();
+    assert_eq!(1, events.len(),
+               "There should be one request event");
+    assert_eq!(dec!("300"), events[0].amount,
+               "The request event should give the amount");
+    assert_eq!(660, events[0].finalizes_at,
+               "The request event should say when it can be finalized");
+    drop(receipt);
+    assert_eq!(dec!("600"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Requested funds should have left the pool");
+
+    let receipt = call_finalize_withdraw(&mut test_runner, &bob, escrow,
+                                         &alice_pool_badge, first_id, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2093 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Request another one and cancel it
+    let receipt = call_request_withdraw(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_pool_badge,
+                                        XRD,
+                                        TokenQuantity::Fungible(dec!("200")),
+                                        PaymentRecipient::Account(alice.account),
+                                        true);
+    let second_id: u64 = receipt.expect_commit_success().output(3);
+    drop(receipt);
+
+    let receipt = call_cancel_withdraw(&mut test_runner, &alice, escrow, &alice_pool_badge,
+                                       &alice_pool_badge, second_id, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2094 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let receipt = call_cancel_withdraw(&mut test_runner, &bob, escrow, &bob_badge,
+                                       &alice_pool_badge, second_id, true);
+    let events: Vec<WithdrawalCancelledEvent> = receipt.expect_commit_success().application_events
+        .iter()
+        .filter_map(|(_, data)| scrypto_decode::<WithdrawalCancelledEvent>(data).ok())
+        .collect();
+    assert_eq!(1, events.len(),
+               "There should be one cancel event");
+    assert_eq!(second_id, events[0].withdrawal_id,
+               "The cancel event should name the withdrawal");
+    drop(receipt);
+    assert_eq!(dec!("600"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Cancelled funds should be back in the pool");
+
+    let receipt = call_cancel_withdraw(&mut test_runner, &bob, escrow, &bob_badge,
+                                       &alice_pool_badge, second_id, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2092 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Anyone can finalize once the delay has passed
+    set_test_runner_clock(&mut test_runner, 660);
+    let receipt = call_finalize_withdraw(&mut test_runner, &bob, escrow,
+                                         &alice_pool_badge, first_id, true);
+    let result = receipt.expect_commit_success();
+    assert_eq!(dec!("300"),
+               balance_change_amount(result, test_runner.get_component_vaults(alice.account, XRD), XRD),
+               "Alice should be up 300 XRD");
+    let events: Vec<WithdrawalFinalizedEvent> = result.application_events
+        .iter()
+        .filter_map(|(_, data)| scrypto_decode::<WithdrawalFinalizedEvent>(data).ok())
+        .collect();
+    assert_eq!(1, events.len(),
+               "There should be one finalize event");
+    drop(receipt);
+
+    let receipt = call_finalize_withdraw(&mut test_runner, &bob, escrow,
+                                         &alice_pool_badge, first_id, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2092 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let receipt = call_finalize_withdraw(&mut test_runner, &bob, escrow,
+                                         &alice_pool_badge, 9, false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2091 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that the threshold applies to everything taken out in
+    // a period
+    call_withdraw(&mut test_runner,
+                  &alice,
+                  escrow,
+                  &alice_pool_badge,
+                  XRD,
+                  TokenQuantity::Fungible(dec!("60")));
+    let receipt = call_withdraw_impl(&mut test_runner,
+                                     &alice,
+                                     escrow,
+                                     &alice_pool_badge,
+                                     XRD,
+                                     TokenQuantity::Fungible(dec!("50")),
+                                     false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2088 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    let receipt = call_open_deal_impl(&mut test_runner,
+                                      &alice,
+                                      escrow,
+                                      &alice_pool_badge,
+                                      &bob_badge,
+                                      None,
+                                      XRD,
+                                      TokenQuantity::Fungible(dec!("50")),
+                                      TimeBound::UnixTime(2000),
+                                      false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2088 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+
+    // Verify that the owner alone can't mint Allowances or change
+    // the inheritance
+    let receipt = call_mint_allowance_impl(&mut test_runner,
+                                           &alice,
+                                           escrow,
+                                           &alice_pool_badge,
+                                           None,
+                                           0,
+                                           AllowanceLifeCycle::Accumulating,
+                                           XRD,
+                                           Some(TokenQuantity::Fungible(dec!("500"))),
+                                           AllowanceOptions::default(),
+                                           false);
+    receipt.expect_specific_failure(|error| {
+        matches!(error, RuntimeError::SystemModuleError(SystemModuleError::AuthError(..)))
+    });
+    drop(receipt);
+
+    let receipt = call_set_inheritance(&mut test_runner,
+                                       &alice,
+                                       escrow,
+                                       &alice_pool_badge,
+                                       Some(Inheritance {
+                                           shares: [(alice.nfgid.clone(), dec!("1"))].into(),
+                                           inactivity_period: 0,
+                                       }),
+                                       false);
+    receipt.expect_specific_failure(|error| {
+        matches!(error, RuntimeError::SystemModuleError(SystemModuleError::AuthError(..)))
+    });
+    drop(receipt);
+
+    // Verify that a cancelled request no longer counts against a
+    // savings lock
+    call_set_savings_lock(&mut test_runner,
+                          &alice,
+                          escrow,
+                          &alice_pool_badge,
+                          Some(XRD),
+                          SavingsLock {
+                              until: TimeBound::UnixTime(3000),
+                              limit: Some(SavingsLimit {
+                                  amount: dec!("100"),
+                                  period: 600,
+                              }),
+                          },
+                          true);
+    let receipt = call_request_withdraw(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_pool_badge,
+                                        XRD,
+                                        TokenQuantity::Fungible(dec!("100")),
+                                        PaymentRecipient::Account(alice.account),
+                                        true);
+    let third_id: u64 = receipt.expect_commit_success().output(3);
+    drop(receipt);
+    call_cancel_withdraw(&mut test_runner, &bob, escrow, &bob_badge,
+                         &alice_pool_badge, third_id, true);
+    call_request_withdraw(&mut test_runner,
+                          &alice,
+                          escrow,
+                          &alice_pool_badge,
+                          XRD,
+                          TokenQuantity::Fungible(dec!("100")),
+                          PaymentRecipient::Account(alice.account),
+                          true);
+    let receipt = call_request_withdraw(&mut test_runner,
+                                        &alice,
+                                        escrow,
+                                        &alice_pool_badge,
+                                        XRD,
+                                        TokenQuantity::Fungible(dec!("1")),
+                                        PaymentRecipient::Account(alice.account),
+                                        false);
+    receipt.expect_specific_failure(|error| {
+        if let RuntimeError::ApplicationError(ApplicationError::PanicMessage(msg)) = error {
+            msg.starts_with("2087 ")
+        } else {
+            false
+        }
+    });
+    drop(receipt);
+    assert_eq!(dec!("440"),
+               call_read_funds(&mut test_runner, &alice, escrow, &alice_pool_badge, XRD),
+               "Only the second request should have left the pool");
+}
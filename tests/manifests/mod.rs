@@ -3,7 +3,10 @@ use scrypto_unit::*;
 use transaction::builder::ManifestBuilder;
 use radix_engine::transaction::{CommitResult, TransactionReceipt};
 use escrow::token_quantity::TokenQuantity;
-use escrow::AllowanceLifeCycle;
+use escrow::time_bound::TimeBound;
+use escrow::{AllowanceLifeCycle, AllowanceOptions, AllowanceQuote, AllowanceStatus,
+             ClaimCodeTerms, Guardians, Inheritance, PaymentRecipient, Recovery, SavingsLock,
+             WithdrawalDelay};
 
 use crate::common::*;
 
@@ -160,6 +163,25 @@ pub fn call_withdraw(test_runner: &mut DefaultTestRunner,
                  caller: &NonFungibleGlobalId,
                  resource: ResourceAddress,
                  quantity: TokenQuantity) -> CommitResult
+{
+    let receipt = call_withdraw_impl(test_runner,
+                                     user,
+                                     escrow,
+                                     caller,
+                                     resource,
+                                     quantity,
+                                     true);
+
+    receipt.expect_commit_success().clone()
+}
+
+pub fn call_withdraw_impl(test_runner: &mut DefaultTestRunner,
+                      user: &User,
+                      escrow: ComponentAddress,
+                      caller: &NonFungibleGlobalId,
+                      resource: ResourceAddress,
+                      quantity: TokenQuantity,
+                      expect_success: bool) -> TransactionReceipt
 {
     let manifest = ManifestBuilder::new()
         .create_proof_from_account_of_non_fungibles(
@@ -180,12 +202,12 @@ pub fn call_withdraw(test_runner: &mut DefaultTestRunner,
         vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
     );
 
-    if !receipt.is_commit_success() {
+    if receipt.is_commit_success() != expect_success {
         println!("{:?}", receipt);
-        panic!("TRANSACTION FAIL");
+        panic!("TRANSACTION BAD");
     }
 
-    receipt.expect_commit_success().clone()
+    receipt
 }
 
 pub fn call_withdraw_all_of(test_runner: &mut DefaultTestRunner,
@@ -574,6 +596,52 @@ pub fn call_mint_allowance(test_runner: &mut DefaultTestRunner,
                        life_cycle: AllowanceLifeCycle,
                        for_resource: ResourceAddress,
                        max_amount: Option<TokenQuantity>) -> NonFungibleGlobalId
+{
+    call_mint_allowance_with_options(test_runner,
+                                     user,
+                                     escrow,
+                                     caller,
+                                     valid_until,
+                                     valid_from,
+                                     life_cycle,
+                                     for_resource,
+                                     max_amount,
+                                     AllowanceOptions::default())
+}
+
+pub fn call_mint_allowance_with_options(test_runner: &mut DefaultTestRunner,
+                                    user: &User,
+                                    escrow: ComponentAddress,
+                                    caller: &NonFungibleGlobalId,
+                                    valid_until: Option<i64>,
+                                    valid_from: i64,
+                                    life_cycle: AllowanceLifeCycle,
+                                    for_resource: ResourceAddress,
+                                    max_amount: Option<TokenQuantity>,
+                                    options: AllowanceOptions) -> NonFungibleGlobalId
+{
+    call_mint_allowance_with_time_bounds(test_runner,
+                                         user,
+                                         escrow,
+                                         caller,
+                                         valid_until.map(TimeBound::UnixTime),
+                                         TimeBound::UnixTime(valid_from),
+                                         life_cycle,
+                                         for_resource,
+                                         max_amount,
+                                         options)
+}
+
+pub fn call_mint_allowance_with_time_bounds(test_runner: &mut DefaultTestRunner,
+                                        user: &User,
+                                        escrow: ComponentAddress,
+                                        caller: &NonFungibleGlobalId,
+                                        valid_until: Option<TimeBound>,
+                                        valid_from: TimeBound,
+                                        life_cycle: AllowanceLifeCycle,
+                                        for_resource: ResourceAddress,
+                                        max_amount: Option<TokenQuantity>,
+                                        options: AllowanceOptions) -> NonFungibleGlobalId
 {
     let manifest = ManifestBuilder::new()
         .create_proof_from_account_of_non_fungibles(
@@ -589,7 +657,8 @@ pub fn call_mint_allowance(test_runner: &mut DefaultTestRunner,
                                     valid_from,
                                     life_cycle,
                                     for_resource,
-                                    max_amount))
+                                    max_amount,
+                                    options))
         .deposit_batch(user.account)
         .build();
     let receipt = test_runner.execute_manifest_ignoring_fee(
@@ -619,6 +688,286 @@ pub fn call_mint_allowance(test_runner: &mut DefaultTestRunner,
     NonFungibleGlobalId::new(allowance_resaddr, added.first().unwrap().clone())
 }
 
+pub fn call_mint_allowance_impl(test_runner: &mut DefaultTestRunner,
+                            user: &User,
+                            escrow: ComponentAddress,
+                            caller: &NonFungibleGlobalId,
+                            valid_until: Option<i64>,
+                            valid_from: i64,
+                            life_cycle: AllowanceLifeCycle,
+                            for_resource: ResourceAddress,
+                            max_amount: Option<TokenQuantity>,
+                            options: AllowanceOptions,
+                            expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            caller.resource_address(),
+            BTreeSet::from([caller.local_id().clone()]))
+        .pop_from_auth_zone("caller_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "mint_allowance",
+            |lookup| manifest_args!(lookup.proof("caller_proof"),
+                                    valid_until.map(TimeBound::UnixTime),
+                                    TimeBound::UnixTime(valid_from),
+                                    life_cycle,
+                                    for_resource,
+                                    max_amount,
+                                    options))
+        .deposit_batch(user.account)
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_read_reserved_funds(
+    test_runner: &mut DefaultTestRunner,
+    user: &User,
+    escrow: ComponentAddress,
+    owner: &NonFungibleGlobalId,
+    resource: ResourceAddress)
+    -> Decimal
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "read_reserved_funds",
+                     manifest_args!(owner, resource))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(1)
+}
+
+pub fn call_release_reservation(test_runner: &mut DefaultTestRunner,
+                            user: &User,
+                            escrow: ComponentAddress,
+                            owner: &NonFungibleGlobalId,
+                            allowance: &NonFungibleGlobalId,
+                            expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            escrow,
+            "release_reservation",
+            manifest_args!(owner, allowance.local_id()))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_release_recalled_reservation(test_runner: &mut DefaultTestRunner,
+                                     user: &User,
+                                     escrow: ComponentAddress,
+                                     owner: &NonFungibleGlobalId,
+                                     allowance: &NonFungibleGlobalId,
+                                     expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            allowance.resource_address(),
+            BTreeSet::from([allowance.local_id().clone()]))
+        .pop_from_auth_zone("allowance_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "release_recalled_reservation",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    lookup.proof("allowance_proof")))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_allowance_status(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         allowance: &NonFungibleGlobalId) -> AllowanceStatus
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "allowance_status",
+                     manifest_args!(allowance))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(1)
+}
+
+pub fn call_add_allowance_ids(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          allowance: &NonFungibleGlobalId,
+                          nflids: IndexSet<NonFungibleLocalId>,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "add_allowance_ids",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    allowance,
+                                    nflids))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_revoke_allowance(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         owner: &NonFungibleGlobalId,
+                         allowance: &NonFungibleGlobalId,
+                         expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "revoke_allowance",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    allowance))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_quote_allowance(test_runner: &mut DefaultTestRunner,
+                        user: &User,
+                        escrow: ComponentAddress,
+                        allowance: &NonFungibleGlobalId,
+                        quantity: TokenQuantity) -> AllowanceQuote
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "quote_allowance",
+                     manifest_args!(allowance, quantity))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(1)
+}
+
+pub fn call_set_priority_floor(test_runner: &mut DefaultTestRunner,
+                           user: &User,
+                           escrow: ComponentAddress,
+                           owner: &NonFungibleGlobalId,
+                           resource: ResourceAddress,
+                           priority: u8,
+                           floor: Decimal) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "set_priority_floor",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    resource,
+                                    priority,
+                                    floor))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt
+}
+
 pub fn call_reduce_allowance_to_amount(test_runner: &mut DefaultTestRunner,
                                    user: &User,
                                    escrow: ComponentAddress,
@@ -726,28 +1075,41 @@ pub fn call_withdraw_with_allowance(test_runner: &mut DefaultTestRunner,
     receipt.clone()
 }
 
-pub fn call_add_trusted_resource(test_runner: &mut DefaultTestRunner,
-                             user: &User,
-                             escrow: ComponentAddress,
-                             caller: &NonFungibleGlobalId,
-                             resource: ResourceAddress,
-                             succeed: bool) -> TransactionReceipt
+/// Like [call_withdraw_with_allowance] but with a proof of
+/// `approver`, held by `co_signer`, in the auth zone.
+pub fn call_withdraw_with_co_signed_allowance(test_runner: &mut DefaultTestRunner,
+                                          user: &User,
+                                          co_signer: &User,
+                                          approver: &NonFungibleGlobalId,
+                                          escrow: ComponentAddress,
+                                          allowance: &NonFungibleGlobalId,
+                                          quantity: TokenQuantity,
+                                          succeed: bool) -> TransactionReceipt
 {
     let manifest = ManifestBuilder::new()
         .create_proof_from_account_of_non_fungibles(
+            co_signer.account,
+            approver.resource_address(),
+            BTreeSet::from([approver.local_id().clone()]))
+        .withdraw_non_fungibles_from_account(
             user.account,
-            caller.resource_address(),
-            BTreeSet::from([caller.local_id().clone()]))
-        .pop_from_auth_zone("caller_proof")
+            allowance.resource_address(),
+            BTreeSet::from([allowance.local_id().clone()]))
+        .take_non_fungibles_from_worktop(
+            allowance.resource_address(),
+            BTreeSet::from([allowance.local_id().clone()]),
+            "allowance_bucket")
         .call_method_with_name_lookup(
             escrow,
-            "add_trusted_resource",
-            |lookup| manifest_args!(lookup.proof("caller_proof"),
-                                    resource))
+            "withdraw_with_allowance",
+            |lookup| manifest_args!(lookup.bucket("allowance_bucket"),
+                                    quantity))
+        .deposit_batch(user.account)
         .build();
     let receipt = test_runner.execute_manifest_ignoring_fee(
         manifest,
-        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey),
+             NonFungibleGlobalId::from_public_key(&co_signer.pubkey)],
     );
 
     if receipt.is_commit_success() != succeed {
@@ -763,12 +1125,89 @@ pub fn call_add_trusted_resource(test_runner: &mut DefaultTestRunner,
     receipt.clone()
 }
 
-pub fn call_remove_trusted_resource(test_runner: &mut DefaultTestRunner,
-                                user: &User,
-                                escrow: ComponentAddress,
-                                caller: &NonFungibleGlobalId,
-                                resource: ResourceAddress,
-                                succeed: bool) -> TransactionReceipt
+/// Returns the quantity achieved and the nflids that were missing,
+/// along with the receipt.
+pub fn call_withdraw_with_allowance_up_to(test_runner: &mut DefaultTestRunner,
+                                      user: &User,
+                                      escrow: ComponentAddress,
+                                      allowance: &NonFungibleGlobalId,
+                                      quantity: TokenQuantity)
+                                      -> (TokenQuantity, IndexSet<NonFungibleLocalId>, TransactionReceipt)
+{
+    let manifest = ManifestBuilder::new()
+        .withdraw_non_fungibles_from_account(
+            user.account,
+            allowance.resource_address(),
+            BTreeSet::from([allowance.local_id().clone()]))
+        .take_non_fungibles_from_worktop(
+            allowance.resource_address(),
+            BTreeSet::from([allowance.local_id().clone()]),
+            "allowance_bucket")
+        .call_method_with_name_lookup(
+            escrow,
+            "withdraw_with_allowance_up_to",
+            |lookup| manifest_args!(lookup.bucket("allowance_bucket"),
+                                    quantity))
+        .deposit_batch(user.account)
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    let (_, achieved, missing, _): (Own, TokenQuantity, IndexSet<NonFungibleLocalId>, Option<Own>) =
+        receipt.expect_commit_success().output(3);
+    (achieved, missing, receipt)
+}
+
+pub fn call_add_trusted_resource(test_runner: &mut DefaultTestRunner,
+                             user: &User,
+                             escrow: ComponentAddress,
+                             caller: &NonFungibleGlobalId,
+                             resource: ResourceAddress,
+                             succeed: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            caller.resource_address(),
+            BTreeSet::from([caller.local_id().clone()]))
+        .pop_from_auth_zone("caller_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "add_trusted_resource",
+            |lookup| manifest_args!(lookup.proof("caller_proof"),
+                                    resource))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != succeed {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    if succeed {
+        receipt.expect_commit_success();
+    } else {
+        receipt.expect_commit_failure();
+    }
+    receipt.clone()
+}
+
+pub fn call_remove_trusted_resource(test_runner: &mut DefaultTestRunner,
+                                user: &User,
+                                escrow: ComponentAddress,
+                                caller: &NonFungibleGlobalId,
+                                resource: ResourceAddress,
+                                succeed: bool) -> TransactionReceipt
 {
     let manifest = ManifestBuilder::new()
         .create_proof_from_account_of_non_fungibles(
@@ -939,3 +1378,1243 @@ pub fn call_is_nfgid_trusted(test_runner: &mut DefaultTestRunner,
     }
     (receipt.clone(), answer)
 }
+
+pub fn call_open_deal(test_runner: &mut DefaultTestRunner,
+                  user: &User,
+                  escrow: ComponentAddress,
+                  payer: &NonFungibleGlobalId,
+                  payee: &NonFungibleGlobalId,
+                  arbiter: Option<NonFungibleGlobalId>,
+                  resource: ResourceAddress,
+                  quantity: TokenQuantity,
+                  deadline: i64) -> NonFungibleGlobalId
+{
+    call_open_deal_with_deadline(test_runner,
+                                 user,
+                                 escrow,
+                                 payer,
+                                 payee,
+                                 arbiter,
+                                 resource,
+                                 quantity,
+                                 TimeBound::UnixTime(deadline))
+}
+
+pub fn call_open_deal_with_deadline(test_runner: &mut DefaultTestRunner,
+                                user: &User,
+                                escrow: ComponentAddress,
+                                payer: &NonFungibleGlobalId,
+                                payee: &NonFungibleGlobalId,
+                                arbiter: Option<NonFungibleGlobalId>,
+                                resource: ResourceAddress,
+                                quantity: TokenQuantity,
+                                deadline: TimeBound) -> NonFungibleGlobalId
+{
+    let receipt = call_open_deal_impl(test_runner,
+                                      user,
+                                      escrow,
+                                      payer,
+                                      payee,
+                                      arbiter,
+                                      resource,
+                                      quantity,
+                                      deadline,
+                                      true);
+
+    let result = receipt.expect_commit_success().clone();
+
+    // This only works because the locked funds are XRD and so the
+    // only non-XRD balance change is the receipt we're interested
+    // in.
+    let receipt_resaddr = result.vault_balance_changes()
+        .iter()
+        .filter(|(_, (res, _))| *res != XRD)
+        .collect::<Vec<_>>()[0].1.0;
+
+    let (added, _) = balance_change_nflids(
+        &result,
+        test_runner.get_component_vaults(user.account, receipt_resaddr),
+        receipt_resaddr);
+
+    NonFungibleGlobalId::new(receipt_resaddr, added.first().unwrap().clone())
+}
+
+pub fn call_open_deal_impl(test_runner: &mut DefaultTestRunner,
+                       user: &User,
+                       escrow: ComponentAddress,
+                       payer: &NonFungibleGlobalId,
+                       payee: &NonFungibleGlobalId,
+                       arbiter: Option<NonFungibleGlobalId>,
+                       resource: ResourceAddress,
+                       quantity: TokenQuantity,
+                       deadline: TimeBound,
+                       expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            payer.resource_address(),
+            BTreeSet::from([payer.local_id().clone()]))
+        .pop_from_auth_zone("payer_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "open_deal",
+            |lookup| manifest_args!(lookup.proof("payer_proof"),
+                                    payee,
+                                    arbiter,
+                                    resource,
+                                    quantity,
+                                    deadline))
+        .deposit_batch(user.account)
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_approve_deal(test_runner: &mut DefaultTestRunner,
+                     user: &User,
+                     escrow: ComponentAddress,
+                     payer: &NonFungibleGlobalId,
+                     deal: &NonFungibleGlobalId,
+                     expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            payer.resource_address(),
+            BTreeSet::from([payer.local_id().clone()]))
+        .pop_from_auth_zone("payer_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "approve_deal",
+            |lookup| manifest_args!(lookup.proof("payer_proof"),
+                                    deal.local_id()))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_settle_deal(test_runner: &mut DefaultTestRunner,
+                    user: &User,
+                    escrow: ComponentAddress,
+                    arbiter: &NonFungibleGlobalId,
+                    deal: &NonFungibleGlobalId,
+                    to_payee: TokenQuantity,
+                    expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            arbiter.resource_address(),
+            BTreeSet::from([arbiter.local_id().clone()]))
+        .pop_from_auth_zone("arbiter_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "settle_deal",
+            |lookup| manifest_args!(lookup.proof("arbiter_proof"),
+                                    deal.local_id(),
+                                    to_payee))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_refund_deal(test_runner: &mut DefaultTestRunner,
+                    user: &User,
+                    escrow: ComponentAddress,
+                    deal: &NonFungibleGlobalId,
+                    expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            escrow,
+            "refund_deal",
+            manifest_args!(deal.local_id()))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_lock_with_hash(test_runner: &mut DefaultTestRunner,
+                       user: &User,
+                       escrow: ComponentAddress,
+                       owner: &NonFungibleGlobalId,
+                       recipient: &NonFungibleGlobalId,
+                       resource: ResourceAddress,
+                       quantity: TokenQuantity,
+                       hash_lock: Hash,
+                       deadline: i64,
+                       expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "lock_with_hash",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    recipient,
+                                    resource,
+                                    quantity,
+                                    hash_lock,
+                                    TimeBound::UnixTime(deadline)))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_claim_hash_lock(test_runner: &mut DefaultTestRunner,
+                        user: &User,
+                        escrow: ComponentAddress,
+                        recipient: &NonFungibleGlobalId,
+                        lock_id: u64,
+                        preimage: Vec<u8>,
+                        expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            recipient.resource_address(),
+            BTreeSet::from([recipient.local_id().clone()]))
+        .pop_from_auth_zone("recipient_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "claim_hash_lock",
+            |lookup| manifest_args!(lookup.proof("recipient_proof"),
+                                    lock_id,
+                                    preimage))
+        .deposit_batch(user.account)
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_reclaim_hash_lock(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          lock_id: u64,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "reclaim_hash_lock",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    lock_id))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_mint_allowance_claim_code(test_runner: &mut DefaultTestRunner,
+                                  user: &User,
+                                  escrow: ComponentAddress,
+                                  caller: &NonFungibleGlobalId,
+                                  code_hash: Hash,
+                                  expires: i64,
+                                  pay_out_funds: bool,
+                                  life_cycle: AllowanceLifeCycle,
+                                  for_resource: ResourceAddress,
+                                  max_amount: Option<TokenQuantity>,
+                                  expect_success: bool) -> TransactionReceipt
+{
+    let options = AllowanceOptions {
+        claim_code: Some(ClaimCodeTerms {
+            code_hash,
+            expires: TimeBound::UnixTime(expires),
+            pay_out_funds,
+        }),
+        ..Default::default()
+    };
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            caller.resource_address(),
+            BTreeSet::from([caller.local_id().clone()]))
+        .pop_from_auth_zone("caller_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "mint_allowance",
+            |lookup| manifest_args!(lookup.proof("caller_proof"),
+                                    None::<TimeBound>,
+                                    TimeBound::UnixTime(0),
+                                    life_cycle,
+                                    for_resource,
+                                    max_amount,
+                                    options))
+        .deposit_batch(user.account)
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_redeem_claim_code(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          secret: Vec<u8>,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            escrow,
+            "redeem_claim_code",
+            manifest_args!(secret))
+        .deposit_batch(user.account)
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_cancel_claim_code(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          caller: &NonFungibleGlobalId,
+                          code_hash: Hash,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            caller.resource_address(),
+            BTreeSet::from([caller.local_id().clone()]))
+        .pop_from_auth_zone("caller_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "cancel_claim_code",
+            |lookup| manifest_args!(lookup.proof("caller_proof"),
+                                    code_hash))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_add_standing_order(test_runner: &mut DefaultTestRunner,
+                           user: &User,
+                           escrow: ComponentAddress,
+                           owner: &NonFungibleGlobalId,
+                           resource: ResourceAddress,
+                           amount: Decimal,
+                           recipient: PaymentRecipient,
+                           first_due: i64,
+                           period: i64,
+                           end: Option<i64>,
+                           max_catch_up: u64) -> u64
+{
+    let receipt = call_add_standing_order_impl(test_runner,
+                                               user,
+                                               escrow,
+                                               owner,
+                                               resource,
+                                               amount,
+                                               recipient,
+                                               first_due,
+                                               period,
+                                               end,
+                                               max_catch_up,
+                                               true);
+
+    receipt.expect_commit_success().output(3)
+}
+
+pub fn call_add_standing_order_impl(test_runner: &mut DefaultTestRunner,
+                                user: &User,
+                                escrow: ComponentAddress,
+                                owner: &NonFungibleGlobalId,
+                                resource: ResourceAddress,
+                                amount: Decimal,
+                                recipient: PaymentRecipient,
+                                first_due: i64,
+                                period: i64,
+                                end: Option<i64>,
+                                max_catch_up: u64,
+                                expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "add_standing_order",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    resource,
+                                    amount,
+                                    recipient,
+                                    first_due,
+                                    period,
+                                    end,
+                                    max_catch_up))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_cancel_standing_order(test_runner: &mut DefaultTestRunner,
+                              user: &User,
+                              escrow: ComponentAddress,
+                              owner: &NonFungibleGlobalId,
+                              order_id: u64,
+                              expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "cancel_standing_order",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    order_id))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_execute_due_orders(test_runner: &mut DefaultTestRunner,
+                           user: &User,
+                           escrow: ComponentAddress,
+                           owner: &NonFungibleGlobalId) -> CommitResult
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            escrow,
+            "execute_due_orders",
+            manifest_args!(owner))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().clone()
+}
+
+pub fn call_start_stream(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         owner: &NonFungibleGlobalId,
+                         recipient: &NonFungibleGlobalId,
+                         resource: ResourceAddress,
+                         rate: Decimal,
+                         funding: Decimal) -> u64
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "start_stream",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    recipient,
+                                    resource,
+                                    rate,
+                                    funding))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(3)
+}
+
+pub fn call_top_up_stream(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          stream_id: u64,
+                          amount: Decimal,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "top_up_stream",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    stream_id,
+                                    amount))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_pause_stream(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         owner: &NonFungibleGlobalId,
+                         stream_id: u64,
+                         expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "pause_stream",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    stream_id))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_resume_stream(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          stream_id: u64,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "resume_stream",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    stream_id))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_cancel_stream(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          stream_id: u64,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "cancel_stream",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    stream_id))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_claim_stream(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         recipient: &NonFungibleGlobalId,
+                         owner: &NonFungibleGlobalId,
+                         stream_id: u64,
+                         expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            recipient.resource_address(),
+            BTreeSet::from([recipient.local_id().clone()]))
+        .pop_from_auth_zone("recipient_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "claim_stream",
+            |lookup| manifest_args!(lookup.proof("recipient_proof"),
+                                    owner,
+                                    stream_id))
+        .deposit_batch(user.account)
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_read_stream(test_runner: &mut DefaultTestRunner,
+                        user: &User,
+                        escrow: ComponentAddress,
+                        owner: &NonFungibleGlobalId,
+                        stream_id: u64) -> (Decimal, Decimal)
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "read_stream",
+                     manifest_args!(owner, stream_id))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(1)
+}
+
+pub fn call_set_guardians(test_runner: &mut DefaultTestRunner,
+                      user: &User,
+                      escrow: ComponentAddress,
+                      owner: &NonFungibleGlobalId,
+                      guardians: Option<Guardians>,
+                      expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "set_guardians",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    guardians))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_approve_recovery(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         guardian: &NonFungibleGlobalId,
+                         owner: &NonFungibleGlobalId,
+                         new_owner: &NonFungibleGlobalId,
+                         expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            guardian.resource_address(),
+            BTreeSet::from([guardian.local_id().clone()]))
+        .pop_from_auth_zone("guardian_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "approve_recovery",
+            |lookup| manifest_args!(lookup.proof("guardian_proof"),
+                                    owner,
+                                    new_owner))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_cancel_recovery(test_runner: &mut DefaultTestRunner,
+                        user: &User,
+                        escrow: ComponentAddress,
+                        owner: &NonFungibleGlobalId,
+                        expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "cancel_recovery",
+            |lookup| manifest_args!(lookup.proof("owner_proof")))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_complete_recovery(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "complete_recovery",
+                     manifest_args!(owner))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_read_recovery(test_runner: &mut DefaultTestRunner,
+                      user: &User,
+                      escrow: ComponentAddress,
+                      owner: &NonFungibleGlobalId) -> Option<Recovery>
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "read_recovery",
+                     manifest_args!(owner))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(1)
+}
+
+pub fn call_set_inheritance(test_runner: &mut DefaultTestRunner,
+                        user: &User,
+                        escrow: ComponentAddress,
+                        owner: &NonFungibleGlobalId,
+                        inheritance: Option<Inheritance>,
+                        expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "set_inheritance",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    inheritance))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_claim_inheritance(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          beneficiary: &NonFungibleGlobalId,
+                          resource: Option<ResourceAddress>,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "claim_inheritance",
+                     manifest_args!(owner, beneficiary, resource))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_read_inheritance(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         owner: &NonFungibleGlobalId) -> Option<Inheritance>
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "read_inheritance",
+                     manifest_args!(owner))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(1)
+}
+
+pub fn call_read_last_active(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         owner: &NonFungibleGlobalId) -> i64
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "read_last_active",
+                     manifest_args!(owner))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(1)
+}
+
+pub fn call_set_savings_lock(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         owner: &NonFungibleGlobalId,
+                         resource: Option<ResourceAddress>,
+                         lock: SavingsLock,
+                         expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "set_savings_lock",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    resource,
+                                    lock))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_read_savings_lock(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          resource: Option<ResourceAddress>) -> Option<SavingsLock>
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "read_savings_lock",
+                     manifest_args!(owner, resource))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if !receipt.is_commit_success() {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION FAIL");
+    }
+
+    receipt.expect_commit_success().output(1)
+}
+
+/// Sets the withdrawal delay of `owner`'s pool. If the pool already
+/// has one, its `canceller` badge must be given as well, held by the
+/// user named with it.
+pub fn call_set_withdrawal_delay(test_runner: &mut DefaultTestRunner,
+                             user: &User,
+                             escrow: ComponentAddress,
+                             owner: &NonFungibleGlobalId,
+                             withdrawal_delay: Option<WithdrawalDelay>,
+                             canceller: Option<(&User, &NonFungibleGlobalId)>,
+                             expect_success: bool) -> TransactionReceipt
+{
+    let mut manifest = ManifestBuilder::new();
+    let mut signers = vec![NonFungibleGlobalId::from_public_key(&user.pubkey)];
+    if let Some((canceller_user, canceller_badge)) = canceller {
+        manifest = manifest
+            .create_proof_from_account_of_non_fungibles(
+                canceller_user.account,
+                canceller_badge.resource_address(),
+                BTreeSet::from([canceller_badge.local_id().clone()]));
+        signers.push(NonFungibleGlobalId::from_public_key(&canceller_user.pubkey));
+    }
+    let manifest = manifest
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "set_withdrawal_delay",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    withdrawal_delay))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        signers,
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_request_withdraw(test_runner: &mut DefaultTestRunner,
+                         user: &User,
+                         escrow: ComponentAddress,
+                         owner: &NonFungibleGlobalId,
+                         resource: ResourceAddress,
+                         quantity: TokenQuantity,
+                         recipient: PaymentRecipient,
+                         expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            owner.resource_address(),
+            BTreeSet::from([owner.local_id().clone()]))
+        .pop_from_auth_zone("owner_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "request_withdraw",
+            |lookup| manifest_args!(lookup.proof("owner_proof"),
+                                    resource,
+                                    quantity,
+                                    recipient))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_finalize_withdraw(test_runner: &mut DefaultTestRunner,
+                          user: &User,
+                          escrow: ComponentAddress,
+                          owner: &NonFungibleGlobalId,
+                          withdrawal_id: u64,
+                          expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .call_method(escrow,
+                     "finalize_withdraw",
+                     manifest_args!(owner, withdrawal_id))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+pub fn call_cancel_withdraw(test_runner: &mut DefaultTestRunner,
+                        user: &User,
+                        escrow: ComponentAddress,
+                        canceller: &NonFungibleGlobalId,
+                        owner: &NonFungibleGlobalId,
+                        withdrawal_id: u64,
+                        expect_success: bool) -> TransactionReceipt
+{
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            canceller.resource_address(),
+            BTreeSet::from([canceller.local_id().clone()]))
+        .pop_from_auth_zone("canceller_proof")
+        .call_method_with_name_lookup(
+            escrow,
+            "cancel_withdraw",
+            |lookup| manifest_args!(lookup.proof("canceller_proof"),
+                                    owner,
+                                    withdrawal_id))
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
+
+/// Recalls the NFT `nfgid` from `holder`'s account, authorized by
+/// `user`'s `badge`.
+pub fn call_recall_nft(test_runner: &mut DefaultTestRunner,
+                   user: &User,
+                   badge: &NonFungibleGlobalId,
+                   holder: &User,
+                   nfgid: &NonFungibleGlobalId,
+                   expect_success: bool) -> TransactionReceipt
+{
+    let vault = test_runner.get_component_vaults(holder.account, nfgid.resource_address())[0];
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account_of_non_fungibles(
+            user.account,
+            badge.resource_address(),
+            BTreeSet::from([badge.local_id().clone()]))
+        .recall_non_fungibles(InternalAddress::new_or_panic(vault.into()),
+                              [nfgid.local_id().clone()])
+        .deposit_batch(user.account)
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&user.pubkey)],
+    );
+
+    if receipt.is_commit_success() != expect_success {
+        println!("{:?}", receipt);
+        panic!("TRANSACTION BAD");
+    }
+
+    receipt
+}
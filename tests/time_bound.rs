@@ -0,0 +1,27 @@
+use std::cmp::Ordering;
+use escrow::time_bound::*;
+
+#[test]
+fn test_time_bound_comparisons() {
+    let now = Now { unix_time: 1000, epoch: 50 };
+
+    assert_eq!(Ordering::Less, TimeBound::UnixTime(999).cmp_to_now(&now),
+               "Earlier Unix time should be before now");
+    assert_eq!(Ordering::Equal, TimeBound::UnixTime(1000).cmp_to_now(&now),
+               "Same Unix time should be now");
+    assert_eq!(Ordering::Greater, TimeBound::Epoch(51).cmp_to_now(&now),
+               "Later epoch should be after now");
+    assert_eq!(Ordering::Equal, TimeBound::Epoch(50).cmp_to_now(&now),
+               "Same epoch should be now");
+
+    assert!(TimeBound::Epoch(49).is_before(&now), "Earlier epoch should be before now");
+    assert!(!TimeBound::Epoch(50).is_before(&now), "Current epoch is not before now");
+    assert!(!TimeBound::Epoch(50).is_after(&now), "Current epoch is not after now");
+    assert!(TimeBound::UnixTime(1001).is_after(&now), "Later Unix time should be after now");
+
+    // Each form only looks at its own part of now
+    assert!(TimeBound::Epoch(1000).is_after(&now),
+            "Epoch should not be compared to Unix time");
+    assert!(TimeBound::UnixTime(50).is_before(&now),
+            "Unix time should not be compared to epoch");
+}
@@ -0,0 +1,244 @@
+use scrypto::prelude::*;
+use escrow::token_quantity::{IntegerIdRanges, NflidSet, TokenQuantity};
+
+#[test]
+fn test_id_ranges_normalize() {
+    assert_eq!(&[(1, 10), (20, 30)],
+               IntegerIdRanges::from_ranges(vec![(20, 25), (1, 5), (6, 10), (22, 30)]).ranges(),
+               "Ranges should be sorted and merged");
+    assert!(IntegerIdRanges::from_ranges(vec![(5, 1)]).is_empty(),
+            "Reversed range should be empty");
+    assert_eq!(&[(0, u64::MAX)],
+               IntegerIdRanges::from_ranges(vec![(0, 10), (11, u64::MAX)]).ranges(),
+               "Adjacent ranges should merge without overflow");
+}
+
+#[test]
+fn test_id_ranges_difference() {
+    let ranges = IntegerIdRanges::new(1, 10000).excluding(5, 5);
+    assert_eq!(&[(1, 4), (6, 10000)], ranges.ranges(), "Should punch a hole");
+    assert_eq!(9999, ranges.len(), "Should count without expanding");
+
+    let ranges = ranges.difference(&IntegerIdRanges::from_ranges(vec![(0, 1), (3, 7), (10000, 20000)]));
+    assert_eq!(&[(2, 2), (8, 9999)], ranges.ranges(), "Should cut both ends and the middle");
+
+    let ranges = ranges.without_ids([2.into(), 100.into(), NonFungibleLocalId::string("a").unwrap()].iter());
+    assert_eq!(&[(8, 99), (101, 9999)], ranges.ranges(),
+               "Should remove integer ids and ignore others");
+
+    assert!(ranges.difference(&IntegerIdRanges::new(0, u64::MAX)).is_empty(),
+            "Removing everything should leave nothing");
+}
+
+#[test]
+fn test_id_ranges_lookup() {
+    let ranges = IntegerIdRanges::from_ranges(vec![(1, 3), (10, 12)]);
+    assert!(ranges.contains(&2.into()), "2 should be in range");
+    assert!(!ranges.contains(&5.into()), "5 should not be in range");
+    assert!(!ranges.contains(&NonFungibleLocalId::string("two").unwrap()),
+            "String ids are never in range");
+    assert_eq!(vec![NonFungibleLocalId::integer(1), 2.into(), 3.into(), 10.into(), 11.into(), 12.into()],
+               ranges.iter().collect::<Vec<_>>(),
+               "Should iterate in order");
+    assert_eq!(3, ranges.intersection_len(&IntegerIdRanges::from_ranges(vec![(3, 11)])),
+               "Should count overlaps across ranges");
+    assert!(ranges.is_well_formed(), "Built ranges are always well formed");
+}
+
+#[test]
+fn test_nflid_sets() {
+    let ranges = NflidSet::Ranges(IntegerIdRanges::new(1, 1000));
+    let ids = NflidSet::Ids([5.into(), 2000.into()].into());
+
+    assert_eq!(1, ranges.intersection_len(&ids), "Ranges and ids should intersect");
+    assert_eq!(1, ids.intersection_len(&ranges), "Intersection should be symmetric");
+    assert_eq!(NflidSet::Ranges(IntegerIdRanges::new(1, 1000).excluding(5, 5)),
+               ranges.difference(&ids),
+               "Difference should keep ranges as ranges");
+    assert_eq!(NflidSet::Ids([2000.into()].into()),
+               ids.difference(&ranges),
+               "Difference should keep ids as ids");
+}
+
+#[test]
+fn test_token_quantity_with_ranges() {
+    let quantity = TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(1, 10000), Some(5));
+    assert_eq!(dec!("10005"), quantity.to_amount(), "Should count ranges and amount");
+    assert!(!quantity.is_zero(), "Should not be zero");
+    assert!(TokenQuantity::NonFungibleRanges(IntegerIdRanges::from_ranges(vec![]), None).is_zero(),
+            "Empty ranges should be zero");
+
+    let (nflids, amount) = quantity.extract_max_values();
+    assert_eq!(Some(NflidSet::Ranges(IntegerIdRanges::new(1, 10000))), nflids,
+               "Should extract ranges as ranges");
+    assert_eq!(Some(dec!("5")), amount, "Should extract amount");
+    assert_eq!(quantity, TokenQuantity::non_fungible(nflids, Some(5)),
+               "Should rebuild the same quantity");
+}
+
+#[test]
+fn test_checked_sub() {
+    assert_eq!(Some(TokenQuantity::Fungible(dec!("7"))),
+               TokenQuantity::Fungible(dec!("10")).checked_sub(
+                   &TokenQuantity::NonFungible(Some([1.into(), 2.into()].into()), Some(1))),
+               "Fungible limit should count nflids as amounts");
+    assert_eq!(None,
+               TokenQuantity::Fungible(dec!("1")).checked_sub(&TokenQuantity::Fungible(dec!("2"))),
+               "Fungible should not go negative");
+
+    let limit = TokenQuantity::NonFungible(Some([1.into(), 2.into(), 3.into()].into()), Some(2));
+    assert_eq!(Some(TokenQuantity::NonFungible(Some([3.into()].into()), Some(1))),
+               limit.checked_sub(&TokenQuantity::NonFungible(Some([1.into(), 2.into(), 9.into()].into()),
+                                                             None)),
+               "Named ids should come off the set, others off the count");
+    assert_eq!(Some(TokenQuantity::NonFungible(Some([1.into(), 2.into(), 3.into()].into()), Some(0))),
+               limit.checked_sub(&TokenQuantity::Fungible(dec!("2"))),
+               "Fungible ask should count as arbitrary NFTs");
+    assert_eq!(None, limit.checked_sub(&TokenQuantity::Fungible(dec!("1.5"))),
+               "Fractional NFTs can't be taken");
+    assert_eq!(None, limit.checked_sub(&TokenQuantity::NonFungible(None, Some(3))),
+               "Named ids should not pay for arbitrary NFTs");
+
+    let no_count = TokenQuantity::NonFungible(Some([1.into()].into()), None);
+    assert_eq!(Some(TokenQuantity::NonFungible(Some(IndexSet::new()), None)),
+               no_count.checked_sub(&no_count),
+               "Taking only named ids should keep a missing count missing");
+    assert_eq!(None, no_count.checked_sub(&TokenQuantity::NonFungible(Some([2.into()].into()), None)),
+               "A missing count should not underflow");
+    assert_eq!(None,
+               TokenQuantity::NonFungible(None, Some(u64::MAX)).checked_sub(
+                   &TokenQuantity::NonFungible(Some([1.into()].into()), Some(u64::MAX))),
+               "Debit should not overflow");
+
+    let ranges = TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(1, 10000), Some(1));
+    assert_eq!(Some(TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(1, 10000).excluding(5, 5),
+                                                     Some(0))),
+               ranges.checked_sub(&TokenQuantity::NonFungible(Some([5.into(), 20000.into()].into()),
+                                                              None)),
+               "Ranges should stay ranges");
+}
+
+#[test]
+fn test_contains_and_satisfies() {
+    let limit = TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(1, 100), None);
+    let ask = TokenQuantity::NonFungible(Some([50.into()].into()), None);
+    assert!(limit.contains(&ask), "Limit should contain an id in range");
+    assert!(ask.satisfies(&limit), "Ask should satisfy limit");
+    assert!(!ask.contains(&limit), "Ask should not contain limit");
+    assert!(limit.contains(&TokenQuantity::NonFungible(None, None)),
+            "Everything contains nothing");
+    assert!(!TokenQuantity::Fungible(dec!("1")).contains(&TokenQuantity::Fungible(dec!("-1"))),
+            "Negative amounts are never contained");
+}
+
+#[test]
+fn test_checked_add() {
+    assert_eq!(Some(TokenQuantity::Fungible(dec!("3"))),
+               TokenQuantity::Fungible(dec!("1")).checked_add(&TokenQuantity::Fungible(dec!("2"))),
+               "Fungibles should add");
+    assert_eq!(None,
+               TokenQuantity::Fungible(dec!("1")).checked_add(&TokenQuantity::NonFungible(None, Some(1))),
+               "Fungible and non-fungible should not add");
+    assert_eq!(None,
+               TokenQuantity::NonFungible(None, Some(u64::MAX)).checked_add(
+                   &TokenQuantity::NonFungible(None, Some(1))),
+               "Count should not overflow");
+    assert_eq!(Some(TokenQuantity::NonFungible(Some([1.into(), 2.into(), 3.into()].into()), Some(1))),
+               TokenQuantity::NonFungible(Some([1.into(), 2.into()].into()), None).checked_add(
+                   &TokenQuantity::NonFungible(Some([2.into(), 3.into()].into()), Some(1))),
+               "Ids should be a union and counts a sum");
+    assert_eq!(Some(TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(1, 11), None)),
+               TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(1, 10), None).checked_add(
+                   &TokenQuantity::NonFungible(Some([11.into()].into()), None)),
+               "Integer ids should join ranges");
+    let string_id = NonFungibleLocalId::string("a").unwrap();
+    assert_eq!(Some(TokenQuantity::NonFungible(Some([1.into(), 2.into(), string_id.clone()].into()),
+                                               None)),
+               TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(1, 2), None).checked_add(
+                   &TokenQuantity::NonFungible(Some([string_id].into()), None)),
+               "Ranges should expand to hold other ids");
+}
+
+#[test]
+fn test_intersect() {
+    assert_eq!(Some(TokenQuantity::Fungible(dec!("2"))),
+               TokenQuantity::Fungible(dec!("5")).intersect(&TokenQuantity::Fungible(dec!("2"))),
+               "Fungibles should give the smaller");
+    assert_eq!(None,
+               TokenQuantity::Fungible(dec!("5")).intersect(&TokenQuantity::NonFungible(None, None)),
+               "Fungible and non-fungible have nothing in common");
+    assert_eq!(Some(TokenQuantity::NonFungible(Some([2.into()].into()), Some(1))),
+               TokenQuantity::NonFungible(Some([1.into(), 2.into()].into()), Some(3)).intersect(
+                   &TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(2, 10), Some(1))),
+               "Should keep shared ids and the smaller count");
+    assert_eq!(Some(TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(5, 10), None)),
+               TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(1, 10), None).intersect(
+                   &TokenQuantity::NonFungibleRanges(IntegerIdRanges::new(5, 20), Some(4))),
+               "Ranges should intersect as ranges");
+}
+
+#[test]
+fn test_all_quantity() {
+    let all = TokenQuantity::All;
+    assert!(!all.is_zero(), "All is never zero");
+    assert_eq!(Decimal::MAX, all.to_amount(), "All has no upper bound");
+    assert!(all.contains(&TokenQuantity::Fungible(dec!("1000000"))), "All should contain anything");
+    assert!(all.contains(&all), "All should contain itself");
+    assert!(!TokenQuantity::Fungible(Decimal::MAX).contains(&all),
+            "Only All should contain All");
+    assert_eq!(Some(TokenQuantity::All), all.checked_sub(&TokenQuantity::Fungible(dec!("5"))),
+               "All should stay All");
+    assert_eq!(Some(TokenQuantity::All), TokenQuantity::Fungible(dec!("5")).checked_add(&all),
+               "Adding All should give All");
+    assert_eq!(Some(TokenQuantity::Fungible(dec!("5"))),
+               all.intersect(&TokenQuantity::Fungible(dec!("5"))),
+               "All should have everything in common");
+}
+
+#[test]
+fn test_except_quantity() {
+    let rare = NflidSet::Ranges(IntegerIdRanges::new(1, 10));
+    let limit = TokenQuantity::NonFungibleExcept(rare.clone(), Some(5));
+    assert_eq!(dec!("5"), limit.to_amount(), "Should count its number");
+    assert_eq!(Decimal::MAX, TokenQuantity::NonFungibleExcept(rare.clone(), None).to_amount(),
+               "No number means no upper bound");
+    assert!(TokenQuantity::NonFungibleExcept(rare.clone(), Some(0)).is_zero(),
+            "A number of zero should be zero");
+    assert_eq!(Some(&rare), limit.excluded_ids(), "Should report its excluded ids");
+
+    assert_eq!(Some(TokenQuantity::NonFungibleExcept(rare.clone(), Some(3))),
+               limit.checked_sub(&TokenQuantity::NonFungible(Some([11.into(), 12.into()].into()),
+                                                             None)),
+               "Named ids outside the exclusion should come off the number");
+    assert_eq!(None,
+               limit.checked_sub(&TokenQuantity::NonFungible(Some([5.into()].into()), None)),
+               "Excluded ids should never be covered");
+    assert_eq!(None,
+               limit.checked_sub(&TokenQuantity::NonFungible(None, Some(1))),
+               "Arbitrary NFTs might be excluded ones");
+    assert_eq!(None,
+               limit.checked_sub(&TokenQuantity::NonFungible(Some(to_ids(11..17)), None)),
+               "Should not go over the number");
+    assert_eq!(Some(TokenQuantity::NonFungibleExcept(NflidSet::Ids(IndexSet::new()), None)),
+               TokenQuantity::NonFungibleExcept(NflidSet::Ids(IndexSet::new()), None)
+               .checked_sub(&TokenQuantity::NonFungible(None, Some(1000))),
+               "Excluding nothing should cover arbitrary NFTs");
+
+    assert_eq!(Some(TokenQuantity::NonFungibleExcept(
+        NflidSet::Ranges(IntegerIdRanges::new(1, 10).excluding(2, 2)), Some(7))),
+               limit.checked_add(&TokenQuantity::NonFungible(Some([2.into()].into()), Some(1))),
+               "Adding should stop excluding the added ids");
+    assert_eq!(Some(TokenQuantity::NonFungible(Some([11.into()].into()), Some(2))),
+               limit.intersect(&TokenQuantity::NonFungible(Some([2.into(), 11.into()].into()),
+                                                           Some(2))),
+               "Intersecting should drop excluded ids");
+    assert_eq!(Some(TokenQuantity::NonFungibleExcept(
+        NflidSet::Ranges(IntegerIdRanges::new(1, 20)), Some(5))),
+               limit.intersect(&TokenQuantity::NonFungibleExcept(
+                   NflidSet::Ranges(IntegerIdRanges::new(5, 20)), None)),
+               "Intersecting exclusions should exclude both");
+}
+
+fn to_ids(ints: std::ops::Range<u64>) -> IndexSet<NonFungibleLocalId> {
+    ints.map(NonFungibleLocalId::integer).collect()
+}